@@ -1,19 +1,27 @@
 // Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{ec2_utils::LaunchPlan, ssm_utils, upload_object};
+use crate::{
+    ec2_utils::LaunchPlan,
+    russula::{AuthToken, TlsConfig},
+    ssm_utils::{self, coordination_utils::RussulaAuth},
+    upload_object,
+};
 use aws_sdk_s3::primitives::ByteStream;
-use tracing::info;
+use std::net::{Ipv4Addr, SocketAddr};
+use tracing::{error, info};
 
 mod cli;
 mod dashboard;
 mod error;
 mod report;
 mod state;
+mod status;
 
 pub use cli::{Cli, HostConfig, OrchestratorConfig};
 pub use error::*;
 pub use state::*;
+pub use status::StatusBoard;
 
 // TODO
 // # Features
@@ -23,8 +31,6 @@ pub use state::*;
 //
 // # Optimization
 // - tar.gz private source
-// - add logging for netbench
-//   - TRACE=stdio
 // - add pcap captures
 //
 // # Nits
@@ -65,6 +71,46 @@ pub async fn run(
     .await
     .unwrap();
 
+    // Per-run secret used to authenticate the Russula control channel. Kept in the
+    // private bucket so that only the hosts can download it.
+    let russula_auth_token = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    upload_object(
+        &s3_client,
+        config.cdk_config.netbench_runner_private_s3_bucket(),
+        ByteStream::from(russula_auth_token.clone().into_bytes()),
+        &format!("{unique_id}/{}", STATE.russula_auth_token_file),
+    )
+    .await
+    .unwrap();
+    let russula_auth_token = AuthToken::new(russula_auth_token);
+
+    // Per-run certificates which the Russula peers use to authenticate each other
+    // and encrypt the control channel. Also kept in the private bucket.
+    let russula_tls = TlsConfig::generate_pem().unwrap();
+    upload_object(
+        &s3_client,
+        config.cdk_config.netbench_runner_private_s3_bucket(),
+        ByteStream::from(russula_tls.clone().into_bytes()),
+        &format!("{unique_id}/{}", STATE.russula_tls_file),
+    )
+    .await
+    .unwrap();
+    let russula_auth = RussulaAuth {
+        token: russula_auth_token,
+        tls: TlsConfig::from_pem(&russula_tls).unwrap(),
+    };
+
+    let status_board = StatusBoard::default();
+    if let Some(status_port) = config.status_port {
+        status_board
+            .serve(SocketAddr::from((Ipv4Addr::LOCALHOST, status_port)))
+            .await?;
+    }
+
     dashboard::update_dashboard(
         dashboard::Step::UploadIndex,
         &s3_client,
@@ -177,15 +223,17 @@ pub async fn run(
             );
 
             // run russula
-            {
+            let run_russula: OrchResult<()> = async {
                 let mut server_russula = ssm_utils::coordination_utils::ServerNetbenchRussula::new(
                     &ssm_client,
                     &infra,
                     server_ids.clone(),
                     &config,
                     &server_driver,
+                    &russula_auth,
+                    &status_board,
                 )
-                .await;
+                .await?;
 
                 let mut client_russula = ssm_utils::coordination_utils::ClientNetbenchRussula::new(
                     &ssm_client,
@@ -193,13 +241,58 @@ pub async fn run(
                     client_ids.clone(),
                     &config,
                     &client_driver,
+                    &russula_auth,
+                    &status_board,
                 )
-                .await;
+                .await?;
 
                 // run client/server
-                server_russula.wait_workers_running(&ssm_client).await;
-                client_russula.wait_done(&ssm_client).await;
-                server_russula.wait_done(&ssm_client).await;
+                let mut run = Ok(());
+                for iteration in 0..config.iterations {
+                    if iteration > 0 {
+                        server_russula.next_iteration();
+                        client_russula.next_iteration();
+                    }
+                    run = async {
+                        server_russula.wait_workers_running(&ssm_client).await?;
+                        let client_stopped = client_russula.wait_workers_stopped(&ssm_client).await;
+                        // stop the servers even if the clients failed
+                        let server_stopped = server_russula.wait_workers_stopped(&ssm_client).await;
+                        client_stopped.and(server_stopped)
+                    }
+                    .await;
+                    if run.is_err() {
+                        break;
+                    }
+                }
+                let client_done = client_russula.wait_done(&ssm_client).await;
+                let server_done = server_russula.wait_done(&ssm_client).await;
+                let run = run.and(client_done).and(server_done);
+
+                // upload the coordination events even if the run failed
+                server_russula
+                    .upload_events(&s3_client, &unique_id, config)
+                    .await;
+                client_russula
+                    .upload_events(&s3_client, &unique_id, config)
+                    .await;
+                run
+            }
+            .await;
+            if let Err(err) = run_russula {
+                error!(
+                    "Aborting server: {} and client: {}. {}",
+                    server_driver.driver_name(),
+                    client_driver.driver_name(),
+                    err
+                );
+                eprintln!(
+                    "Aborting Netbench with server: {} and client: {}. {}",
+                    server_driver.driver_name(),
+                    client_driver.driver_name(),
+                    err
+                );
+                continue;
             }
 
             // copy netbench results
@@ -3,6 +3,7 @@
 
 use crate::{
     orchestrator::{OrchError, OrchResult, STATE},
+    russula::netbench::RunParams,
     Az,
 };
 use aws_sdk_ec2::types::{Placement as AwsPlacement, PlacementGroup};
@@ -14,6 +15,7 @@ use std::{
     fs::File,
     path::{Path, PathBuf},
     process::Command,
+    time::Duration,
 };
 use tracing::debug;
 
@@ -27,6 +29,30 @@ pub struct Cli {
     #[arg(long)]
     netbench_scenario_file: PathBuf,
 
+    /// Kill a netbench process and fail its driver pair if the process is still
+    /// running after this duration
+    #[arg(long, value_parser = humantime::parse_duration)]
+    max_run_duration: Option<Duration>,
+
+    /// Number of times each driver pair is run. Each iteration writes its own
+    /// results without restarting the Russula workers
+    #[arg(long, default_value = "1")]
+    iterations: u32,
+
+    /// Environment variables, as KEY=VALUE, set for the netbench drivers. ex:
+    /// TRACE=stdio
+    #[arg(long = "env", value_parser = RunParams::parse_env)]
+    envs: Vec<(String, String)>,
+
+    /// Extra arguments passed to the netbench drivers
+    #[arg(long = "driver-arg", allow_hyphen_values = true)]
+    driver_args: Vec<String>,
+
+    /// Serve the status of the Russula coordinators as JSON at
+    /// http://127.0.0.1:<STATUS_PORT>/status
+    #[arg(long)]
+    status_port: Option<u16>,
+
     // An infrastructure overlay for the hosts specified in the
     // netbench scenario file
     #[command(flatten)]
@@ -44,6 +70,13 @@ impl Cli {
             netbench_scenario,
             netbench_scenario_filename,
             netbench_scenario_filepath: self.netbench_scenario_file,
+            max_run_duration: self.max_run_duration,
+            iterations: self.iterations,
+            run_params: RunParams {
+                envs: self.envs.into_iter().collect(),
+                args: self.driver_args,
+            },
+            status_port: self.status_port,
             infra: self.infra,
         })
     }
@@ -55,6 +88,10 @@ pub struct IntermediateCli {
     netbench_scenario: NetbenchScenario,
     netbench_scenario_filename: String,
     netbench_scenario_filepath: PathBuf,
+    max_run_duration: Option<Duration>,
+    iterations: u32,
+    run_params: RunParams,
+    status_port: Option<u16>,
     pub infra: CliInfraScenario,
 }
 
@@ -124,6 +161,10 @@ impl IntermediateCli {
         let config = OrchestratorConfig {
             netbench_scenario_filename,
             netbench_scenario_filepath: self.netbench_scenario_filepath,
+            max_run_duration: self.max_run_duration,
+            iterations: self.iterations,
+            run_params: self.run_params,
+            status_port: self.status_port,
             client_config,
             server_config,
             cdk_config,
@@ -224,6 +265,12 @@ pub struct OrchestratorConfig {
     // netbench
     pub netbench_scenario_filename: String,
     pub netbench_scenario_filepath: PathBuf,
+    pub max_run_duration: Option<Duration>,
+    pub iterations: u32,
+    /// Applied to each netbench driver and recorded in the russula events
+    pub run_params: RunParams,
+    /// Local port of the coordination status endpoint
+    pub status_port: Option<u16>,
     // cdk
     pub cdk_config: CdkConfig,
     // infra
@@ -240,6 +287,18 @@ impl OrchestratorConfig {
             .to_str()
             .unwrap()
     }
+
+    /// Optional russula_cli worker args which limit how long netbench can run
+    pub fn max_run_duration_arg(&self) -> String {
+        self.max_run_duration
+            .map(|duration| {
+                format!(
+                    " --max-run-duration {}",
+                    humantime::format_duration(duration)
+                )
+            })
+            .unwrap_or_default()
+    }
 }
 
 // Used for parsing the scenario file generated by the s2n-netbench project
@@ -10,6 +10,7 @@ pub enum OrchError {
     Ec2 { dbg: String },
     Iam { dbg: String },
     Ssm { dbg: String },
+    Russula { dbg: String },
 }
 
 impl std::fmt::Display for OrchError {
@@ -19,8 +20,17 @@ impl std::fmt::Display for OrchError {
             OrchError::Ec2 { dbg } => write!(f, "{}", dbg),
             OrchError::Iam { dbg } => write!(f, "{}", dbg),
             OrchError::Ssm { dbg } => write!(f, "{}", dbg),
+            OrchError::Russula { dbg } => write!(f, "{}", dbg),
         }
     }
 }
 
 impl std::error::Error for OrchError {}
+
+impl From<crate::russula::RussulaError> for OrchError {
+    fn from(err: crate::russula::RussulaError) -> Self {
+        OrchError::Russula {
+            dbg: err.to_string(),
+        }
+    }
+}
@@ -1,11 +1,13 @@
 // Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::orchestrator::OrchestratorConfig;
+use crate::{orchestrator::OrchestratorConfig, russula};
 use core::time::Duration;
 
 pub const STATE: State = State {
-    version: "v2.4.0",
+    // Defined by russula, which sends it during the handshake to detect a
+    // mismatched russula_cli build.
+    version: russula::VERSION,
 
     // netbench
     netbench_repo: "https://github.com/aws/s2n-netbench.git",
@@ -22,7 +24,12 @@ pub const STATE: State = State {
     russula_repo: "https://github.com/toidiu/netbench_orchestrator.git",
     russula_branch: "ak-main",
     russula_port: 9000,
+    russula_auth_token_file: "russula_auth_token",
+    russula_tls_file: "russula_tls.pem",
     poll_delay_russula: Duration::from_secs(5),
+    start_delay_russula: Duration::from_secs(2),
+    heartbeat_interval_russula: Duration::from_secs(5),
+    liveness_timeout_russula: Duration::from_secs(60),
 
     // aws
     ami_name: "/aws/service/ami-amazon-linux-latest/al2023-ami-kernel-default-x86_64",
@@ -49,7 +56,19 @@ pub struct State {
     pub russula_repo: &'static str,
     pub russula_branch: &'static str,
     pub russula_port: u16,
+    // Per-run token used to authenticate the Russula control channel. Copied to
+    // the hosts next to the scenario file.
+    pub russula_auth_token_file: &'static str,
+    // Per-run certificates used to authenticate and encrypt the Russula control
+    // channel with mutual TLS. Copied to the hosts next to the scenario file.
+    pub russula_tls_file: &'static str,
     pub poll_delay_russula: Duration,
+    // Delay before the Workers start so they can all start at the same time
+    pub start_delay_russula: Duration,
+    // Used by the Coordinators and passed to the Workers, which must heartbeat to
+    // outlive the liveness timeout of a long run
+    pub heartbeat_interval_russula: Duration,
+    pub liveness_timeout_russula: Duration,
 
     // aws
     pub ami_name: &'static str,
@@ -85,6 +104,14 @@ impl State {
         format!("{}/bin", self.host_home_path)
     }
 
+    pub fn host_auth_token_path(&self) -> String {
+        format!("{}/{}", self.host_bin_path(), self.russula_auth_token_file)
+    }
+
+    pub fn host_tls_path(&self) -> String {
+        format!("{}/{}", self.host_bin_path(), self.russula_tls_file)
+    }
+
     pub fn cargo_path(&self) -> String {
         format!("{}/bin/cargo", self.host_home_path)
     }
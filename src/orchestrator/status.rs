@@ -0,0 +1,145 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    orchestrator::{OrchError, OrchResult},
+    russula::PeerStatus,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+
+// Upper bound on the size of a status request
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// The coordination status of either the servers or clients
+#[derive(Debug, Clone, Serialize)]
+struct CoordStatus {
+    driver: String,
+    peers: Vec<PeerStatus>,
+}
+
+/// The latest status of each Russula Coordinator.
+///
+/// Updated by the orchestrator while polling the Coordinators and served as
+/// JSON so that tooling can watch a run in progress.
+#[derive(Clone, Default)]
+pub struct StatusBoard(Arc<Mutex<BTreeMap<&'static str, CoordStatus>>>);
+
+impl StatusBoard {
+    pub fn update(&self, side: &'static str, driver: &str, peers: Vec<PeerStatus>) {
+        let status = CoordStatus {
+            driver: driver.to_string(),
+            peers,
+        };
+        self.0.lock().unwrap().insert(side, status);
+    }
+
+    // None if the board was poisoned by a panic while it was being updated
+    fn to_json(&self) -> Option<String> {
+        let board = self.0.lock().ok()?;
+        serde_json::to_string_pretty(&*board).ok()
+    }
+
+    /// Serve the status as JSON at `GET /status` for the rest of the run.
+    pub async fn serve(&self, addr: SocketAddr) -> OrchResult<()> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .map_err(|err| OrchError::Init {
+                dbg: format!("failed to bind the status endpoint {}: {}", addr, err),
+            })?;
+        info!("Status endpoint: http://{}/status", addr);
+
+        let board = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _peer)) => stream,
+                    Err(err) => {
+                        debug!("status endpoint failed to accept: {}", err);
+                        continue;
+                    }
+                };
+                let board = board.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = board.respond(stream).await {
+                        debug!("status endpoint failed to respond: {}", err);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    async fn respond(&self, stream: TcpStream) -> io::Result<()> {
+        let request = read_request(&stream).await?;
+        let response = match request_path(&request) {
+            Some("/") | Some("/status") => match self.to_json() {
+                Some(json) => http_response("200 OK", &json),
+                None => http_response("500 Internal Server Error", "{}"),
+            },
+            _ => http_response("404 Not Found", "{}"),
+        };
+        write_all(&stream, response.as_bytes()).await
+    }
+}
+
+// Read the request up to the end of its headers
+async fn read_request(stream: &TcpStream) -> io::Result<String> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "status request too large",
+            ));
+        }
+
+        stream.readable().await?;
+        match stream.try_read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => request.extend_from_slice(&buf[..len]),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(String::from_utf8_lossy(&request).into_owned())
+}
+
+// The path of a GET request
+fn request_path(request: &str) -> Option<&str> {
+    let mut request_line = request.lines().next()?.split_whitespace();
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(path)) => Some(path),
+        _ => None,
+    }
+}
+
+fn http_response(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+async fn write_all(stream: &TcpStream, data: &[u8]) -> io::Result<()> {
+    let mut written = 0;
+    while written < data.len() {
+        stream.writable().await?;
+        match stream.try_write(&data[written..]) {
+            Ok(len) => written += len,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
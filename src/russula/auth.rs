@@ -0,0 +1,154 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::russula::{network_utils::Msg, RussulaError, RussulaResult};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A secret shared by the Coordinator and Workers of a run.
+///
+/// When configured, each peer proves that it knows the token during the
+/// handshake by answering a challenge from the other peer. The token itself
+/// is never sent over the network.
+#[derive(Clone)]
+pub struct AuthToken(Bytes);
+
+impl AuthToken {
+    pub fn new(secret: impl Into<Bytes>) -> Self {
+        AuthToken(secret.into())
+    }
+
+    // Mac over the challenge sent by the peer and the local challenge. Including
+    // both prevents a peer from reflecting a challenge back at its sender.
+    fn mac(&self, protocol: &str, peer_nonce: &str, local_nonce: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.0).expect("hmac accepts keys of any length");
+        for data in [protocol, peer_nonce, local_nonce] {
+            mac.update(&(data.len() as u64).to_be_bytes());
+            mac.update(data.as_bytes());
+        }
+        mac
+    }
+
+    /// Answer the challenge sent by the peer
+    pub(crate) fn respond(
+        &self,
+        protocol: &str,
+        peer: &AuthChallenge,
+        local: &AuthChallenge,
+    ) -> AuthResponse {
+        let mac = self.mac(protocol, &peer.nonce, &local.nonce);
+        AuthResponse {
+            mac: STANDARD.encode(mac.finalize().into_bytes()),
+        }
+    }
+
+    /// Check the peer's answer to the local challenge
+    pub(crate) fn verify(
+        &self,
+        protocol: &str,
+        local: &AuthChallenge,
+        peer: &AuthChallenge,
+        response: &AuthResponse,
+    ) -> RussulaResult<()> {
+        let fail = |dbg: &str| RussulaError::AuthFailed {
+            dbg: dbg.to_string(),
+        };
+        if local.nonce == peer.nonce {
+            return Err(fail("peer reflected the auth challenge"));
+        }
+
+        let mac = STANDARD
+            .decode(&response.mac)
+            .map_err(|_err| fail("malformed auth response"))?;
+        self.mac(protocol, &local.nonce, &peer.nonce)
+            .verify_slice(&mac)
+            .map_err(|_err| fail("peer auth token doesn't match"))
+    }
+}
+
+impl std::fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AuthToken(..)")
+    }
+}
+
+/// Sent by each peer after the Handshake when an [`AuthToken`] is configured.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct AuthChallenge {
+    nonce: String,
+}
+
+impl AuthChallenge {
+    pub fn new() -> Self {
+        AuthChallenge {
+            nonce: uuid::Uuid::new_v4().to_string(),
+        }
+    }
+
+    pub fn as_msg(&self) -> Msg {
+        Msg::new(Bytes::from(serde_json::to_string(self).unwrap()))
+    }
+
+    pub fn from_msg(msg: &Msg) -> RussulaResult<Self> {
+        serde_json::from_slice(msg.as_bytes()).map_err(|err| RussulaError::BadMsg {
+            dbg: format!("received a malformed AuthChallenge msg: {}", err),
+        })
+    }
+}
+
+/// Proof that the sender knows the [`AuthToken`]
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct AuthResponse {
+    mac: String,
+}
+
+impl AuthResponse {
+    pub fn as_msg(&self) -> Msg {
+        Msg::new(Bytes::from(serde_json::to_string(self).unwrap()))
+    }
+
+    pub fn from_msg(msg: &Msg) -> RussulaResult<Self> {
+        serde_json::from_slice(msg.as_bytes()).map_err(|err| RussulaError::BadMsg {
+            dbg: format!("received a malformed AuthResponse msg: {}", err),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn challenge_response() {
+        let token = AuthToken::new("secret");
+        let (coord, worker) = (AuthChallenge::new(), AuthChallenge::new());
+
+        // the worker answers the coordinator's challenge
+        let response = token.respond("netbench-server", &coord, &worker);
+        assert!(token
+            .verify("netbench-server", &coord, &worker, &response)
+            .is_ok());
+
+        // a different token or protocol produces a different answer
+        let response = AuthToken::new("guess").respond("netbench-server", &coord, &worker);
+        assert!(matches!(
+            token.verify("netbench-server", &coord, &worker, &response),
+            Err(RussulaError::AuthFailed { .. })
+        ));
+        let response = token.respond("netbench-client", &coord, &worker);
+        assert!(token
+            .verify("netbench-server", &coord, &worker, &response)
+            .is_err());
+
+        // the coordinator's own answer can't be replayed back to it
+        let response = token.respond("netbench-server", &worker, &coord);
+        assert!(token
+            .verify("netbench-server", &coord, &worker, &response)
+            .is_err());
+    }
+}
@@ -0,0 +1,66 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Wall-clock time in microseconds since the UNIX epoch
+pub fn now_us() -> u64 {
+    to_us(SystemTime::now())
+}
+
+pub fn to_us(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .expect("system time before UNIX epoch")
+        .as_micros() as u64
+}
+
+/// Estimated offset of the peer's clock relative to the local clock.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClockOffset {
+    /// peer clock - local clock
+    pub offset_us: i64,
+    /// Round trip time of the exchange used to make the estimate. The estimate
+    /// is accurate to within half the round trip time.
+    pub rtt_us: u64,
+}
+
+impl ClockOffset {
+    /// NTP style estimate from a single exchange.
+    ///
+    /// - `t1`: local time the request was sent
+    /// - `t2`: peer time the request was received
+    /// - `t3`: peer time the response was sent
+    /// - `t4`: local time the response was received
+    pub fn estimate(t1: u64, t2: u64, t3: u64, t4: u64) -> Self {
+        let (t1, t2, t3, t4) = (t1 as i64, t2 as i64, t3 as i64, t4 as i64);
+        ClockOffset {
+            offset_us: ((t2 - t1) + (t3 - t4)) / 2,
+            rtt_us: ((t4 - t1) - (t3 - t2)).max(0) as u64,
+        }
+    }
+
+    /// Convert a time on the peer's clock to the local clock
+    pub fn peer_to_local(&self, peer_us: u64) -> u64 {
+        (peer_us as i64 - self.offset_us) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_offset() {
+        // peer clock is 1000us ahead with a 100us one-way delay
+        let offset = ClockOffset::estimate(0, 1100, 1150, 250);
+        assert_eq!(offset.offset_us, 1000);
+        assert_eq!(offset.rtt_us, 200);
+        assert_eq!(offset.peer_to_local(5000), 4000);
+
+        // peer clock is behind
+        let offset = ClockOffset::estimate(5000, 4100, 4100, 5200);
+        assert_eq!(offset.offset_us, -1000);
+        assert_eq!(offset.peer_to_local(4000), 5000);
+    }
+}
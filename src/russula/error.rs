@@ -1,16 +1,30 @@
 // Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::net::SocketAddr;
 use tokio::io::ErrorKind;
 
 pub type RussulaResult<T, E = RussulaError> = Result<T, E>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum RussulaError {
     NetworkConnectionRefused { dbg: String },
     NetworkFail { dbg: String },
     NetworkBlocked { dbg: String },
     BadMsg { dbg: String },
+    VersionMismatch { local: String, peer: String },
+    ProtocolMismatch { dbg: String },
+    AuthFailed { dbg: String },
+    ProcessFailed { dbg: String },
+    PeerTimeout { addr: SocketAddr, dbg: String },
+    PeerFailures { failures: Vec<PeerFailure> },
+}
+
+/// A fatal error seen while polling a peer
+#[derive(Debug, Clone)]
+pub struct PeerFailure {
+    pub addr: SocketAddr,
+    pub err: RussulaError,
 }
 
 impl std::fmt::Display for RussulaError {
@@ -22,6 +36,20 @@ impl std::fmt::Display for RussulaError {
             RussulaError::NetworkFail { dbg } => write!(f, "NetworkFail {}", dbg),
             RussulaError::NetworkBlocked { dbg } => write!(f, "NetworkBlocked {}", dbg),
             RussulaError::BadMsg { dbg } => write!(f, "BadMsg {}", dbg),
+            RussulaError::VersionMismatch { local, peer } => {
+                write!(f, "VersionMismatch local: {} peer: {}", local, peer)
+            }
+            RussulaError::ProtocolMismatch { dbg } => write!(f, "ProtocolMismatch {}", dbg),
+            RussulaError::AuthFailed { dbg } => write!(f, "AuthFailed {}", dbg),
+            RussulaError::ProcessFailed { dbg } => write!(f, "ProcessFailed {}", dbg),
+            RussulaError::PeerTimeout { addr, dbg } => write!(f, "PeerTimeout {} {}", addr, dbg),
+            RussulaError::PeerFailures { failures } => {
+                write!(f, "PeerFailures")?;
+                for failure in failures {
+                    write!(f, " [{}: {}]", failure.addr, failure.err)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -36,6 +64,15 @@ impl RussulaError {
             _ => true,
         }
     }
+
+    /// The connection to the peer was lost and could be re-established
+    pub fn is_disconnect(&self) -> bool {
+        matches!(
+            self,
+            RussulaError::NetworkFail { dbg: _ }
+                | RussulaError::NetworkConnectionRefused { dbg: _ }
+        )
+    }
 }
 
 impl From<tokio::io::Error> for RussulaError {
@@ -1,34 +1,291 @@
 // Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use core::fmt::{Debug, Display};
+use super::{
+    clock::{self, ClockOffset},
+    process::{ProcessExit, RunParams},
+};
+use core::{
+    fmt::{Debug, Display},
+    time::Duration,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
 
 pub enum EventType {
-    SendMsg,
-    RecvMsg,
+    // Number of bytes sent
+    SendMsg(usize),
+    // Number of bytes received
+    RecvMsg(usize),
+    // Moved to the next state
+    Transition { from: String, to: String },
+    // An error seen while polling the peer
+    Error(String),
 }
 
-#[derive(Debug, Default, Clone)]
+/// A State the protocol was in and how long it lasted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateRecord {
+    pub state: String,
+    pub entered_at_us: u64,
+    /// None if this is the current state
+    pub duration_us: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorRecord {
+    pub at_us: u64,
+    pub err: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EventRecorder {
+    created_at_us: u64,
     send_msg: u64,
     recv_msg: u64,
+    send_bytes: u64,
+    recv_bytes: u64,
+    states: Vec<StateRecord>,
+    errors: Vec<ErrorRecord>,
+    // Offset of the Coordinator's clock relative to the Worker's clock
+    clock_offset: Option<ClockOffset>,
+    // Time between the scheduled start and when the Worker process was started
+    start_skew_us: Option<i64>,
+    // How the process supervised by the Worker stopped
+    process_exit: Option<ProcessExit>,
+    // Parameters applied to the process supervised by the Worker
+    run_params: Option<RunParams>,
+    // Wall-clock time the last msg was received from the peer
+    last_recv_at_us: Option<u64>,
+    // The last State received from the peer
+    peer_state: Option<String>,
+}
+
+impl Default for EventRecorder {
+    fn default() -> Self {
+        EventRecorder {
+            created_at_us: clock::now_us(),
+            send_msg: 0,
+            recv_msg: 0,
+            send_bytes: 0,
+            recv_bytes: 0,
+            states: Vec::new(),
+            errors: Vec::new(),
+            clock_offset: None,
+            start_skew_us: None,
+            process_exit: None,
+            run_params: None,
+            last_recv_at_us: None,
+            peer_state: None,
+        }
+    }
 }
 
 impl EventRecorder {
     pub fn process(&mut self, event: EventType) {
         match event {
-            EventType::SendMsg => self.send_msg += 1,
-            EventType::RecvMsg => self.recv_msg += 1,
+            EventType::SendMsg(bytes) => {
+                self.send_msg += 1;
+                self.send_bytes += bytes as u64;
+            }
+            EventType::RecvMsg(bytes) => {
+                self.recv_msg += 1;
+                self.recv_bytes += bytes as u64;
+                self.last_recv_at_us = Some(clock::now_us());
+            }
+            EventType::Transition { from, to } => self.on_transition(from, to),
+            EventType::Error(err) => self.errors.push(ErrorRecord {
+                at_us: clock::now_us(),
+                err,
+            }),
+        }
+    }
+
+    pub fn record_clock_offset(&mut self, clock_offset: ClockOffset) {
+        self.clock_offset = Some(clock_offset);
+    }
+
+    pub fn record_start_skew(&mut self, start_skew_us: i64) {
+        self.start_skew_us = Some(start_skew_us);
+    }
+
+    pub fn record_process_exit(&mut self, process_exit: ProcessExit) {
+        self.process_exit = Some(process_exit);
+    }
+
+    pub fn record_run_params(&mut self, run_params: RunParams) {
+        self.run_params = Some(run_params);
+    }
+
+    pub fn record_peer_state(&mut self, peer_state: String) {
+        self.peer_state = Some(peer_state);
+    }
+
+    pub fn counts(&self) -> EventCounts {
+        EventCounts {
+            send_msg: self.send_msg,
+            recv_msg: self.recv_msg,
+            send_bytes: self.send_bytes,
+            recv_bytes: self.recv_bytes,
+            errors: self.errors.len(),
         }
     }
+
+    #[cfg(test)]
+    pub fn states(&self) -> &[StateRecord] {
+        &self.states
+    }
+
+    #[cfg(test)]
+    pub fn start_skew_us(&self) -> Option<i64> {
+        self.start_skew_us
+    }
+
+    #[cfg(test)]
+    pub fn errors(&self) -> &[ErrorRecord] {
+        &self.errors
+    }
+
+    fn on_transition(&mut self, from: String, to: String) {
+        let now = clock::now_us();
+        match self.states.last_mut() {
+            Some(current) => {
+                current.duration_us = Some(now.saturating_sub(current.entered_at_us));
+            }
+            None => {
+                // the initial state was entered when the recorder was created
+                self.states.push(StateRecord {
+                    state: from,
+                    entered_at_us: self.created_at_us,
+                    duration_us: Some(now.saturating_sub(self.created_at_us)),
+                });
+            }
+        }
+
+        self.states.push(StateRecord {
+            state: to,
+            entered_at_us: now,
+            duration_us: None,
+        });
+    }
 }
 
 impl Display for EventRecorder {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "send_cnt: {}, recv_cnt: {}",
-            self.send_msg, self.recv_msg
-        )
+            "send_cnt: {}, recv_cnt: {}, send_bytes: {}, recv_bytes: {}, errors: {}",
+            self.send_msg,
+            self.recv_msg,
+            self.send_bytes,
+            self.recv_bytes,
+            self.errors.len()
+        )?;
+        if let Some(clock_offset) = self.clock_offset {
+            write!(
+                f,
+                ", clock_offset_us: {} (rtt_us: {})",
+                clock_offset.offset_us, clock_offset.rtt_us
+            )?;
+        }
+        if let Some(start_skew_us) = self.start_skew_us {
+            write!(f, ", start_skew_us: {}", start_skew_us)?;
+        }
+        if let Some(process_exit) = &self.process_exit {
+            write!(f, ", process: {}", process_exit)?;
+        }
+        for state in self.states.iter() {
+            match state.duration_us {
+                Some(duration_us) => write!(
+                    f,
+                    "\n  {}: {:?}",
+                    state.state,
+                    Duration::from_micros(duration_us)
+                )?,
+                None => write!(f, "\n  {}", state.state)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Events recorded by a protocol instance for one of its peers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerEvents {
+    pub addr: SocketAddr,
+    pub events: EventRecorder,
+}
+
+/// Totals of the events recorded for a peer
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct EventCounts {
+    pub send_msg: u64,
+    pub recv_msg: u64,
+    pub send_bytes: u64,
+    pub recv_bytes: u64,
+    pub errors: usize,
+}
+
+/// A snapshot of the coordination with a peer, used to watch a run in progress
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerStatus {
+    pub addr: SocketAddr,
+    /// The local State of the protocol instance for this peer
+    pub state: String,
+    /// The last State received from the peer
+    pub peer_state: Option<String>,
+    /// Wall-clock time the last msg was received from the peer
+    pub last_msg_at_us: Option<u64>,
+    pub counts: EventCounts,
+    /// A fatal error seen while polling the peer
+    pub failure: Option<String>,
+}
+
+impl PeerStatus {
+    pub(crate) fn new(
+        addr: SocketAddr,
+        state: String,
+        events: &EventRecorder,
+        failure: Option<String>,
+    ) -> Self {
+        PeerStatus {
+            addr,
+            state,
+            peer_state: events.peer_state.clone(),
+            last_msg_at_us: events.last_recv_at_us,
+            counts: events.counts(),
+            failure,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_durations() {
+        let mut recorder = EventRecorder::default();
+        recorder.process(EventType::Transition {
+            from: "CheckWorker".to_string(),
+            to: "Ready".to_string(),
+        });
+        std::thread::sleep(Duration::from_millis(10));
+        recorder.process(EventType::Transition {
+            from: "Ready".to_string(),
+            to: "Done".to_string(),
+        });
+
+        let states: Vec<&str> = recorder
+            .states()
+            .iter()
+            .map(|state| state.state.as_str())
+            .collect();
+        assert_eq!(states, ["CheckWorker", "Ready", "Done"]);
+        assert!(recorder.states()[1].duration_us.unwrap() >= 10_000);
+        assert!(recorder.states()[2].duration_us.is_none());
+
+        let json = serde_json::to_value(&recorder).unwrap();
+        assert_eq!(json["states"].as_array().unwrap().len(), 3);
     }
 }
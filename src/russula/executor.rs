@@ -0,0 +1,288 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::russula::process::ProcessSpec;
+use std::{
+    fmt::Debug,
+    fs::File,
+    io,
+    os::unix::process::ExitStatusExt,
+    process::{Child, ExitStatus},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tracing::{debug, info};
+
+// Ids handed out to processes which aren't run by the OS
+static NEXT_SIMULATED_PID: AtomicU32 = AtomicU32::new(1);
+
+/// Starts the process described by a [`ProcessSpec`] on behalf of a Worker.
+///
+/// The spec describes what a Worker runs and the Executor how it's run, so the
+/// same spec can be run locally, simulated in tests or only printed.
+pub trait Executor: Debug + Send + Sync {
+    /// Start the process of `iteration`, which names its output files
+    fn spawn(&self, spec: &ProcessSpec, iteration: u32) -> io::Result<Box<dyn ProcessHandle>>;
+}
+
+/// A process started by an [`Executor`]
+pub trait ProcessHandle: Debug + Send {
+    fn id(&self) -> u32;
+
+    /// The exit status if the process has exited. Doesn't block.
+    fn try_wait(&mut self) -> io::Result<Option<ExitStatus>>;
+
+    /// Block until the process exits
+    fn wait(&mut self) -> io::Result<ExitStatus>;
+
+    /// Send `signal` to the process, or to its whole process group if it has one.
+    /// Succeeds if the process has already exited.
+    fn signal(&mut self, signal: libc::c_int) -> io::Result<()>;
+}
+
+/// Runs the process on the local host
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalProcess;
+
+impl Executor for LocalProcess {
+    fn spawn(&self, spec: &ProcessSpec, iteration: u32) -> io::Result<Box<dyn ProcessHandle>> {
+        let mut cmd = spec.command();
+        if let Some(stdout_path) = spec.stdout_path(iteration) {
+            cmd.stdout(File::create(stdout_path)?);
+        }
+        if let Some(stderr_path) = spec.stderr_path(iteration) {
+            cmd.stderr(File::create(stderr_path)?);
+        }
+        debug!("{:?}", cmd);
+
+        let child = cmd.spawn()?;
+        Ok(Box::new(LocalHandle {
+            child,
+            process_group: spec.process_group,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct LocalHandle {
+    child: Child,
+    process_group: bool,
+}
+
+impl ProcessHandle for LocalHandle {
+    fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.child.try_wait()
+    }
+
+    fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.child.wait()
+    }
+
+    fn signal(&mut self, signal: libc::c_int) -> io::Result<()> {
+        let pid = self.child.id() as libc::pid_t;
+        // a negative pid signals every process in the group
+        let target = if self.process_group { -pid } else { pid };
+
+        // SAFETY: kill only sends a signal and doesn't touch any memory
+        if unsafe { libc::kill(target, signal) } != 0 {
+            let err = io::Error::last_os_error();
+            // the processes have already exited
+            if err.raw_os_error() != Some(libc::ESRCH) {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints and records the command which would be run without running it. The
+/// process exits successfully as soon as it's started.
+#[derive(Debug, Clone, Default)]
+pub struct DryRun {
+    commands: Arc<Mutex<Vec<String>>>,
+}
+
+impl DryRun {
+    /// The commands which would have been run, in order
+    #[cfg(test)]
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.lock().unwrap().clone()
+    }
+}
+
+impl Executor for DryRun {
+    fn spawn(&self, spec: &ProcessSpec, iteration: u32) -> io::Result<Box<dyn ProcessHandle>> {
+        let mut command = format!("{:?}", spec.command());
+        if let Some(stdout_path) = spec.stdout_path(iteration) {
+            command.push_str(&format!(" > {}", stdout_path.display()));
+        }
+        if let Some(stderr_path) = spec.stderr_path(iteration) {
+            command.push_str(&format!(" 2> {}", stderr_path.display()));
+        }
+        info!("dry run: {}", command);
+        self.commands.lock().unwrap().push(command);
+
+        Ok(Box::new(SimulatedHandle::new(Some(Duration::ZERO), 0)))
+    }
+}
+
+/// Simulates a process which writes `output` to its stdout and exits with
+/// `exit_code` once `duration` passes. Without a duration the process runs until
+/// it's signaled.
+#[derive(Debug, Clone, Default)]
+pub struct Simulated {
+    duration: Option<Duration>,
+    exit_code: i32,
+    output: String,
+}
+
+impl Simulated {
+    pub fn new() -> Self {
+        Simulated::default()
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_exit_code(mut self, exit_code: i32) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+
+    pub fn with_output(mut self, output: impl Into<String>) -> Self {
+        self.output = output.into();
+        self
+    }
+}
+
+impl Executor for Simulated {
+    fn spawn(&self, spec: &ProcessSpec, iteration: u32) -> io::Result<Box<dyn ProcessHandle>> {
+        if let Some(stdout_path) = spec.stdout_path(iteration) {
+            std::fs::write(stdout_path, &self.output)?;
+        }
+        Ok(Box::new(SimulatedHandle::new(
+            self.duration,
+            self.exit_code,
+        )))
+    }
+}
+
+#[derive(Debug)]
+struct SimulatedHandle {
+    pid: u32,
+    started: Instant,
+    duration: Option<Duration>,
+    exit_code: i32,
+    // The signal which stopped the process
+    signaled: Option<libc::c_int>,
+}
+
+impl SimulatedHandle {
+    fn new(duration: Option<Duration>, exit_code: i32) -> Self {
+        SimulatedHandle {
+            pid: NEXT_SIMULATED_PID.fetch_add(1, Ordering::Relaxed),
+            started: Instant::now(),
+            duration,
+            exit_code,
+            signaled: None,
+        }
+    }
+}
+
+impl ProcessHandle for SimulatedHandle {
+    fn id(&self) -> u32 {
+        self.pid
+    }
+
+    fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        if let Some(signal) = self.signaled {
+            // the raw wait status of a process terminated by a signal
+            return Ok(Some(ExitStatus::from_raw(signal)));
+        }
+        let exited = self
+            .duration
+            .is_some_and(|duration| self.started.elapsed() >= duration);
+        Ok(exited.then(|| ExitStatus::from_raw(self.exit_code << 8)))
+    }
+
+    fn wait(&mut self) -> io::Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.try_wait()? {
+                return Ok(status);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn signal(&mut self, signal: libc::c_int) -> io::Result<()> {
+        // signal 0 only checks that the process exists
+        if signal != 0 && self.try_wait()?.is_none() {
+            self.signaled = Some(signal);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulated() {
+        let dir = tempdir::TempDir::new("simulated").unwrap();
+        let stdout_path = dir.path().join("out.json");
+        let spec = ProcessSpec::new("netbench").with_stdout_path(&stdout_path);
+
+        let executor = Simulated::new()
+            .with_duration(Duration::from_millis(50))
+            .with_exit_code(3)
+            .with_output("{}");
+        let mut process = executor.spawn(&spec, 0).unwrap();
+        assert_eq!(std::fs::read_to_string(&stdout_path).unwrap(), "{}");
+        assert_eq!(process.try_wait().unwrap(), None);
+        assert_eq!(process.wait().unwrap().code(), Some(3));
+
+        // runs until it's signaled without a duration
+        let mut process = Simulated::new().spawn(&spec, 1).unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(process.try_wait().unwrap(), None);
+        process.signal(libc::SIGTERM).unwrap();
+        let status = process.try_wait().unwrap().unwrap();
+        assert_eq!(status.code(), None);
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+    }
+
+    #[test]
+    fn dry_run() {
+        let dir = tempdir::TempDir::new("dry_run").unwrap();
+        let stdout_path = dir.path().join("out.json");
+        let spec = ProcessSpec::new("netbench-collector")
+            .with_args(["netbench-driver-s2n-quic-server"])
+            .with_env("PORT", "4433")
+            .with_stdout_path(&stdout_path);
+
+        let executor = DryRun::default();
+        let mut process = executor.spawn(&spec, 2).unwrap();
+        assert!(process.try_wait().unwrap().unwrap().success());
+
+        // nothing is run or written
+        let commands = executor.commands();
+        assert_eq!(commands.len(), 1);
+        assert!(commands[0].starts_with(
+            r#"PORT="4433" "netbench-collector" "netbench-driver-s2n-quic-server" > "#
+        ));
+        assert!(commands[0].ends_with("iter2-out.json"));
+        assert!(!stdout_path.exists());
+        assert!(!dir.path().join("iter2-out.json").exists());
+    }
+}
@@ -0,0 +1,453 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::{
+    netbench::{self, client, server},
+    protocol::Protocol,
+    transport::{
+        memory::{Fault, Faults, MemoryNetwork},
+        Network,
+    },
+    FailurePolicy, Russula, RussulaBuilder, RussulaError, RussulaResult,
+};
+use core::time::Duration;
+use futures::{
+    channel::oneshot,
+    future::{join_all, FutureExt},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::{Ipv4Addr, SocketAddr},
+};
+
+const POLL_DELAY: Duration = Duration::from_millis(100);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(200);
+// Workers don't read msgs while awaiting the process so this has to outlast the
+// simulated netbench run
+const LIVENESS_TIMEOUT: Duration = Duration::from_secs(8);
+// Upper bound on driving the Coordinator through all of the phases
+const RUN_LIMIT: Duration = Duration::from_secs(20);
+// How long the Workers have to finish once the Coordinator is done
+const SETTLE: Duration = Duration::from_secs(1);
+// Workers listen on consecutive ports, which are only used as addrs on the
+// in-memory network
+const BASE_PORT: u16 = 7000;
+
+/// A failure injected into a run
+#[derive(Debug, Clone)]
+pub enum Failure {
+    /// The Worker drops its connection when it sends `state`
+    WorkerDisconnect { worker: usize, state: &'static str },
+    /// The Worker sends a malformed msg in place of `state`
+    WorkerBadMsg { worker: usize, state: &'static str },
+    /// The Coordinator sends a malformed msg to the Worker in place of `state`
+    CoordBadMsg { worker: usize, state: &'static str },
+    /// The Coordinator is replaced by a new one once it completes the phase
+    CoordRestart(Phase),
+}
+
+/// The phases the Coordinator is driven through, as done by the orchestrator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Ready,
+    WorkerRunning,
+    Done,
+}
+
+/// How a peer ended the run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    /// Failed with the named [`RussulaError`] variant
+    Failed(&'static str),
+    /// Hadn't finished by the end of the run and was left at the named State
+    Stalled(String),
+}
+
+/// The outcome for each Worker, as seen by the Coordinator and by the Worker
+#[derive(Debug)]
+pub struct Report {
+    pub coord: Vec<Outcome>,
+    pub workers: Vec<Outcome>,
+}
+
+/// Runs a netbench Coordinator against Workers in `testing` mode with scripted
+/// failures.
+///
+/// The peers are connected over a [`MemoryNetwork`] and the failures are injected
+/// into the msgs sent at the chosen States. The run ends once the Coordinator
+/// completes, fails or reaches the run limit, and the Workers which haven't
+/// finished shortly after are reported as stalled.
+pub struct Harness<C, W> {
+    coord: C,
+    workers: Vec<W>,
+    failures: Vec<Failure>,
+    failure_policy: FailurePolicy,
+    reconnect_attempts: usize,
+    run_limit: Duration,
+}
+
+impl Harness<server::CoordProtocol, server::WorkerProtocol> {
+    /// `workers` netbench server Workers. `name` identifies the output of the run.
+    pub fn server(name: &str, workers: usize) -> Self {
+        let workers = (0..workers)
+            .map(|idx| {
+                let id = format!("{}-{}", name, idx);
+                server::WorkerProtocol::new(id, netbench::ServerContext::testing())
+            })
+            .collect();
+        Harness::new(server::CoordProtocol::new(), workers)
+    }
+}
+
+impl Harness<client::CoordProtocol, client::WorkerProtocol> {
+    /// `workers` netbench client Workers. `name` identifies the output of the run.
+    pub fn client(name: &str, workers: usize) -> Self {
+        let workers = (0..workers)
+            .map(|idx| {
+                let id = format!("{}-{}", name, idx);
+                client::WorkerProtocol::new(id, netbench::ClientContext::testing())
+            })
+            .collect();
+        Harness::new(client::CoordProtocol::new(), workers)
+    }
+}
+
+impl<C, W> Harness<C, W>
+where
+    C: Protocol + Send,
+    W: Protocol + Send,
+{
+    fn new(coord: C, workers: Vec<W>) -> Self {
+        Harness {
+            coord,
+            workers,
+            failures: Vec::new(),
+            failure_policy: FailurePolicy::default(),
+            reconnect_attempts: 3,
+            run_limit: RUN_LIMIT,
+        }
+    }
+
+    pub fn with_failure(mut self, failure: Failure) -> Self {
+        self.failures.push(failure);
+        self
+    }
+
+    pub fn with_failure_policy(mut self, failure_policy: FailurePolicy) -> Self {
+        self.failure_policy = failure_policy;
+        self
+    }
+
+    /// Number of attempts made by both sides to re-establish a dropped connection
+    pub fn with_reconnect_attempts(mut self, attempts: usize) -> Self {
+        self.reconnect_attempts = attempts;
+        self
+    }
+
+    pub fn with_run_limit(mut self, run_limit: Duration) -> Self {
+        self.run_limit = run_limit;
+        self
+    }
+
+    pub async fn run(self) -> Report {
+        let addrs: Vec<SocketAddr> = (0..self.workers.len())
+            .map(|idx| SocketAddr::from((Ipv4Addr::LOCALHOST, BASE_PORT + idx as u16)))
+            .collect();
+        let network = Network::Memory(self.network(&addrs));
+
+        // the Workers are given a little longer once the Coordinator is done
+        let (coord_done, done) = oneshot::channel::<()>();
+        let done = done.shared();
+
+        let workers = self.workers.iter().zip(addrs.iter()).map(|(worker, addr)| {
+            let done = done.clone();
+            self.run_worker(&network, *addr, worker.clone(), async move {
+                let _ = done.await;
+                tokio::time::sleep(SETTLE).await;
+            })
+        });
+        let coord = async {
+            let outcome = self.run_coord(&network, &addrs).await;
+            let _ = coord_done.send(());
+            outcome
+        };
+
+        let (coord, workers) = tokio::join!(coord, join_all(workers));
+        Report { coord, workers }
+    }
+
+    // Apply the scripted failures to the connection with each Worker
+    fn network(&self, addrs: &[SocketAddr]) -> MemoryNetwork {
+        let mut faults: BTreeMap<usize, (Faults, Faults)> = BTreeMap::new();
+        for failure in self.failures.iter() {
+            let (worker, state, fault, from_coord) = match failure {
+                Failure::WorkerDisconnect { worker, state } => {
+                    (worker, state, Fault::Disconnect, false)
+                }
+                Failure::WorkerBadMsg { worker, state } => (worker, state, Fault::Corrupt, false),
+                Failure::CoordBadMsg { worker, state } => (worker, state, Fault::Corrupt, true),
+                Failure::CoordRestart(_) => continue,
+            };
+
+            // the State is sent as a quoted name, followed by any data it carries
+            let pattern = format!("\"{}\"", state);
+            let (coord_faults, worker_faults) = faults.entry(*worker).or_default();
+            match from_coord {
+                true => *coord_faults = coord_faults.clone().on_frame(&pattern, fault, 1),
+                false => *worker_faults = worker_faults.clone().on_frame(&pattern, fault, 1),
+            }
+        }
+
+        faults.into_iter().fold(
+            MemoryNetwork::new(),
+            |network, (worker, (coord_faults, worker_faults))| {
+                network.with_peer_faults(addrs[worker], coord_faults, worker_faults)
+            },
+        )
+    }
+
+    async fn run_coord(&self, network: &Network, addrs: &[SocketAddr]) -> Vec<Outcome> {
+        let restart = self.failures.iter().find_map(|failure| match failure {
+            Failure::CoordRestart(phase) => Some(*phase),
+            _ => None,
+        });
+
+        let deadline = tokio::time::Instant::now() + self.run_limit;
+        let mut coord = match self.build(network, addrs, self.coord.clone()).await {
+            Ok(coord) => coord,
+            Err(err) => return vec![Outcome::Failed(error_name(&err)); addrs.len()],
+        };
+        for phase in [Phase::Ready, Phase::WorkerRunning, Phase::Done] {
+            let res = match phase {
+                Phase::Ready => tokio::time::timeout_at(deadline, coord.run_till_ready()).await,
+                Phase::WorkerRunning => {
+                    tokio::time::timeout_at(deadline, coord.run_till_worker_running()).await
+                }
+                Phase::Done => tokio::time::timeout_at(deadline, coord.run_till_done()).await,
+            };
+            if !matches!(res, Ok(Ok(()))) {
+                break;
+            }
+
+            if restart == Some(phase) {
+                // close the connections so that the Workers accept the new Coordinator
+                drop(coord);
+                coord = match self.build(network, addrs, self.coord.clone()).await {
+                    Ok(coord) => coord,
+                    Err(err) => return vec![Outcome::Failed(error_name(&err)); addrs.len()],
+                };
+            }
+        }
+
+        let outcomes = coord
+            .instance_list
+            .iter()
+            .map(|peer| match &peer.failure {
+                Some(err) => Outcome::Failed(error_name(err)),
+                None if peer.protocol.is_done_state() => Outcome::Done,
+                None => Outcome::Stalled(state_name(peer.protocol.state())),
+            })
+            .collect();
+
+        // keep the connections open for a moment so that Workers which were moved
+        // straight to Done can read the Coordinator's final State
+        tokio::time::sleep(SETTLE).await;
+        outcomes
+    }
+
+    async fn run_worker(
+        &self,
+        network: &Network,
+        addr: SocketAddr,
+        protocol: W,
+        deadline: impl core::future::Future<Output = ()>,
+    ) -> Outcome {
+        tokio::pin!(deadline);
+        let addrs = [addr];
+        let mut worker = tokio::select! {
+            worker = self.build(network, &addrs, protocol) => match worker {
+                Ok(worker) => worker,
+                Err(err) => return Outcome::Failed(error_name(&err)),
+            },
+            _ = &mut deadline => return Outcome::Stalled("NotConnected".to_string()),
+        };
+        let res = tokio::select! {
+            res = worker.run_till_done() => res,
+            _ = &mut deadline => {
+                let state = worker.instance_list[0].protocol.state();
+                return Outcome::Stalled(state_name(state));
+            }
+        };
+
+        match res {
+            Ok(()) => Outcome::Done,
+            // a Worker only has the one peer so report its failure directly
+            Err(RussulaError::PeerFailures { failures }) => {
+                Outcome::Failed(error_name(&failures[0].err))
+            }
+            Err(err) => Outcome::Failed(error_name(&err)),
+        }
+    }
+
+    async fn build<P: Protocol + Send>(
+        &self,
+        network: &Network,
+        addrs: &[SocketAddr],
+        protocol: P,
+    ) -> RussulaResult<Russula<P>> {
+        RussulaBuilder::new(
+            BTreeSet::from_iter(addrs.iter().copied()),
+            protocol,
+            POLL_DELAY,
+        )
+        .with_network(network.clone())
+        .with_failure_policy(self.failure_policy)
+        .with_reconnect_attempts(self.reconnect_attempts)
+        .with_heartbeat_interval(Some(HEARTBEAT_INTERVAL))
+        .with_liveness_timeout(Some(LIVENESS_TIMEOUT))
+        .build()
+        .await
+    }
+}
+
+// The State without any data it carries
+fn state_name<S: core::fmt::Debug>(state: &S) -> String {
+    let state = format!("{:?}", state);
+    match state.split_once('(') {
+        Some((name, _data)) => name.to_string(),
+        None => state,
+    }
+}
+
+fn error_name(err: &RussulaError) -> &'static str {
+    match err {
+        RussulaError::NetworkConnectionRefused { .. } => "NetworkConnectionRefused",
+        RussulaError::NetworkFail { .. } => "NetworkFail",
+        RussulaError::NetworkBlocked { .. } => "NetworkBlocked",
+        RussulaError::BadMsg { .. } => "BadMsg",
+        RussulaError::VersionMismatch { .. } => "VersionMismatch",
+        RussulaError::ProtocolMismatch { .. } => "ProtocolMismatch",
+        RussulaError::AuthFailed { .. } => "AuthFailed",
+        RussulaError::ProcessFailed { .. } => "ProcessFailed",
+        RussulaError::PeerTimeout { .. } => "PeerTimeout",
+        RussulaError::PeerFailures { .. } => "PeerFailures",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn server_no_failures() {
+        let report = Harness::server("harness-server", 2).run().await;
+        assert_eq!(report.coord, vec![Outcome::Done; 2]);
+        assert_eq!(report.workers, vec![Outcome::Done; 2]);
+    }
+
+    #[tokio::test]
+    async fn client_no_failures() {
+        let report = Harness::client("harness-client", 2).run().await;
+        assert_eq!(report.coord, vec![Outcome::Done; 2]);
+        assert_eq!(report.workers, vec![Outcome::Done; 2]);
+    }
+
+    #[tokio::test]
+    async fn worker_disconnect_reconnects() {
+        let report = Harness::server("harness-disconnect", 2)
+            .with_failure(Failure::WorkerDisconnect {
+                worker: 0,
+                state: "RunningAwaitKill",
+            })
+            .run()
+            .await;
+        assert_eq!(report.coord, vec![Outcome::Done; 2]);
+        assert_eq!(report.workers, vec![Outcome::Done; 2]);
+    }
+
+    #[tokio::test]
+    async fn worker_disconnect_drive_to_done() {
+        let report = Harness::server("harness-disconnect-fail", 2)
+            .with_failure(Failure::WorkerDisconnect {
+                worker: 0,
+                state: "Ready",
+            })
+            .with_failure_policy(FailurePolicy::DriveToDone)
+            .with_reconnect_attempts(0)
+            .run()
+            .await;
+        assert_eq!(
+            report.coord,
+            vec![Outcome::Failed("NetworkFail"), Outcome::Done]
+        );
+        assert_eq!(
+            report.workers,
+            vec![Outcome::Failed("NetworkFail"), Outcome::Done]
+        );
+    }
+
+    #[tokio::test]
+    async fn worker_bad_msg() {
+        let report = Harness::server("harness-worker-bad-msg", 2)
+            .with_failure(Failure::WorkerBadMsg {
+                worker: 1,
+                state: "Ready",
+            })
+            .with_failure_policy(FailurePolicy::DriveToDone)
+            .run()
+            .await;
+        assert_eq!(report.coord, vec![Outcome::Done, Outcome::Failed("BadMsg")]);
+        // the Coordinator stops polling the Worker so it never sees RunWorker
+        assert_eq!(
+            report.workers,
+            vec![Outcome::Done, Outcome::Stalled("Ready".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn coord_bad_msg() {
+        let report = Harness::client("harness-coord-bad-msg", 2)
+            .with_failure(Failure::CoordBadMsg {
+                worker: 0,
+                state: "WorkersRunning",
+            })
+            .with_failure_policy(FailurePolicy::DriveToDone)
+            .run()
+            .await;
+        assert_eq!(
+            report.workers,
+            vec![Outcome::Failed("BadMsg"), Outcome::Done]
+        );
+        assert!(matches!(report.coord[0], Outcome::Failed(_)));
+        assert_eq!(report.coord[1], Outcome::Done);
+    }
+
+    #[tokio::test]
+    async fn coord_restart_ready() {
+        let report = Harness::server("harness-restart-ready", 2)
+            .with_failure(Failure::CoordRestart(Phase::Ready))
+            .run()
+            .await;
+        assert_eq!(report.coord, vec![Outcome::Done; 2]);
+        assert_eq!(report.workers, vec![Outcome::Done; 2]);
+    }
+
+    #[tokio::test]
+    async fn coord_restart_running() {
+        // a new Coordinator can't drive Workers which are already running
+        let report = Harness::server("harness-restart-running", 2)
+            .with_failure(Failure::CoordRestart(Phase::WorkerRunning))
+            .with_run_limit(Duration::from_secs(5))
+            .run()
+            .await;
+        assert_eq!(
+            report.coord,
+            vec![Outcome::Stalled("CheckWorker".to_string()); 2]
+        );
+        assert_eq!(
+            report.workers,
+            vec![Outcome::Stalled("RunningAwaitKill".to_string()); 2]
+        );
+    }
+}
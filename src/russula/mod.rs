@@ -3,29 +3,194 @@
 
 use core::{task::Poll, time::Duration};
 use paste::paste;
-use std::{collections::BTreeSet, net::SocketAddr};
-use tokio::net::TcpStream;
-use tracing::{error, info};
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Instant, SystemTime},
+};
+use tracing::{debug, error, info, warn};
 
+mod auth;
+mod clock;
 mod error;
 mod event;
+mod executor;
+#[cfg(test)]
+mod harness;
 pub mod netbench;
 mod network_utils;
+mod process;
 mod protocol;
+mod results;
 mod states;
+#[cfg(test)]
+mod transition_table;
+mod transport;
 
-use error::{RussulaError, RussulaResult};
-use protocol::Protocol;
+pub use auth::AuthToken;
+pub use error::{PeerFailure, RussulaError, RussulaResult};
+use event::EventType;
+pub use event::{PeerEvents, PeerStatus};
+use network_utils::FramedStream;
+pub use protocol::Protocol;
 use states::{StateApi, TransitionStep};
+pub use transport::tls::TlsConfig;
+use transport::Network;
+
+// Number of attempts made to connect to a peer before giving up
+const CONNECT_RETRY_ATTEMPTS: usize = 10;
+
+// How long the remaining peers are polled towards Done once a peer has failed
+const DRIVE_TO_DONE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The orchestrator version, also reported as `STATE.version`. Defined here since
+/// the orchestrator state isn't part of the russula_cli build. Exchanged during the
+/// handshake so that a Coordinator and Worker built from different sources fail
+/// early.
+pub const VERSION: &str = "v2.4.0";
 
 // TODO
 // - separate Russula struct for Coord/Worker since they have different APIs
-// - look at NTP for synchronization: start_at(time)
 
 struct ProtocolInstance<P: Protocol> {
     pub addr: SocketAddr,
-    pub stream: TcpStream,
+    pub stream: FramedStream,
     pub protocol: P,
+    // The peer is considered dead if no progress is made within this duration
+    pub liveness_timeout: Option<Duration>,
+    // A fatal error seen while polling the peer. Failed peers are no longer polled.
+    pub failure: Option<RussulaError>,
+    // Attempts made to re-establish a dropped connection before the peer is failed
+    pub reconnect_attempts: usize,
+    pub retry_delay: Duration,
+    pub auth_token: Option<AuthToken>,
+    pub network: Network,
+    // When polling the peer first returned Pending for the current target state
+    pub pending_since: Option<Instant>,
+    // The last poll moved the protocol to a new state
+    pub progressed: bool,
+}
+
+impl<P: Protocol> ProtocolInstance<P> {
+    // Attempt to make progress towards `state` and record any fatal errors
+    async fn poll_state(&mut self, state: &P::State) {
+        let prev = self.protocol.state().clone();
+        let poll = self.protocol.poll_state(&mut self.stream, state).await;
+        self.progressed = !prev.eq(self.protocol.state());
+        let poll = poll.and_then(|poll| self.check_liveness(poll));
+
+        if poll.is_ok() && self.protocol.is_done_state() {
+            self.check_peer_failure();
+        }
+
+        if let Err(err) = poll {
+            if self.protocol.is_done_state() && err.is_disconnect() {
+                // The peer is free to close the connection once coordination is Done
+                debug!("Ignore {} since coordination is Done. {}", err, self.addr);
+                return;
+            }
+
+            if err.is_fatal() {
+                error!("{} {}", err, self.addr);
+                self.protocol
+                    .event_recorder()
+                    .process(EventType::Error(err.to_string()));
+                if err.is_disconnect() && self.reconnect_attempts > 0 {
+                    match self.reconnect().await {
+                        Ok(()) => return,
+                        Err(reconnect_err) => {
+                            error!("Failed to reconnect to {}: {}", self.addr, reconnect_err)
+                        }
+                    }
+                }
+                self.failure = Some(err);
+            }
+        }
+    }
+
+    // Fail the peer if it reported a failure, such as a crashed Worker process
+    fn check_peer_failure(&mut self) {
+        if let Some(err) = self.protocol.peer_failure() {
+            error!("{} {}", err, self.addr);
+            self.protocol
+                .event_recorder()
+                .process(EventType::Error(err.to_string()));
+            self.failure = Some(err);
+        }
+    }
+
+    // Move straight to Done if the peer hasn't started running
+    async fn cancel(&mut self) {
+        if let Err(err) = self.protocol.cancel(&mut self.stream).await {
+            error!("{} {}", err, self.addr);
+            self.protocol
+                .event_recorder()
+                .process(EventType::Error(err.to_string()));
+            self.failure = Some(err);
+        }
+    }
+
+    // Fail with PeerTimeout if the peer has been pending without sending a msg
+    // for longer than the liveness timeout
+    fn check_liveness(&mut self, poll: Poll<()>) -> RussulaResult<Poll<()>> {
+        match (poll, self.liveness_timeout) {
+            (Poll::Pending, Some(timeout)) => {
+                let pending_since = *self.pending_since.get_or_insert_with(Instant::now);
+                if pending_since.max(self.stream.last_recv()).elapsed() > timeout {
+                    return Err(self.peer_timeout());
+                }
+            }
+            _ => self.pending_since = None,
+        }
+        Ok(poll)
+    }
+
+    // Re-establish the connection and resume the state machine from the current state
+    async fn reconnect(&mut self) -> RussulaResult<()> {
+        warn!(
+            "{} lost connection to {}.. reconnecting",
+            self.protocol.name(),
+            self.addr
+        );
+        let mut stream = connect_peer(
+            &mut self.protocol,
+            &self.network,
+            &self.addr,
+            self.reconnect_attempts,
+            self.retry_delay,
+            self.auth_token.as_ref(),
+        )
+        .await?;
+        stream.set_heartbeat_interval(self.stream.heartbeat_interval());
+        self.stream = stream;
+        Ok(())
+    }
+
+    fn peer_timeout(&self) -> RussulaError {
+        RussulaError::PeerTimeout {
+            addr: self.addr,
+            dbg: format!(
+                "{} no progress within {:?}. last msg received {:?} ago",
+                self.protocol.name(),
+                self.liveness_timeout.unwrap_or_default(),
+                self.stream.last_recv().elapsed()
+            ),
+        }
+    }
+}
+
+/// What to do with the remaining peers once a peer has failed
+#[derive(Debug, Default, Clone, Copy)]
+pub enum FailurePolicy {
+    /// Stop polling and leave the remaining peers at their current state
+    #[default]
+    Abort,
+    /// Drive the remaining peers to the Done state before reporting the failure.
+    /// Peers which haven't started running move straight to Done and peers which
+    /// don't reach Done within the drive to Done timeout are failed.
+    DriveToDone,
 }
 
 pub struct Russula<P: Protocol> {
@@ -34,7 +199,10 @@ pub struct Russula<P: Protocol> {
     // The Coord should be a list of size 1
     // The Worker can be list of size >=1
     instance_list: Vec<ProtocolInstance<P>>,
+    // Upper bound on how long to wait for a msg before polling the peers again
     poll_delay: Duration,
+    failure_policy: FailurePolicy,
+    drive_to_done_timeout: Duration,
 }
 
 macro_rules! state_api {
@@ -46,22 +214,18 @@ macro_rules! state_api {
     $(#[$meta])*
     pub async fn [<run_till_ $state>](&mut self) -> RussulaResult<()> {
         while self.[<poll_ $state>]().await?.is_pending() {
-            tokio::time::sleep(self.poll_delay).await;
+            self.await_peers(|protocol| protocol.[<$state _state>]()).await;
         }
 
         Ok(())
     }
 
     $(#[$meta])*
+    /// Returns [`RussulaError::PeerFailures`] if any of the peers have failed.
     pub async fn [<poll_ $state>](&mut self) -> RussulaResult<Poll<()>> {
-        for peer in self.instance_list.iter_mut() {
-            if let Err(err) = peer.protocol.[<poll_ $state>](&peer.stream).await {
-                if err.is_fatal() {
-                    error!("{} {}", err, peer.addr);
-                    panic!("{} {}", err, peer.addr);
-                }
-            }
-        }
+        self.poll_peers(|protocol| protocol.[<$state _state>]()).await;
+        self.check_failures().await?;
+
         let poll = if self.[<is_ $state _state>]() {
             Poll::Ready(())
         } else {
@@ -72,7 +236,7 @@ macro_rules! state_api {
 
     /// Check if all instances are at the desired state
     fn [< is_ $state _state>](&self) -> bool {
-        for peer in self.instance_list.iter() {
+        for peer in self.healthy_peers() {
             // All instance must be at the desired state
             if !peer.protocol.[< is_ $state _state>]() {
                 return false;
@@ -91,6 +255,193 @@ impl<P: Protocol + Send> Russula<P> {
         /// Should only be called by Coordinators
         worker_running
     );
+
+    state_api!(
+        /// Should only be called by Coordinators
+        worker_stopped
+    );
+
+    /// Schedule the Workers to start at the wall-clock time `start_at`.
+    ///
+    /// Should only be called by Coordinators before moving the Workers to Run. Each
+    /// Worker converts `start_at` to its own clock using the offset estimated during
+    /// the handshake, and fails the run if it's scheduled more than a minute ahead.
+    pub fn start_at(&mut self, start_at: SystemTime) {
+        for peer in self.instance_list.iter_mut() {
+            peer.protocol.set_start_at(start_at);
+        }
+    }
+
+    /// Send `run` to the Workers along with the State which starts the next run.
+    ///
+    /// Should only be called by Coordinators before moving the Workers to Run. Each
+    /// Worker overrides the parameters it was launched with using `run`, which lets
+    /// a long-lived Worker run something different each iteration.
+    pub fn set_run<T: Serialize>(&mut self, run: &T) {
+        let run = serde_json::to_value(run).expect("run parameters serialize to JSON");
+        for peer in self.instance_list.iter_mut() {
+            peer.protocol.set_run(run.clone());
+        }
+    }
+
+    /// Have the Workers stream their result files once their process stops, which
+    /// are written under `dir` as they arrive.
+    ///
+    /// Should only be called by Coordinators. The results are complete once the
+    /// Workers reach Done.
+    pub fn collect_results(&mut self, dir: impl Into<PathBuf>) {
+        let dir = dir.into();
+        for peer in self.instance_list.iter_mut() {
+            peer.protocol.set_results_dir(dir.clone());
+        }
+    }
+
+    /// The number of result files collected from the Workers so far
+    pub fn results_collected(&self) -> usize {
+        self.instance_list
+            .iter()
+            .map(|peer| peer.protocol.results_written())
+            .sum()
+    }
+
+    /// Run the Workers again once they reach the worker_stopped State, rather than
+    /// moving to Done. Each iteration writes its own output files.
+    ///
+    /// Should only be called by Coordinators once the Workers have stopped or before
+    /// polling them to stop.
+    pub fn next_iteration(&mut self) {
+        for peer in self.instance_list.iter_mut() {
+            peer.protocol.next_iteration();
+        }
+    }
+
+    /// Events recorded for each peer, which can be serialized to JSON
+    pub fn peer_events(&mut self) -> Vec<PeerEvents> {
+        self.instance_list
+            .iter_mut()
+            .map(|peer| PeerEvents {
+                addr: peer.addr,
+                events: peer.protocol.event_recorder().clone(),
+            })
+            .collect()
+    }
+
+    /// A snapshot of the coordination with each peer, for watching a run in progress
+    pub fn status(&mut self) -> Vec<PeerStatus> {
+        self.instance_list
+            .iter_mut()
+            .map(|peer| {
+                let state = format!("{:?}", peer.protocol.state());
+                let failure = peer.failure.as_ref().map(|err| err.to_string());
+                PeerStatus::new(peer.addr, state, peer.protocol.event_recorder(), failure)
+            })
+            .collect()
+    }
+
+    // Poll all peers which have not failed
+    async fn poll_peers(&mut self, state: impl Fn(&P) -> P::State) {
+        for peer in self.instance_list.iter_mut() {
+            if peer.failure.is_none() {
+                let state = state(&peer.protocol);
+                peer.poll_state(&state).await;
+            }
+        }
+    }
+
+    // Wait for a msg from any peer which has yet to reach the desired state.
+    //
+    // Returns immediately if a peer made progress on the last poll since it might be
+    // able to progress further without a msg. The wait is bounded by `poll_delay` and
+    // the heartbeat interval so that silent peers continue to be polled.
+    async fn await_peers(&self, state: impl Fn(&P) -> P::State) {
+        let mut timeout = self.poll_delay;
+        let mut streams = Vec::new();
+        for peer in self.healthy_peers() {
+            if peer.protocol.state().eq(&state(&peer.protocol)) {
+                continue;
+            }
+            if peer.progressed {
+                return;
+            }
+            if let Some(interval) = peer.stream.heartbeat_interval() {
+                timeout = timeout.min(interval);
+            }
+            streams.push(&peer.stream);
+        }
+
+        let _ = tokio::time::timeout(timeout, network_utils::readable_any(&streams)).await;
+    }
+
+    // Report failed peers after applying the FailurePolicy to the remaining peers
+    async fn check_failures(&mut self) -> RussulaResult<()> {
+        if self.healthy_peers().count() == self.instance_list.len() {
+            return Ok(());
+        }
+
+        if let FailurePolicy::DriveToDone = self.failure_policy {
+            info!("Peer failure.. driving remaining peers to Done");
+            for peer in self.instance_list.iter_mut() {
+                if peer.failure.is_none() {
+                    peer.cancel().await;
+                }
+            }
+
+            let deadline = Instant::now() + self.drive_to_done_timeout;
+            loop {
+                self.poll_peers(|protocol| protocol.done_state()).await;
+                if self.is_done_state() {
+                    break;
+                }
+                if Instant::now() >= deadline {
+                    self.fail_not_done();
+                    break;
+                }
+                self.await_peers(|protocol| protocol.done_state()).await;
+            }
+        }
+
+        let failures = self
+            .instance_list
+            .iter()
+            .filter_map(|peer| {
+                peer.failure.as_ref().map(|err| PeerFailure {
+                    addr: peer.addr,
+                    err: err.clone(),
+                })
+            })
+            .collect();
+        Err(RussulaError::PeerFailures { failures })
+    }
+
+    // Fail the healthy peers which didn't reach Done within the drive to Done timeout
+    fn fail_not_done(&mut self) {
+        let timeout = self.drive_to_done_timeout;
+        for peer in self.instance_list.iter_mut() {
+            if peer.failure.is_some() || peer.protocol.is_done_state() {
+                continue;
+            }
+            let err = RussulaError::PeerTimeout {
+                addr: peer.addr,
+                dbg: format!(
+                    "{} not Done within {:?}. stuck at {:?}",
+                    peer.protocol.name(),
+                    timeout,
+                    peer.protocol.state()
+                ),
+            };
+            error!("{} {}", err, peer.addr);
+            peer.protocol
+                .event_recorder()
+                .process(EventType::Error(err.to_string()));
+            peer.failure = Some(err);
+        }
+    }
+
+    fn healthy_peers(&self) -> impl Iterator<Item = &ProtocolInstance<P>> {
+        self.instance_list
+            .iter()
+            .filter(|peer| peer.failure.is_none())
+    }
 }
 
 pub type SockProtocol<P> = (SocketAddr, P);
@@ -101,6 +452,13 @@ pub struct RussulaBuilder<P: Protocol> {
     // The Worker gets its own addr to 'listen' on.
     russula_pair_addr_list: Vec<SockProtocol<P>>,
     poll_delay: Duration,
+    heartbeat_interval: Option<Duration>,
+    liveness_timeout: Option<Duration>,
+    failure_policy: FailurePolicy,
+    drive_to_done_timeout: Duration,
+    reconnect_attempts: usize,
+    auth_token: Option<AuthToken>,
+    network: Network,
 }
 
 impl<P: Protocol> RussulaBuilder<P> {
@@ -113,75 +471,754 @@ impl<P: Protocol> RussulaBuilder<P> {
         Self {
             russula_pair_addr_list: peer_list,
             poll_delay,
+            heartbeat_interval: None,
+            liveness_timeout: None,
+            failure_policy: FailurePolicy::default(),
+            drive_to_done_timeout: DRIVE_TO_DONE_TIMEOUT,
+            reconnect_attempts: CONNECT_RETRY_ATTEMPTS,
+            auth_token: None,
+            network: Network::default(),
+        }
+    }
+
+    /// Set what happens to the remaining peers once a peer has failed
+    pub fn with_failure_policy(mut self, failure_policy: FailurePolicy) -> Self {
+        self.failure_policy = failure_policy;
+        self
+    }
+
+    /// Fail the peers which don't reach Done within `timeout` when driven to Done by
+    /// [`FailurePolicy::DriveToDone`]
+    pub fn with_drive_to_done_timeout(mut self, timeout: Duration) -> Self {
+        self.drive_to_done_timeout = timeout;
+        self
+    }
+
+    /// Re-send the current state to peers which have been silent for `interval`
+    pub fn with_heartbeat_interval(mut self, interval: Option<Duration>) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    /// Fail with [`RussulaError::PeerTimeout`] if polling a peer doesn't complete within
+    /// `timeout`
+    pub fn with_liveness_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.liveness_timeout = timeout;
+        self
+    }
+
+    /// Number of attempts made to re-establish a dropped connection to a peer.
+    ///
+    /// Setting this to 0 fails the peer as soon as the connection is dropped.
+    pub fn with_reconnect_attempts(mut self, attempts: usize) -> Self {
+        self.reconnect_attempts = attempts;
+        self
+    }
+
+    /// Require peers to authenticate with a pre-shared `auth_token` during the
+    /// handshake
+    pub fn with_auth_token(mut self, auth_token: Option<AuthToken>) -> Self {
+        self.auth_token = auth_token;
+        self
+    }
+
+    /// Authenticate the peers and encrypt their connections with mutual TLS, using
+    /// the certificates of the run
+    pub fn with_tls(mut self, tls: Option<TlsConfig>) -> Self {
+        if let Some(tls) = tls {
+            self.network = Network::Tls(tls);
         }
+        self
+    }
+
+    /// Connect the peers over `network` rather than TCP
+    #[cfg(test)]
+    fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
     }
 
     pub async fn build(self) -> RussulaResult<Russula<P>> {
         let mut stream_protocol_list = Vec::new();
-        for (addr, protocol) in self.russula_pair_addr_list.into_iter() {
-            let stream;
-            let mut retry_attempts = 10;
-            loop {
-                if retry_attempts == 0 {
-                    return Err(RussulaError::NetworkConnectionRefused {
-                        dbg: "Failed to connect to peer".to_string(),
-                    });
-                }
-                match protocol.connect(&addr).await {
-                    Ok(connect) => {
-                        stream = connect;
-                        break;
-                    }
-                    Err(err) => {
-                        error!(
-                            "Failed to connect.. wait and retry. Try disabling VPN and check your network connectivity.
-                            \nRetry attempts left: {}. addr: {} dbg: {}",
-                            retry_attempts, addr, err
-                        );
-                        println!(
-                            "Failed to connect.. wait and retry. Try disabling VPN and check your network connectivity.
-                            \nRetry attempts left: {}. addr: {} dbg: {}",
-                            retry_attempts, addr, err
-                        );
-                        tokio::time::sleep(self.poll_delay).await;
-                    }
-                }
-                retry_attempts -= 1
-            }
-
-            info!("Coordinator: successfully connected to {}", addr);
+        for (addr, mut protocol) in self.russula_pair_addr_list.into_iter() {
+            let mut stream = connect_peer(
+                &mut protocol,
+                &self.network,
+                &addr,
+                CONNECT_RETRY_ATTEMPTS,
+                self.poll_delay,
+                self.auth_token.as_ref(),
+            )
+            .await?;
+            stream.set_heartbeat_interval(self.heartbeat_interval);
             stream_protocol_list.push(ProtocolInstance {
                 addr,
                 stream,
                 protocol,
+                liveness_timeout: self.liveness_timeout,
+                failure: None,
+                reconnect_attempts: self.reconnect_attempts,
+                retry_delay: self.poll_delay,
+                auth_token: self.auth_token.clone(),
+                network: self.network.clone(),
+                pending_since: None,
+                progressed: false,
             });
         }
 
         Ok(Russula {
             instance_list: stream_protocol_list,
             poll_delay: self.poll_delay,
+            failure_policy: self.failure_policy,
+            drive_to_done_timeout: self.drive_to_done_timeout,
         })
     }
 }
 
+// Connect to the peer, retrying on failure, exchange a handshake and report the
+// current state.
+//
+// Peers which fail to authenticate, with the auth token or TLS, are dropped and
+// count as a failed attempt, so that a Worker continues to listen for the
+// Coordinator.
+async fn connect_peer<P: Protocol>(
+    protocol: &mut P,
+    network: &Network,
+    addr: &SocketAddr,
+    mut retry_attempts: usize,
+    retry_delay: Duration,
+    auth_token: Option<&AuthToken>,
+) -> RussulaResult<FramedStream> {
+    let mut auth_err = None;
+    let (mut stream, clock_offset) = loop {
+        if retry_attempts == 0 {
+            if let Some(err) = auth_err {
+                return Err(err);
+            }
+            return Err(RussulaError::NetworkConnectionRefused {
+                dbg: "Failed to connect to peer".to_string(),
+            });
+        }
+        match protocol.connect(network, addr).await {
+            Ok(connect) => {
+                info!("{}: successfully connected to {}", protocol.name(), addr);
+                let mut stream = FramedStream::new(connect);
+                match protocol.handshake(&mut stream, auth_token).await {
+                    Ok(clock_offset) => break (stream, clock_offset),
+                    Err(err @ RussulaError::AuthFailed { dbg: _ }) => {
+                        error!("{}: rejected peer {}. {}", protocol.name(), addr, err);
+                        auth_err = Some(err);
+                        tokio::time::sleep(retry_delay).await;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            Err(err) => {
+                if let RussulaError::AuthFailed { dbg: _ } = err {
+                    auth_err = Some(err.clone());
+                }
+                error!(
+                    "Failed to connect.. wait and retry. Try disabling VPN and check your network connectivity.
+                    \nRetry attempts left: {}. addr: {} dbg: {}",
+                    retry_attempts, addr, err
+                );
+                println!(
+                    "Failed to connect.. wait and retry. Try disabling VPN and check your network connectivity.
+                    \nRetry attempts left: {}. addr: {} dbg: {}",
+                    retry_attempts, addr, err
+                );
+                tokio::time::sleep(retry_delay).await;
+            }
+        }
+        retry_attempts -= 1
+    };
+
+    protocol.set_clock_offset(clock_offset);
+    protocol.resume(&mut stream).await?;
+    Ok(stream)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::russula::netbench::{client, server};
+    use crate::russula::{
+        netbench::{client, server},
+        transport::memory::{Fault, Faults, MemoryNetwork, MemoryTransport},
+    };
     use futures::future::join_all;
     use std::str::FromStr;
 
     const POLL_DELAY_DURATION: Duration = Duration::from_secs(1);
 
+    fn memory_network() -> Network {
+        Network::Memory(MemoryNetwork::new())
+    }
+
+    #[tokio::test]
+    async fn silent_peer_timeout() {
+        let network = memory_network();
+        let addr = SocketAddr::from_str("127.0.0.1:9000").unwrap();
+        let listener = network.listen(&addr).await.unwrap();
+
+        // a worker which completes the handshake and then goes silent
+        let worker = tokio::spawn(async move {
+            let stream = listener.accept().await.unwrap();
+            let mut stream = FramedStream::new(stream);
+            let protocol =
+                server::WorkerProtocol::new("0".to_string(), netbench::ServerContext::testing());
+            protocol.handshake(&mut stream, None).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let coord = RussulaBuilder::new(
+            BTreeSet::from_iter([addr]),
+            server::CoordProtocol::new(),
+            POLL_DELAY_DURATION,
+        )
+        .with_heartbeat_interval(Some(Duration::from_millis(100)))
+        .with_liveness_timeout(Some(Duration::from_millis(500)))
+        .with_network(network);
+        let mut coord = coord.build().await.unwrap();
+
+        match coord.run_till_ready().await {
+            Err(RussulaError::PeerFailures { failures }) => {
+                assert_eq!(failures.len(), 1);
+                assert_eq!(failures[0].addr, addr);
+                assert!(matches!(failures[0].err, RussulaError::PeerTimeout { .. }));
+            }
+            res => panic!("expected PeerTimeout but found: {:?}", res),
+        }
+        worker.abort();
+    }
+
+    #[tokio::test]
+    async fn heartbeat_outlives_liveness_timeout() {
+        let network = memory_network();
+        let worker_addr = SocketAddr::from_str("127.0.0.1:9005").unwrap();
+        let worker_network = network.clone();
+
+        // the client runs for longer than the Coordinator's liveness timeout and
+        // only stays alive by heartbeating. The Worker gets its own thread, as it
+        // would be its own process, so that polling its process doesn't starve the
+        // Coordinator.
+        let worker = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async move {
+                let worker = RussulaBuilder::new(
+                    BTreeSet::from_iter([worker_addr]),
+                    client::WorkerProtocol::new(
+                        "0".to_string(),
+                        netbench::ClientContext::testing_with_duration(Duration::from_secs(3)),
+                    ),
+                    POLL_DELAY_DURATION,
+                )
+                .with_heartbeat_interval(Some(Duration::from_millis(200)))
+                .with_network(worker_network);
+                let mut worker = worker.build().await.unwrap();
+                worker.run_till_done().await.unwrap();
+                worker
+            })
+        });
+
+        let coord = RussulaBuilder::new(
+            BTreeSet::from_iter([worker_addr]),
+            client::CoordProtocol::new(),
+            POLL_DELAY_DURATION,
+        )
+        .with_heartbeat_interval(Some(Duration::from_millis(200)))
+        .with_liveness_timeout(Some(Duration::from_secs(1)))
+        .with_network(network);
+        let mut coord = coord.build().await.unwrap();
+
+        coord.run_till_worker_running().await.unwrap();
+        coord.run_till_done().await.unwrap();
+        assert!(coord.instance_list[0].failure.is_none());
+        assert!(worker.join().unwrap().is_done_state());
+    }
+
+    #[tokio::test]
+    async fn peer_failure_drive_to_done() {
+        let network = memory_network();
+        let failed_addr = SocketAddr::from_str("127.0.0.1:9010").unwrap();
+        let listener = network.listen(&failed_addr).await.unwrap();
+
+        // a worker which completes the handshake and then disconnects
+        tokio::spawn(async move {
+            let stream = listener.accept().await.unwrap();
+            let mut stream = FramedStream::new(stream);
+            let protocol =
+                server::WorkerProtocol::new("0".to_string(), netbench::ServerContext::testing());
+            protocol.handshake(&mut stream, None).await.unwrap();
+        });
+
+        let worker_addr = SocketAddr::from_str("127.0.0.1:9011").unwrap();
+        let worker_network = network.clone();
+        let worker = tokio::spawn(async move {
+            let worker = RussulaBuilder::new(
+                BTreeSet::from_iter([worker_addr]),
+                server::WorkerProtocol::new("1".to_string(), netbench::ServerContext::testing()),
+                POLL_DELAY_DURATION,
+            )
+            .with_network(worker_network);
+            let mut worker = worker.build().await.unwrap();
+            worker.run_till_done().await.unwrap();
+            worker
+        });
+
+        let coord = RussulaBuilder::new(
+            BTreeSet::from_iter([failed_addr, worker_addr]),
+            server::CoordProtocol::new(),
+            POLL_DELAY_DURATION,
+        )
+        .with_failure_policy(FailurePolicy::DriveToDone)
+        // the failed peer is gone so don't attempt to reconnect
+        .with_reconnect_attempts(0)
+        .with_network(network);
+        let mut coord = coord.build().await.unwrap();
+
+        match coord.run_till_worker_running().await {
+            Err(RussulaError::PeerFailures { failures }) => {
+                assert_eq!(failures.len(), 1);
+                assert_eq!(failures[0].addr, failed_addr);
+            }
+            res => panic!("expected PeerFailures but found: {:?}", res),
+        }
+
+        // the remaining worker was driven to Done
+        assert!(worker.await.unwrap().is_done_state());
+    }
+
+    #[tokio::test]
+    async fn peer_failure_cancels_run() {
+        let network = memory_network();
+        let failed_addr = SocketAddr::from_str("127.0.0.1:9012").unwrap();
+        let listener = network.listen(&failed_addr).await.unwrap();
+
+        // a worker which completes the handshake and then disconnects
+        tokio::spawn(async move {
+            let stream = listener.accept().await.unwrap();
+            let mut stream = FramedStream::new(stream);
+            let protocol =
+                server::WorkerProtocol::new("0".to_string(), netbench::ServerContext::testing());
+            protocol.handshake(&mut stream, None).await.unwrap();
+        });
+
+        let worker_addr = SocketAddr::from_str("127.0.0.1:9013").unwrap();
+        let worker_network = network.clone();
+        let worker = tokio::spawn(async move {
+            let worker = RussulaBuilder::new(
+                BTreeSet::from_iter([worker_addr]),
+                server::WorkerProtocol::new("1".to_string(), netbench::ServerContext::testing()),
+                POLL_DELAY_DURATION,
+            )
+            .with_network(worker_network);
+            let mut worker = worker.build().await.unwrap();
+            worker.run_till_done().await.unwrap();
+            worker
+        });
+
+        let coord = RussulaBuilder::new(
+            BTreeSet::from_iter([failed_addr, worker_addr]),
+            server::CoordProtocol::new(),
+            POLL_DELAY_DURATION,
+        )
+        .with_failure_policy(FailurePolicy::DriveToDone)
+        .with_reconnect_attempts(0)
+        .with_network(network);
+        let mut coord = coord.build().await.unwrap();
+
+        match coord.run_till_ready().await {
+            Err(RussulaError::PeerFailures { failures }) => {
+                assert_eq!(failures.len(), 1);
+                assert_eq!(failures[0].addr, failed_addr);
+            }
+            res => panic!("expected PeerFailures but found: {:?}", res),
+        }
+
+        // the remaining worker moved straight to Done without running its process
+        let mut worker = worker.await.unwrap();
+        assert!(worker.is_done_state());
+        let events = serde_json::to_value(worker.peer_events()).unwrap();
+        let states: Vec<&str> = events[0]["events"]["states"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|state| state["state"].as_str().unwrap())
+            .collect();
+        assert!(!states.contains(&"Run"), "{:?}", states);
+    }
+
+    #[tokio::test]
+    async fn drive_to_done_timeout() {
+        let network = memory_network();
+
+        // workers which report Ready and then either go silent or disconnect once
+        // asked to run, which gives the other worker time to be asked to run too
+        let fake_worker = |listener: transport::Listener, disconnect: bool| {
+            tokio::spawn(async move {
+                let stream = listener.accept().await.unwrap();
+                let mut stream = FramedStream::new(stream);
+                let protocol = server::WorkerProtocol::new(
+                    "0".to_string(),
+                    netbench::ServerContext::testing(),
+                );
+                protocol.handshake(&mut stream, None).await.unwrap();
+                let ready = states::Envelope {
+                    seq: stream.next_seq(),
+                    sender: protocol.name(),
+                    state: server::WorkerState::Ready,
+                    payload: None,
+                };
+                network_utils::send_msg(&mut stream, ready.as_msg())
+                    .await
+                    .unwrap();
+
+                loop {
+                    let msg = network_utils::recv_msg(&mut stream).await.unwrap();
+                    let envelope = states::Envelope::<server::CoordState>::from_msg(&msg).unwrap();
+                    if disconnect && matches!(envelope.state, server::CoordState::RunWorker) {
+                        tokio::time::sleep(Duration::from_millis(300)).await;
+                        return;
+                    }
+                }
+            })
+        };
+        let failed_addr = SocketAddr::from_str("127.0.0.1:9014").unwrap();
+        let wedged_addr = SocketAddr::from_str("127.0.0.1:9015").unwrap();
+        let _failed = fake_worker(network.listen(&failed_addr).await.unwrap(), true);
+        let wedged = fake_worker(network.listen(&wedged_addr).await.unwrap(), false);
+
+        let coord = RussulaBuilder::new(
+            BTreeSet::from_iter([failed_addr, wedged_addr]),
+            server::CoordProtocol::new(),
+            Duration::from_millis(100),
+        )
+        .with_failure_policy(FailurePolicy::DriveToDone)
+        .with_drive_to_done_timeout(Duration::from_millis(500))
+        .with_reconnect_attempts(0)
+        .with_network(network.clone());
+        let mut coord = coord.build().await.unwrap();
+
+        // the wedged peer never reaches Done so it's failed once the timeout passes
+        match coord.run_till_worker_running().await {
+            Err(RussulaError::PeerFailures { failures }) => {
+                assert_eq!(failures.len(), 2);
+                assert_eq!(failures[0].addr, failed_addr);
+                assert_eq!(failures[1].addr, wedged_addr);
+                assert!(matches!(failures[1].err, RussulaError::PeerTimeout { .. }));
+            }
+            res => panic!("expected PeerFailures but found: {:?}", res),
+        }
+        wedged.abort();
+    }
+
+    #[tokio::test]
+    async fn process_spawn_failure() {
+        let dir = tempdir::TempDir::new("process_spawn_failure").unwrap();
+        let output_dir = dir.path().to_path_buf();
+        let network = memory_network();
+        let worker_addr = SocketAddr::from_str("127.0.0.1:9016").unwrap();
+        let worker_network = network.clone();
+        let worker = tokio::spawn(async move {
+            let ctx = netbench::ClientContext::new(
+                "/nonexistent".into(),
+                "sim".to_string(),
+                "sim.json".to_string(),
+                vec![],
+            )
+            .with_output_dir(output_dir);
+            let worker = RussulaBuilder::new(
+                BTreeSet::from_iter([worker_addr]),
+                client::WorkerProtocol::new("0".to_string(), ctx),
+                POLL_DELAY_DURATION,
+            )
+            .with_network(worker_network);
+            let mut worker = worker.build().await.unwrap();
+            worker.run_till_done().await.unwrap();
+            worker
+        });
+
+        let coord = RussulaBuilder::new(
+            BTreeSet::from_iter([worker_addr]),
+            client::CoordProtocol::new(),
+            POLL_DELAY_DURATION,
+        )
+        .with_network(network);
+        let mut coord = coord.build().await.unwrap();
+
+        // the Worker reports the failed run rather than going down with it
+        match coord.run_till_done().await {
+            Err(RussulaError::PeerFailures { failures }) => {
+                assert_eq!(failures.len(), 1);
+                assert!(matches!(
+                    failures[0].err,
+                    RussulaError::ProcessFailed { .. }
+                ));
+            }
+            res => panic!("expected ProcessFailed but found: {:?}", res),
+        }
+        assert!(coord.instance_list[0].protocol.is_done_state());
+        assert!(worker.await.unwrap().is_done_state());
+    }
+
+    #[tokio::test]
+    async fn reconnect_resume() {
+        let network = memory_network();
+        let worker_addr = SocketAddr::from_str("127.0.0.1:9021").unwrap();
+        let worker_network = network.clone();
+        let worker = tokio::spawn(async move {
+            let worker = RussulaBuilder::new(
+                BTreeSet::from_iter([worker_addr]),
+                server::WorkerProtocol::new("0".to_string(), netbench::ServerContext::testing()),
+                POLL_DELAY_DURATION,
+            )
+            .with_network(worker_network);
+            let mut worker = worker.build().await.unwrap();
+            worker.run_till_done().await.unwrap();
+            worker
+        });
+
+        let coord = RussulaBuilder::new(
+            BTreeSet::from_iter([worker_addr]),
+            server::CoordProtocol::new(),
+            POLL_DELAY_DURATION,
+        )
+        .with_network(network);
+        let mut coord = coord.build().await.unwrap();
+        coord.run_till_worker_running().await.unwrap();
+
+        // drop the connection while the worker is running
+        let (dead_stream, _) = MemoryTransport::pair(Faults::default(), Faults::default());
+        coord.instance_list[0].stream = FramedStream::new(Box::new(dead_stream));
+
+        // both halves reconnect and resume from the running state
+        coord.run_till_done().await.unwrap();
+        assert!(coord.instance_list[0].failure.is_none());
+        assert!(worker.await.unwrap().is_done_state());
+
+        // the dropped connection and each transition were recorded
+        let events = coord.instance_list[0].protocol.event_recorder();
+        assert!(!events.errors().is_empty());
+        let events = serde_json::to_value(coord.peer_events()).unwrap();
+        let states: Vec<&str> = events[0]["events"]["states"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|state| state["state"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            states,
+            [
+                "CheckWorker",
+                "Ready",
+                "RunWorker",
+                "WorkersRunning",
+                "KillWorker",
+                "WorkerKilled",
+                "Done"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn synchronized_start() {
+        let network = memory_network();
+        let mut worker_addrs = Vec::new();
+        let mut workers = Vec::new();
+        for port in [9031, 9032] {
+            let sock = SocketAddr::from_str(&format!("127.0.0.1:{}", port)).unwrap();
+            let network = network.clone();
+            workers.push(tokio::spawn(async move {
+                let worker = RussulaBuilder::new(
+                    BTreeSet::from_iter([sock]),
+                    server::WorkerProtocol::new(
+                        sock.port().to_string(),
+                        netbench::ServerContext::testing(),
+                    ),
+                    POLL_DELAY_DURATION,
+                )
+                .with_network(network);
+                let mut worker = worker.build().await.unwrap();
+                worker.run_till_done().await.unwrap();
+            }));
+            worker_addrs.push(sock);
+        }
+
+        let coord = RussulaBuilder::new(
+            BTreeSet::from_iter(worker_addrs),
+            server::CoordProtocol::new(),
+            POLL_DELAY_DURATION,
+        )
+        .with_network(network);
+        let mut coord = coord.build().await.unwrap();
+        coord.run_till_ready().await.unwrap();
+
+        let start_delay = Duration::from_millis(500);
+        let start = Instant::now();
+        coord.start_at(SystemTime::now() + start_delay);
+        coord.run_till_worker_running().await.unwrap();
+        assert!(start.elapsed() >= start_delay);
+
+        // each worker reported when it started relative to the scheduled start
+        for peer in coord.instance_list.iter_mut() {
+            let start_skew_us = peer.protocol.event_recorder().start_skew_us().unwrap();
+            assert!(start_skew_us.abs() < 100_000, "skew: {}us", start_skew_us);
+        }
+
+        coord.run_till_done().await.unwrap();
+        for worker in workers {
+            worker.await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn iterations() {
+        let network = memory_network();
+        let worker_addr = SocketAddr::from_str("127.0.0.1:9041").unwrap();
+        let worker_network = network.clone();
+        let worker = tokio::spawn(async move {
+            let worker = RussulaBuilder::new(
+                BTreeSet::from_iter([worker_addr]),
+                server::WorkerProtocol::new(
+                    "iterations".to_string(),
+                    netbench::ServerContext::testing(),
+                ),
+                POLL_DELAY_DURATION,
+            )
+            .with_network(worker_network);
+            let mut worker = worker.build().await.unwrap();
+            worker.run_till_done().await.unwrap();
+            worker
+        });
+
+        let coord = RussulaBuilder::new(
+            BTreeSet::from_iter([worker_addr]),
+            server::CoordProtocol::new(),
+            POLL_DELAY_DURATION,
+        )
+        .with_network(network);
+        let mut coord = coord.build().await.unwrap();
+        let results_dir = tempdir::TempDir::new("iterations").unwrap();
+        coord.collect_results(results_dir.path());
+        for iteration in 0..2 {
+            if iteration > 0 {
+                coord.next_iteration();
+            }
+            coord.run_till_worker_running().await.unwrap();
+
+            let status = coord.status();
+            assert_eq!(status.len(), 1);
+            assert_eq!(status[0].addr, worker_addr);
+            assert_eq!(status[0].state, "WorkersRunning");
+            let peer_state = status[0].peer_state.as_deref().unwrap();
+            assert!(peer_state.starts_with("RunningAwaitKill"));
+            assert!(status[0].last_msg_at_us.is_some());
+            assert!(status[0].counts.recv_msg > 0);
+            assert!(status[0].failure.is_none());
+            coord.run_till_worker_stopped().await.unwrap();
+        }
+        coord.run_till_done().await.unwrap();
+        assert!(worker.await.unwrap().is_done_state());
+
+        // the worker was run twice in the same session
+        let events = serde_json::to_value(coord.peer_events()).unwrap();
+        let runs = events[0]["events"]["states"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|state| state["state"] == "RunWorker")
+            .count();
+        assert_eq!(runs, 2);
+
+        // each iteration streamed its result to the coordinator
+        for result in [
+            "server-iterations-sim.json",
+            "iter1-server-iterations-sim.json",
+        ] {
+            let result = results_dir.path().join("sim/sim").join(result);
+            let result = std::fs::read_to_string(result).unwrap();
+            assert_eq!(result, netbench::SIM_RESULT);
+        }
+        assert_eq!(coord.results_collected(), 2);
+    }
+
+    // Run a server Coordinator and Worker to Done over a network which injects
+    // `faults` into the Coordinator's msgs. The Coordinator hangs up once it is Done
+    // and the Worker's result is returned.
+    async fn run_till_done_with_faults(
+        id: &str,
+        faults: Faults,
+    ) -> (Russula<server::WorkerProtocol>, RussulaResult<()>) {
+        let network = Network::Memory(MemoryNetwork::new().with_coord_faults(faults));
+        let worker_addr = SocketAddr::from_str("127.0.0.1:9051").unwrap();
+        let worker = RussulaBuilder::new(
+            BTreeSet::from_iter([worker_addr]),
+            server::WorkerProtocol::new(id.to_string(), netbench::ServerContext::testing()),
+            POLL_DELAY_DURATION,
+        )
+        .with_reconnect_attempts(0)
+        .with_network(network.clone());
+        let worker = tokio::spawn(async move {
+            let mut worker = worker.build().await.unwrap();
+            let res = worker.run_till_done().await;
+            (worker, res)
+        });
+
+        let coord = RussulaBuilder::new(
+            BTreeSet::from_iter([worker_addr]),
+            server::CoordProtocol::new(),
+            POLL_DELAY_DURATION,
+        )
+        .with_network(network);
+        let mut coord = coord.build().await.unwrap();
+        coord.run_till_worker_running().await.unwrap();
+        coord.run_till_done().await.unwrap();
+        drop(coord);
+
+        worker.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn done_notification() {
+        // msgs sent before the Coordinator hangs up are still delivered
+        for (id, fault) in [
+            ("done-delay", Fault::Delay(Duration::from_millis(200))),
+            ("done-duplicate", Fault::Duplicate),
+        ] {
+            let faults = Faults::default().on_frame("\"state\":\"Done\"", fault, 1);
+            let (worker, res) = run_till_done_with_faults(id, faults).await;
+            res.unwrap();
+            assert!(worker.is_done_state());
+        }
+
+        // the Done msg is only sent once so losing it strands the Worker, which
+        // fails once the Coordinator hangs up
+        let faults = Faults::default().on_frame("\"state\":\"Done\"", Fault::Drop, 1);
+        let (worker, res) = run_till_done_with_faults("done-drop", faults).await;
+        match res {
+            Err(RussulaError::PeerFailures { failures }) => {
+                assert_eq!(failures.len(), 1);
+                assert!(failures[0].err.is_disconnect());
+            }
+            res => panic!("expected PeerFailures but found: {:?}", res),
+        }
+        assert!(!worker.instance_list[0].protocol.is_done_state());
+    }
+
     #[tokio::test]
     async fn netbench_server_protocol() {
-        env_logger::init();
+        let _ = env_logger::try_init();
+        let network = memory_network();
 
         let mut worker_addrs = Vec::new();
         let mut workers = Vec::new();
         macro_rules! worker {
             {$port:literal} => {
                 let sock = SocketAddr::from_str(&format!("127.0.0.1:{}", $port)).unwrap();
+                let network = network.clone();
                 let worker = tokio::spawn(async move {
                     let worker = RussulaBuilder::new(
                         BTreeSet::from_iter([sock]),
@@ -190,7 +1227,8 @@ mod tests {
                             netbench::ServerContext::testing(),
                         ),
                         POLL_DELAY_DURATION,
-                    );
+                    )
+                    .with_network(network);
                     let mut worker = worker.build().await.unwrap();
                     worker
                         .run_till_done()
@@ -217,7 +1255,8 @@ mod tests {
         let c1 = tokio::spawn(async move {
             let addr = BTreeSet::from_iter(worker_addrs);
             let protocol = server::CoordProtocol::new();
-            let coord = RussulaBuilder::new(addr, protocol, POLL_DELAY_DURATION * 2);
+            let coord =
+                RussulaBuilder::new(addr, protocol, POLL_DELAY_DURATION * 2).with_network(network);
             let mut coord = coord.build().await.unwrap();
             coord.run_till_ready().await.unwrap();
             coord
@@ -232,14 +1271,10 @@ mod tests {
         println!("\nSTEP 2 --------------- : poll next coord step");
         {
             coord.run_till_worker_running().await.unwrap();
-            let simulate_run_time = Duration::from_secs(5);
-            tokio::time::sleep(simulate_run_time).await;
         }
 
         println!("\nSTEP 3 --------------- : wait till done");
-        while coord.poll_done().await.unwrap().is_pending() {
-            println!("\npoll state: Done");
-        }
+        coord.run_till_done().await.unwrap();
 
         println!("\nSTEP 20 --------------- : confirm worker done");
         {
@@ -252,13 +1287,15 @@ mod tests {
 
     #[tokio::test]
     async fn netbench_client_protocol() {
-        env_logger::init();
+        let _ = env_logger::try_init();
+        let network = memory_network();
         let mut worker_addrs = Vec::new();
         let mut workers = Vec::new();
 
         macro_rules! worker {
             {$port:literal} => {
                 let sock = SocketAddr::from_str(&format!("127.0.0.1:{}", $port)).unwrap();
+                let network = network.clone();
                 let worker = tokio::spawn(async move {
                     let worker = RussulaBuilder::new(
                         BTreeSet::from_iter([sock]),
@@ -267,7 +1304,8 @@ mod tests {
                             netbench::ClientContext::testing(),
                         ),
                         POLL_DELAY_DURATION,
-                    );
+                    )
+                    .with_network(network);
                     let mut worker = worker.build().await.unwrap();
                     worker
                         .run_till_done()
@@ -292,7 +1330,8 @@ mod tests {
             let addr = BTreeSet::from_iter(worker_addrs);
 
             let protocol = client::CoordProtocol::new();
-            let coord = RussulaBuilder::new(addr, protocol, POLL_DELAY_DURATION);
+            let coord =
+                RussulaBuilder::new(addr, protocol, POLL_DELAY_DURATION).with_network(network);
             let mut coord = coord.build().await.unwrap();
             coord.run_till_ready().await.unwrap();
             coord
@@ -310,9 +1349,7 @@ mod tests {
         }
 
         println!("\nSTEP 3 --------------- : wait till done");
-        while coord.poll_done().await.unwrap().is_pending() {
-            println!("\npoll state: Done");
-        }
+        coord.run_till_done().await.unwrap();
 
         println!("\nclient-STEP 20 --------------- : confirm worker done");
         {
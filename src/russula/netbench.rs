@@ -1,19 +1,41 @@
 // Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use std::{net::SocketAddr, path::PathBuf};
+use crate::russula::executor::{DryRun, Executor, LocalProcess, Simulated};
+pub use crate::russula::process::{CoordPayload, RunParams, WorkerPayload};
+// clippy complains about unused import since its used by the russula_cli bin
+#[allow(unused_imports)]
+pub use manifest::RunManifest;
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 use structopt::StructOpt;
 
 mod client_coord;
 mod client_worker;
+mod manifest;
 mod server_coord;
 mod server_worker;
 
 #[derive(StructOpt, Debug, Clone)]
 pub struct ClientContext {
-    /// Run a test program instead of the Netbench process.
+    /// Print the Netbench command which would be run instead of running it.
     #[structopt(long)]
-    testing: bool,
+    dry_run: bool,
+
+    /// Simulate the Netbench process instead of running it. The simulated
+    /// process exits after the duration, if set, or runs until it's killed.
+    #[structopt(long)]
+    simulate: Option<Option<humantime::Duration>>,
+
+    /// Runs the Netbench process in place of the one selected by `dry_run` or
+    /// `simulate`.
+    #[structopt(skip)]
+    executor: Option<Arc<dyn Executor>>,
 
     /// The path to the netbench utility and scenario file.
     #[structopt(long, default_value = "/home/ec2-user/bin")]
@@ -29,16 +51,36 @@ pub struct ClientContext {
     #[structopt(long, default_value = "request_response.json")]
     scenario: String,
 
+    /// Directory which the Netbench output and logs are written to. Defaults to
+    /// the working directory.
+    #[structopt(long)]
+    output_dir: Option<PathBuf>,
+
     /// List of Netbench Server the client should connect to.
     #[structopt(long)]
     netbench_servers: Vec<SocketAddr>,
+
+    /// Kill the Netbench process and fail the run if it is still running after
+    /// this duration.
+    #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+    max_run_duration: Option<Duration>,
 }
 
 #[derive(StructOpt, Debug, Clone)]
 pub struct ServerContext {
-    /// Run a test program instead of the Netbench process.
+    /// Print the Netbench command which would be run instead of running it.
     #[structopt(long)]
-    testing: bool,
+    dry_run: bool,
+
+    /// Simulate the Netbench process instead of running it. The simulated
+    /// process exits after the duration, if set, or runs until it's killed.
+    #[structopt(long)]
+    simulate: Option<Option<humantime::Duration>>,
+
+    /// Runs the Netbench process in place of the one selected by `dry_run` or
+    /// `simulate`.
+    #[structopt(skip)]
+    executor: Option<Arc<dyn Executor>>,
 
     /// The path to the netbench utility and scenario file.
     #[structopt(long, default_value = "/home/ec2-user/bin")]
@@ -54,128 +96,572 @@ pub struct ServerContext {
     #[structopt(long, default_value = "request_response.json")]
     scenario: String,
 
+    /// Directory which the Netbench output and logs are written to. Defaults to
+    /// the working directory.
+    #[structopt(long)]
+    output_dir: Option<PathBuf>,
+
     /// The port which the Netbench Server process should accept connections.
     #[structopt(long, default_value = "4433")]
     netbench_port: u16,
+
+    /// Kill the Netbench process and fail the run if it is still running after
+    /// this duration.
+    #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+    max_run_duration: Option<Duration>,
+}
+
+/// Parameters of a Netbench run which the Coordinator sends along with RunWorker.
+///
+/// Parameters which aren't set default to the values the Worker was launched
+/// with, so that a single Worker can run several drivers in sequence.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NetbenchRun {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scenario: Option<String>,
+    /// The port which the Netbench Server accepts connections on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub netbench_port: Option<u16>,
+    /// Env vars and args passed to the driver, such as `TRACE=stdio`
+    #[serde(default)]
+    pub params: RunParams,
+}
+
+fn trim_driver_name(driver: &str) -> String {
+    driver
+        .trim_start_matches("s2n-netbench-driver-")
+        .trim_start_matches("netbench-driver-")
+        .trim_end_matches(".json")
+        .to_owned()
+}
+
+// Path of a result file in the results tree read by `s2n-netbench report-tree`,
+// laid out as <scenario>/<driver>/<output>.json
+fn result_name(scenario: &str, driver: &str, output: &str) -> PathBuf {
+    let scenario = Path::new(scenario)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let output = format!("{}.json", output);
+    [scenario, trim_driver_name(driver), output]
+        .into_iter()
+        .filter(|component| !component.is_empty())
+        .collect()
+}
+
+// Path of an output file of the Netbench process
+fn output_path(output_dir: &Option<PathBuf>, file_name: String) -> PathBuf {
+    match output_dir {
+        Some(output_dir) => output_dir.join(file_name),
+        None => file_name.into(),
+    }
+}
+
+// The executor selected by a Worker's options
+fn executor(
+    executor: &Option<Arc<dyn Executor>>,
+    dry_run: bool,
+    simulate: Option<Option<humantime::Duration>>,
+) -> Arc<dyn Executor> {
+    match (executor, dry_run, simulate) {
+        (Some(executor), _, _) => executor.clone(),
+        (None, true, _) => Arc::new(DryRun::default()),
+        (None, false, Some(duration)) => {
+            let mut simulated = Simulated::new().with_output(SIM_RESULT);
+            if let Some(duration) = duration {
+                simulated = simulated.with_duration(duration.into());
+            }
+            Arc::new(simulated)
+        }
+        (None, false, None) => Arc::new(LocalProcess),
+    }
 }
 
+// The result written by the simulated Netbench process
+pub const SIM_RESULT: &str = r#"{"driver": "sim"}"#;
+
 impl ServerContext {
-    #[cfg(test)]
-    pub fn testing() -> Self {
+    /// Runs `driver` from `netbench_path`, accepting connections on `netbench_port`
+    pub fn new(
+        netbench_path: PathBuf,
+        driver: String,
+        scenario: String,
+        netbench_port: u16,
+    ) -> Self {
         ServerContext {
-            netbench_path: "".into(),
-            driver: "".to_string(),
-            scenario: "".to_string(),
-            testing: true,
-            netbench_port: 4433,
+            dry_run: false,
+            simulate: None,
+            executor: None,
+            netbench_path,
+            driver,
+            scenario,
+            output_dir: None,
+            netbench_port,
+            max_run_duration: None,
         }
     }
 
-    pub fn trim_driver_name(&self) -> String {
-        self.driver
-            .trim_start_matches("s2n-netbench-driver-")
-            .trim_start_matches("netbench-driver-")
-            .trim_end_matches(".json")
-            .to_owned()
+    pub fn with_output_dir(mut self, output_dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = Some(output_dir.into());
+        self
     }
-}
 
-impl ClientContext {
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Simulate the Netbench process, which exits after `duration` or runs
+    /// until it's killed
+    pub fn with_simulate(mut self, duration: Option<Duration>) -> Self {
+        self.simulate = Some(duration.map(Into::into));
+        self
+    }
+
+    /// Simulates a Netbench server which runs until it's killed
     #[cfg(test)]
     pub fn testing() -> Self {
+        let mut ctx =
+            ServerContext::new("".into(), "sim".to_string(), "sim.json".to_string(), 4433)
+                .with_output_dir("target");
+        ctx.executor = Some(Arc::new(Simulated::new().with_output(SIM_RESULT)));
+        ctx
+    }
+}
+
+impl ClientContext {
+    /// Runs `driver` from `netbench_path` against the Netbench servers at `netbench_servers`
+    pub fn new(
+        netbench_path: PathBuf,
+        driver: String,
+        scenario: String,
+        netbench_servers: Vec<SocketAddr>,
+    ) -> Self {
         ClientContext {
-            netbench_servers: vec![],
-            netbench_path: "".into(),
-            driver: "".to_string(),
-            scenario: "".to_string(),
-            testing: true,
+            dry_run: false,
+            simulate: None,
+            executor: None,
+            netbench_path,
+            driver,
+            scenario,
+            output_dir: None,
+            netbench_servers,
+            max_run_duration: None,
         }
     }
 
-    pub fn trim_driver_name(&self) -> String {
-        self.driver
-            .trim_start_matches("s2n-netbench-driver-")
-            .trim_start_matches("netbench-driver-")
-            .trim_end_matches(".json")
-            .to_owned()
+    pub fn with_output_dir(mut self, output_dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = Some(output_dir.into());
+        self
+    }
+
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Simulate the Netbench process, which exits after `duration` or runs
+    /// until it's killed
+    pub fn with_simulate(mut self, duration: Option<Duration>) -> Self {
+        self.simulate = Some(duration.map(Into::into));
+        self
+    }
+
+    /// Simulates a Netbench client which completes after a second
+    #[cfg(test)]
+    pub fn testing() -> Self {
+        ClientContext::testing_with_duration(Duration::from_secs(1))
+    }
+
+    /// Simulates a Netbench client which completes after `duration`
+    #[cfg(test)]
+    pub fn testing_with_duration(duration: Duration) -> Self {
+        let executor = Simulated::new()
+            .with_duration(duration)
+            .with_output(SIM_RESULT);
+        let mut ctx =
+            ClientContext::new("".into(), "sim".to_string(), "sim.json".to_string(), vec![])
+                .with_output_dir("target");
+        ctx.executor = Some(Arc::new(executor));
+        ctx
     }
 }
 
-// CheckWorker   --------->  WaitCoordInit
-//                              |
-//                              v
-// CheckWorker   <---------  Ready
+// CheckWorker     --------->  WaitCoordInit
+//                                |
+//                                v
+// CheckWorker     <---------  Ready
 //    |
 //    v
 // Ready
 //    | (user)
 //    v
-// RunWorker     --------->  Ready
-//                              |
-//                              v
-//                           Run
-//                              | (self)
-//                              v
-// RunWorker     <---------  RunningAwaitKill
+// RunWorker       --------->  Ready
+//                                |
+//                                v
+//                             Run
+//                                | (self)
+//                                v
+// RunWorker       <---------  RunningAwaitKill
 //    |
 //    v
 // WorkersRunning
 //    | (user)
 //    v
-// KillWorker    --------->  RunningAwaitKill
-//                              |
-//                              v
-//                           Killing
-//                              | (self)
-//                              v
-// WorkerKilled  <---------  Stopped
+// KillWorker      --------->  RunningAwaitKill
+//                                |
+//                                v
+//                             Killing
+//                                | (self)
+//                                v
+// KillWorker      <---------  Stopped
+//    |
+//    v
+// WorkerKilled
+//    | (user)
+//    v
+// Done            --------->  Stopped
+//                                |
+//                                v
+//                             Done
+//
+// WorkerKilled
+//    | (user)
+//    v
+// RunWorker
+//
+// RunWorker       <---------  Stopped
 //    |
 //    v
-// Done          --------->  Stopped
-//                              |
-//                              v
-//                           Done
+// WorkerKilled
+//
+// CheckWorker
+//    | (user)
+//    v
+// Done
+//
+// Ready
+//    | (user)
+//    v
+// Done
+//
+//                             RunningAwaitKill
+//                                | (self)
+//                                v
+//                             TimedOut
+//
+//                             Killing
+//                                | (self)
+//                                v
+//                             TimedOut
+//                                | (self)
+//                                v
+//                             Stopped
+//
+//                             Run
+//                                | (self)
+//                                v
+//                             Stopped
+//
+// RunWorker       --------->  Stopped
+//                                |
+//                                v
+//                             Run
+//
+// Done            --------->  WaitCoordInit
+//                                |
+//                                v
+//                             Done
+//
+// Done            --------->  Ready
+//                                |
+//                                v
+//                             Done
 pub mod server {
-    pub use super::server_coord::CoordProtocol;
+    #[cfg(test)]
+    use crate::russula::transition_table::{Edge, TransitionTable};
+
+    pub use super::server_coord::{CoordProtocol, CoordState};
     // clippy complains about unused import since its used by the russula_cli bin
     #[allow(unused_imports)]
-    pub use super::server_worker::WorkerProtocol;
+    pub use super::server_worker::{WorkerProtocol, WorkerState};
+
+    #[cfg(test)]
+    pub fn transition_table() -> TransitionTable<CoordState, WorkerState> {
+        // the pids carried by the Worker States are only known at runtime
+        TransitionTable::new(
+            vec![
+                Edge::next(CoordState::CheckWorker, CoordState::Ready),
+                Edge::next(CoordState::Ready, CoordState::RunWorker),
+                Edge::next(CoordState::RunWorker, CoordState::WorkersRunning),
+                Edge::next(CoordState::WorkersRunning, CoordState::KillWorker),
+                Edge::next(CoordState::KillWorker, CoordState::WorkerKilled),
+                Edge::next(CoordState::WorkerKilled, CoordState::Done),
+                // another iteration
+                Edge::user_driven(CoordState::WorkerKilled, CoordState::RunWorker),
+                // the Worker's process failed to start
+                Edge::await_next(
+                    CoordState::RunWorker,
+                    WorkerState::Stopped,
+                    CoordState::WorkerKilled,
+                ),
+                // the run was cancelled before the Workers started
+                Edge::user_driven(CoordState::CheckWorker, CoordState::Done),
+                Edge::user_driven(CoordState::Ready, CoordState::Done),
+            ],
+            vec![
+                Edge::next(WorkerState::WaitCoordInit, WorkerState::Ready),
+                Edge::next(WorkerState::Ready, WorkerState::Run),
+                Edge::next(WorkerState::Run, WorkerState::RunningAwaitKill(0)),
+                Edge::next(WorkerState::RunningAwaitKill(0), WorkerState::Killing(0)),
+                Edge::next(WorkerState::Killing(0), WorkerState::Stopped),
+                Edge::next(WorkerState::Stopped, WorkerState::Done),
+                // the process ran longer than the max run duration
+                Edge::self_driven(WorkerState::RunningAwaitKill(0), WorkerState::TimedOut(0)),
+                Edge::self_driven(WorkerState::Killing(0), WorkerState::TimedOut(0)),
+                Edge::next(WorkerState::TimedOut(0), WorkerState::Stopped),
+                // the process failed to start
+                Edge::self_driven(WorkerState::Run, WorkerState::Stopped),
+                // the Coordinator started another iteration
+                Edge::await_next(
+                    WorkerState::Stopped,
+                    CoordState::RunWorker,
+                    WorkerState::Run,
+                ),
+                // the Coordinator cancelled the run before it started
+                Edge::await_next(
+                    WorkerState::WaitCoordInit,
+                    CoordState::Done,
+                    WorkerState::Done,
+                ),
+                Edge::await_next(WorkerState::Ready, CoordState::Done, WorkerState::Done),
+            ],
+        )
+    }
 }
 
-// CheckWorker   --------->  WaitCoordInit
-//                              |
-//                              v
-// CheckWorker   <---------  Ready
+// CheckWorker     --------->  WaitCoordInit
+//                                |
+//                                v
+// CheckWorker     <---------  Ready
 //    |
 //    v
 // Ready
 //    | (user)
 //    v
-// RunWorker     --------->  Ready
-//                              |
-//                              v
-//                           Run
-//                              | (self)
-//                              v
-// RunWorker     <---------  Running
+// RunWorker       --------->  Ready
+//                                |
+//                                v
+//                             Run
+//                                | (self)
+//                                v
+// RunWorker       <---------  Running
+//    |
+//    v
+// WorkersRunning  --------->  Running
+//                                |
+//                                v
+//                             RunningAwaitComplete
+//                                | (self)
+//                                v
+// WorkersRunning  <---------  Stopped
 //    |
 //    v
-// WorkersRunning ---------> Running
-//                              |
-//                              v
-//                           RunningAwaitComplete
-//                              | (self)
-//                              v
-// WorkersRunning <---------  Stopped
+// WorkersStopped
+//    | (user)
+//    v
+// Done            --------->  Stopped
+//                                |
+//                                v
+//                             Done
+//
+// WorkersStopped
+//    | (user)
+//    v
+// RunWorker
+//
+// RunWorker       <---------  Stopped
 //    |
 //    v
-// Done          --------->  Stopped
-//                              |
-//                              v
-//                           Done
+// WorkersStopped
+//
+// CheckWorker
+//    | (user)
+//    v
+// Done
+//
+// Ready
+//    | (user)
+//    v
+// Done
+//
+//                             Running
+//                                | (self)
+//                                v
+//                             TimedOut
+//
+//                             RunningAwaitComplete
+//                                | (self)
+//                                v
+//                             TimedOut
+//                                | (self)
+//                                v
+//                             Stopped
+//
+//                             Run
+//                                | (self)
+//                                v
+//                             Stopped
+//
+// RunWorker       --------->  Stopped
+//                                |
+//                                v
+//                             Run
+//
+// Done            --------->  WaitCoordInit
+//                                |
+//                                v
+//                             Done
+//
+// Done            --------->  Ready
+//                                |
+//                                v
+//                             Done
 pub mod client {
+    #[cfg(test)]
+    use crate::russula::transition_table::{Edge, TransitionTable};
+
     pub use super::client_coord::{CoordProtocol, CoordState};
     // clippy complains about unused import since its used by the russula_cli bin
     #[allow(unused_imports)]
     pub use super::client_worker::{WorkerProtocol, WorkerState};
+
+    #[cfg(test)]
+    pub fn transition_table() -> TransitionTable<CoordState, WorkerState> {
+        // the pids carried by the Worker States are only known at runtime
+        TransitionTable::new(
+            vec![
+                Edge::next(CoordState::CheckWorker, CoordState::Ready),
+                Edge::next(CoordState::Ready, CoordState::RunWorker),
+                Edge::next(CoordState::RunWorker, CoordState::WorkersRunning),
+                Edge::next(CoordState::WorkersRunning, CoordState::WorkersStopped),
+                Edge::next(CoordState::WorkersStopped, CoordState::Done),
+                // another iteration
+                Edge::user_driven(CoordState::WorkersStopped, CoordState::RunWorker),
+                // the Worker's process failed to start
+                Edge::await_next(
+                    CoordState::RunWorker,
+                    WorkerState::Stopped,
+                    CoordState::WorkersStopped,
+                ),
+                // the run was cancelled before the Workers started
+                Edge::user_driven(CoordState::CheckWorker, CoordState::Done),
+                Edge::user_driven(CoordState::Ready, CoordState::Done),
+            ],
+            vec![
+                Edge::next(WorkerState::WaitCoordInit, WorkerState::Ready),
+                Edge::next(WorkerState::Ready, WorkerState::Run),
+                Edge::next(WorkerState::Run, WorkerState::Running(0)),
+                Edge::next(
+                    WorkerState::Running(0),
+                    WorkerState::RunningAwaitComplete(0),
+                ),
+                Edge::next(WorkerState::RunningAwaitComplete(0), WorkerState::Stopped),
+                Edge::next(WorkerState::Stopped, WorkerState::Done),
+                // the process ran longer than the max run duration
+                Edge::self_driven(WorkerState::Running(0), WorkerState::TimedOut(0)),
+                Edge::self_driven(
+                    WorkerState::RunningAwaitComplete(0),
+                    WorkerState::TimedOut(0),
+                ),
+                Edge::next(WorkerState::TimedOut(0), WorkerState::Stopped),
+                // the process failed to start
+                Edge::self_driven(WorkerState::Run, WorkerState::Stopped),
+                // the Coordinator started another iteration
+                Edge::await_next(
+                    WorkerState::Stopped,
+                    CoordState::RunWorker,
+                    WorkerState::Run,
+                ),
+                // the Coordinator cancelled the run before it started
+                Edge::await_next(
+                    WorkerState::WaitCoordInit,
+                    CoordState::Done,
+                    WorkerState::Done,
+                ),
+                Edge::await_next(WorkerState::Ready, CoordState::Done, WorkerState::Done),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The diagrams above are generated by `TransitionTable::render`
+    fn as_comment(diagram: &str) -> String {
+        diagram
+            .lines()
+            .map(|line| format!("// {}", line).trim_end().to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // The whole comment directly above `item`
+    fn comment_above(item: &str) -> String {
+        let src = include_str!("netbench.rs");
+        let item = src.find(item).unwrap();
+        let mut comment: Vec<_> = src[..item]
+            .lines()
+            .rev()
+            .take_while(|line| line.starts_with("//"))
+            .collect();
+        comment.reverse();
+        comment.join("\n")
+    }
+
+    #[test]
+    fn simulate_duration_is_optional() {
+        let ctx = ServerContext::from_iter_safe(["server", "--driver", "d", "--simulate"]).unwrap();
+        assert_eq!(ctx.simulate, Some(None));
+
+        let ctx =
+            ClientContext::from_iter_safe(["client", "--driver", "d", "--simulate", "2s"]).unwrap();
+        assert_eq!(
+            ctx.simulate.map(|d| d.map(Into::into)),
+            Some(Some(Duration::from_secs(2)))
+        );
+
+        let ctx = ClientContext::from_iter_safe(["client", "--driver", "d"]).unwrap();
+        assert_eq!(ctx.simulate, None);
+    }
+
+    #[test]
+    fn server_transition_table() {
+        let table = server::transition_table();
+        assert_eq!(table.validate(), Ok(()));
+        let diagram = as_comment(&table.render());
+        assert_eq!(
+            comment_above("\npub mod server {"),
+            diagram,
+            "\n{}",
+            diagram
+        );
+    }
+
+    #[test]
+    fn client_transition_table() {
+        let table = client::transition_table();
+        assert_eq!(table.validate(), Ok(()));
+        let diagram = as_comment(&table.render());
+        assert_eq!(
+            comment_above("\npub mod client {"),
+            diagram,
+            "\n{}",
+            diagram
+        );
+    }
 }
@@ -2,17 +2,21 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use crate::russula::{
+    clock,
     error::{RussulaError, RussulaResult},
     event::{EventRecorder, EventType},
-    netbench::client::WorkerState,
-    network_utils::Msg,
+    netbench::{client::WorkerState, CoordPayload, WorkerPayload},
+    network_utils::{FramedStream, Msg},
+    process::ProcessExit,
     protocol::{notify_peer, Protocol},
+    results::ResultCollector,
+    states::Envelope,
+    transport::{Network, Transport},
     StateApi, TransitionStep,
 };
 use core::fmt::Debug;
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
-use tokio::net::TcpStream;
+use std::{net::SocketAddr, path::PathBuf, time::SystemTime};
 use tracing::{debug, info};
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
@@ -21,6 +25,7 @@ pub enum CoordState {
     Ready,
     RunWorker,
     WorkersRunning,
+    WorkersStopped,
     Done,
 }
 
@@ -29,6 +34,20 @@ pub struct CoordProtocol {
     state: CoordState,
     peer_state: WorkerState,
     event_recorder: EventRecorder,
+    // Wall-clock time at which the Workers should start
+    start_at_us: Option<u64>,
+    // How the Worker process stopped. Keeps the first failure if there are
+    // multiple iterations.
+    peer_exit: Option<ProcessExit>,
+    iteration: u32,
+    // The iteration of the Worker's latest State
+    peer_iteration: u32,
+    // Run the Workers again once they stop
+    next_iteration: bool,
+    // Parameters of the next run sent to the Workers
+    run: Option<serde_json::Value>,
+    // Writes the result files streamed by the Worker
+    results: Option<ResultCollector>,
 }
 
 impl CoordProtocol {
@@ -37,8 +56,31 @@ impl CoordProtocol {
             state: CoordState::CheckWorker,
             peer_state: WorkerState::WaitCoordInit,
             event_recorder: EventRecorder::default(),
+            start_at_us: None,
+            peer_exit: None,
+            iteration: 0,
+            peer_iteration: 0,
+            next_iteration: false,
+            run: None,
+            results: None,
         }
     }
+
+    // Move back to RunWorker so that the Workers run their process again
+    fn start_next_iteration(&mut self) {
+        self.next_iteration = false;
+        self.iteration += 1;
+        info!("{} starting iteration {}", self.name(), self.iteration);
+        self.state = CoordState::RunWorker;
+    }
+
+    // The Worker stopped this iteration before it was seen running, such as when
+    // its process failed to start
+    fn worker_stopped_early(&self) -> bool {
+        matches!(self.state, CoordState::RunWorker)
+            && matches!(self.peer_state, WorkerState::Stopped)
+            && self.peer_iteration == self.iteration
+    }
 }
 
 impl Protocol for CoordProtocol {
@@ -47,20 +89,90 @@ impl Protocol for CoordProtocol {
         format!("client-c-{}", 0)
     }
 
-    async fn connect(&self, addr: &SocketAddr) -> RussulaResult<TcpStream> {
+    async fn connect(
+        &mut self,
+        network: &Network,
+        addr: &SocketAddr,
+    ) -> RussulaResult<Box<dyn Transport>> {
         info!("--- Coordinator: attempt to connect on: {}", addr);
+        network.connect(addr).await
+    }
 
-        let connect = TcpStream::connect(addr).await.map_err(RussulaError::from)?;
-        Ok(connect)
+    fn protocol_name(&self) -> &'static str {
+        "netbench-client"
     }
 
     fn update_peer_state(&mut self, msg: Msg) -> RussulaResult<()> {
-        self.peer_state = WorkerState::from_msg(msg)?;
+        let envelope = Envelope::<WorkerState>::from_msg(&msg)?;
+        if let Some(payload) = envelope.payload_as::<WorkerPayload>()? {
+            self.peer_iteration = payload.iteration;
+            if let Some(clock_offset) = payload.clock_offset {
+                self.event_recorder.record_clock_offset(clock_offset);
+            }
+            if let Some(start_skew_us) = self
+                .start_at_us
+                .and_then(|start_at_us| payload.start_skew_us(start_at_us))
+            {
+                self.event_recorder.record_start_skew(start_skew_us);
+            }
+            if let Some(params) = payload.params {
+                self.event_recorder.record_run_params(params);
+            }
+            if let (Some(chunk), Some(results)) = (payload.result, self.results.as_mut()) {
+                results.receive(chunk, &mut self.event_recorder)?;
+            }
+            if let Some(exit) = payload.exit {
+                self.event_recorder.record_process_exit(exit.clone());
+                if self.peer_exit.as_ref().map_or(true, ProcessExit::success) {
+                    self.peer_exit = Some(exit);
+                }
+            }
+        }
+        self.peer_state = envelope.state;
         debug!("{} ... peer_state {:?}", self.name(), self.peer_state);
 
         Ok(())
     }
 
+    fn set_start_at(&mut self, start_at: SystemTime) {
+        self.start_at_us = Some(clock::to_us(start_at));
+    }
+
+    fn set_run(&mut self, run: serde_json::Value) {
+        self.run = Some(run);
+    }
+
+    fn set_results_dir(&mut self, dir: PathBuf) {
+        self.results = Some(ResultCollector::new(dir));
+    }
+
+    fn results_written(&self) -> usize {
+        self.results.as_ref().map_or(0, ResultCollector::written)
+    }
+
+    fn peer_failure(&self) -> Option<RussulaError> {
+        self.peer_exit
+            .as_ref()
+            .filter(|exit| !exit.success())
+            .map(|exit| RussulaError::ProcessFailed {
+                dbg: format!("{} worker process failed. {}", self.protocol_name(), exit),
+            })
+    }
+
+    fn workers_started(&self) -> bool {
+        !matches!(self.state, CoordState::CheckWorker | CoordState::Ready)
+    }
+
+    fn payload(&self) -> Option<serde_json::Value> {
+        let payload = CoordPayload {
+            start_at_us: self.start_at_us,
+            iteration: self.iteration,
+            run: self.run.clone(),
+            collect_results: self.results.is_some(),
+        };
+        Some(serde_json::to_value(payload).unwrap())
+    }
+
     fn state(&self) -> &Self::State {
         &self.state
     }
@@ -81,23 +193,41 @@ impl Protocol for CoordProtocol {
         CoordState::WorkersRunning
     }
 
-    async fn run(&mut self, stream: &TcpStream) -> RussulaResult<Option<Msg>> {
+    fn worker_stopped_state(&self) -> Self::State {
+        CoordState::WorkersStopped
+    }
+
+    fn next_iteration(&mut self) {
+        self.next_iteration = true;
+    }
+
+    async fn run(&mut self, stream: &mut FramedStream) -> RussulaResult<Option<Msg>> {
         match self.state_mut() {
-            CoordState::CheckWorker => {
-                notify_peer!(self, stream);
-                self.await_next_msg(stream).await
-            }
+            CoordState::CheckWorker => self.await_next_msg(stream).await,
             CoordState::Ready => {
                 self.transition_self_or_user_driven(stream).await?;
                 Ok(None)
             }
             CoordState::RunWorker => {
-                notify_peer!(self, stream);
-                self.await_next_msg(stream).await
+                if let Some(msg) = self.await_next_msg(stream).await? {
+                    self.update_peer_state(msg)?;
+                }
+                if self.worker_stopped_early() {
+                    info!("{} worker stopped before it was running", self.name());
+                    self.state = CoordState::WorkersStopped;
+                    notify_peer!(self, stream);
+                }
+                Ok(None)
             }
-            CoordState::WorkersRunning => {
-                notify_peer!(self, stream);
-                self.await_next_msg(stream).await
+            CoordState::WorkersRunning => self.await_next_msg(stream).await,
+            CoordState::WorkersStopped => {
+                if self.next_iteration {
+                    self.start_next_iteration();
+                    notify_peer!(self, stream);
+                } else {
+                    self.transition_self_or_user_driven(stream).await?;
+                }
+                Ok(None)
             }
             CoordState::Done => {
                 notify_peer!(self, stream);
@@ -112,32 +242,81 @@ impl Protocol for CoordProtocol {
 }
 
 impl StateApi for CoordState {
-    fn transition_step(&self) -> TransitionStep {
+    type PeerState = WorkerState;
+
+    fn transition_step(&self) -> TransitionStep<WorkerState> {
         match self {
-            CoordState::CheckWorker => TransitionStep::AwaitNext(WorkerState::Ready.as_bytes()),
+            CoordState::CheckWorker => TransitionStep::AwaitNext(WorkerState::Ready),
             CoordState::Ready => TransitionStep::UserDriven,
-            CoordState::RunWorker => TransitionStep::AwaitNext(WorkerState::Running(0).as_bytes()),
-            CoordState::WorkersRunning => {
-                TransitionStep::AwaitNext(WorkerState::Stopped.as_bytes())
-            }
+            CoordState::RunWorker => TransitionStep::AwaitNext(WorkerState::Running(0)),
+            CoordState::WorkersRunning => TransitionStep::AwaitNext(WorkerState::Stopped),
+            CoordState::WorkersStopped => TransitionStep::UserDriven,
             CoordState::Done => TransitionStep::Finished,
         }
     }
 
-    fn next_state(&self) -> Self {
-        match self {
+    fn next_state(&self) -> Option<Self> {
+        let next = match self {
             CoordState::CheckWorker => CoordState::Ready,
             CoordState::Ready => CoordState::RunWorker,
             CoordState::RunWorker => CoordState::WorkersRunning,
-            CoordState::WorkersRunning => CoordState::Done,
-            CoordState::Done => CoordState::Done,
-        }
+            CoordState::WorkersRunning => CoordState::WorkersStopped,
+            CoordState::WorkersStopped => CoordState::Done,
+            CoordState::Done => return None,
+        };
+        Some(next)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
 
-    #[tokio::test]
-    async fn netbench_state() {}
+    fn stopped(exit: ProcessExit) -> Msg {
+        let payload = WorkerPayload {
+            exit: Some(exit),
+            ..Default::default()
+        };
+        Envelope {
+            seq: 0,
+            sender: "client-0".to_string(),
+            state: WorkerState::Stopped,
+            payload: Some(serde_json::to_value(payload).unwrap()),
+        }
+        .as_msg()
+    }
+
+    #[test]
+    fn worker_process_failed() {
+        let mut coord = CoordProtocol::new();
+        coord
+            .update_peer_state(stopped(ProcessExit {
+                code: Some(0),
+                signal: None,
+                killed: false,
+                timed_out: false,
+                stderr_tail: None,
+                spawn_error: None,
+                wait_error: None,
+            }))
+            .unwrap();
+        assert!(coord.peer_failure().is_none());
+
+        // a crashed driver is reported as a failure
+        coord
+            .update_peer_state(stopped(ProcessExit {
+                code: Some(101),
+                signal: None,
+                killed: false,
+                timed_out: false,
+                stderr_tail: Some("panicked".to_string()),
+                spawn_error: None,
+                wait_error: None,
+            }))
+            .unwrap();
+        assert!(matches!(
+            coord.peer_failure(),
+            Some(RussulaError::ProcessFailed { .. })
+        ));
+    }
 }
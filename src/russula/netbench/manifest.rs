@@ -0,0 +1,185 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::russula::netbench::{NetbenchRun, RunParams};
+use serde::Deserialize;
+use std::{io, net::SocketAddr, path::Path};
+
+/// A Netbench session run against Workers which are already running, such as on
+/// bare metal hosts or an existing fleet, read from a JSON file:
+///
+/// ```json
+/// {
+///   "servers": ["10.0.0.1:7000"],
+///   "clients": ["10.0.0.2:7000", "10.0.0.3:7000"],
+///   "scenario": "request_response_incast.json",
+///   "drivers": [
+///     {
+///       "server": "s2n-netbench-driver-server-s2n-quic",
+///       "client": "s2n-netbench-driver-client-s2n-quic"
+///     }
+///   ],
+///   "iterations": 2
+/// }
+/// ```
+///
+/// The client Workers are launched with the addresses of the Netbench servers,
+/// since they aren't part of a run.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RunManifest {
+    /// The Russula addresses of the server Workers
+    pub servers: Vec<SocketAddr>,
+    /// The Russula addresses of the client Workers
+    pub clients: Vec<SocketAddr>,
+    /// The scenario file, relative to the netbench path of the Workers
+    pub scenario: String,
+    /// Driver pairs which are run in order, each for `iterations` runs
+    pub drivers: Vec<DriverPair>,
+    #[serde(default = "default_iterations")]
+    pub iterations: u32,
+    /// The port which the Netbench servers accept connections on. Defaults to the
+    /// port the server Workers were launched with.
+    #[serde(default)]
+    pub netbench_port: Option<u16>,
+    /// Env vars and args passed to every driver
+    #[serde(default)]
+    pub params: RunParams,
+}
+
+/// A server driver and the client driver run against it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DriverPair {
+    pub server: String,
+    pub client: String,
+}
+
+fn default_iterations() -> u32 {
+    1
+}
+
+impl RunManifest {
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let manifest: RunManifest = serde_json::from_reader(file)?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        let invalid = |msg: &str| Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        if self.servers.is_empty() || self.clients.is_empty() {
+            return invalid("the manifest needs at least one server and one client");
+        }
+        if self.drivers.is_empty() {
+            return invalid("the manifest needs at least one driver pair");
+        }
+        if self.iterations == 0 {
+            return invalid("iterations must be at least 1");
+        }
+        Ok(())
+    }
+
+    /// The server and client runs of the session in order
+    pub fn runs(&self) -> Vec<(NetbenchRun, NetbenchRun)> {
+        let run = |driver: &str, netbench_port| NetbenchRun {
+            driver: Some(driver.to_owned()),
+            scenario: Some(self.scenario.clone()),
+            netbench_port,
+            params: self.params.clone(),
+        };
+        self.drivers
+            .iter()
+            .flat_map(|pair| {
+                let runs = (
+                    run(&pair.server, self.netbench_port),
+                    run(&pair.client, None),
+                );
+                std::iter::repeat(runs).take(self.iterations as usize)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs() {
+        let dir = tempdir::TempDir::new("manifest").unwrap();
+        let path = dir.path().join("manifest.json");
+        let manifest = r#"{
+            "servers": ["10.0.0.1:7000"],
+            "clients": ["10.0.0.2:7000", "10.0.0.3:7000"],
+            "scenario": "incast.json",
+            "drivers": [
+                { "server": "quic-server", "client": "quic-client" },
+                { "server": "tcp-server", "client": "tcp-client" }
+            ],
+            "iterations": 2,
+            "netbench_port": 4433,
+            "params": { "envs": { "TRACE": "stdio" } }
+        }"#;
+        std::fs::write(&path, manifest).unwrap();
+
+        let manifest = RunManifest::from_file(&path).unwrap();
+        assert_eq!(manifest.clients.len(), 2);
+        let runs = manifest.runs();
+        let drivers: Vec<_> = runs
+            .iter()
+            .map(|(server, client)| (server.driver.clone(), client.driver.clone()))
+            .collect();
+        let pair = |server: &str, client: &str| (Some(server.into()), Some(client.into()));
+        assert_eq!(
+            drivers,
+            [
+                pair("quic-server", "quic-client"),
+                pair("quic-server", "quic-client"),
+                pair("tcp-server", "tcp-client"),
+                pair("tcp-server", "tcp-client"),
+            ]
+        );
+
+        let (server, client) = &runs[0];
+        assert_eq!(server.scenario.as_deref(), Some("incast.json"));
+        assert_eq!(server.netbench_port, Some(4433));
+        assert_eq!(client.netbench_port, None);
+        assert_eq!(client.params.envs["TRACE"], "stdio");
+    }
+
+    #[test]
+    fn invalid() {
+        let dir = tempdir::TempDir::new("manifest").unwrap();
+        let path = dir.path().join("manifest.json");
+        let parse = |manifest: &str| {
+            std::fs::write(&path, manifest).unwrap();
+            RunManifest::from_file(&path).unwrap_err().kind()
+        };
+
+        let no_drivers = r#"{
+            "servers": ["10.0.0.1:7000"], "clients": ["10.0.0.2:7000"],
+            "scenario": "incast.json", "drivers": []
+        }"#;
+        assert_eq!(parse(no_drivers), io::ErrorKind::InvalidData);
+        let no_clients = r#"{
+            "servers": ["10.0.0.1:7000"], "clients": [],
+            "scenario": "incast.json", "drivers": [{ "server": "s", "client": "c" }]
+        }"#;
+        assert_eq!(parse(no_clients), io::ErrorKind::InvalidData);
+        // a typo in a field is reported rather than ignored
+        let typo = r#"{
+            "servers": ["10.0.0.1:7000"], "clients": ["10.0.0.2:7000"],
+            "scenario": "incast.json", "drivers": [{ "server": "s", "client": "c" }],
+            "iteration": 2
+        }"#;
+        assert_eq!(parse(typo), io::ErrorKind::InvalidData);
+        assert_eq!(
+            RunManifest::from_file(&dir.path().join("missing.json"))
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
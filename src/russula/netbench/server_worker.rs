@@ -1,220 +1,157 @@
 // Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use super::ServerContext;
+use super::{executor, output_path, result_name, trim_driver_name, NetbenchRun, ServerContext};
 use crate::russula::{
-    error::{RussulaError, RussulaResult},
-    event::{EventRecorder, EventType},
-    netbench::server_coord::CoordState,
-    network_utils::Msg,
-    protocol::{notify_peer, Protocol},
+    executor::Executor,
+    netbench::server::CoordState,
+    process::{ProcessAction, ProcessContext, ProcessSpec, ProcessState, ProcessWorkerProtocol},
     StateApi, TransitionStep,
 };
 use core::fmt::Debug;
 use serde::{Deserialize, Serialize};
-use std::{fs::File, net::SocketAddr, process::Command};
-use sysinfo::{Pid, PidExt, ProcessExt, SystemExt};
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{debug, info};
-
-// Only used when creating a state variant
-const PLACEHOLDER_PID: u32 = 1000;
+use std::sync::Arc;
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum WorkerState {
     WaitCoordInit,
     Ready,
     Run,
-    RunningAwaitKill(#[serde(skip)] u32),
-    Killing(#[serde(skip)] u32),
+    RunningAwaitKill(u32),
+    Killing(u32),
+    // The process ran longer than the max run duration and is being killed
+    TimedOut(u32),
     Stopped,
     Done,
 }
 
-#[derive(Clone, Debug)]
-pub struct WorkerProtocol {
-    id: String,
-    state: WorkerState,
-    peer_state: CoordState,
-    netbench_ctx: ServerContext,
-    event_recorder: EventRecorder,
-}
+/// Runs the Netbench server until the Coordinator asks the Worker to kill it
+pub type WorkerProtocol = ProcessWorkerProtocol<WorkerState, ServerContext>;
 
 impl WorkerProtocol {
     pub fn new(id: String, netbench_ctx: ServerContext) -> Self {
-        WorkerProtocol {
-            id,
-            state: WorkerState::WaitCoordInit,
-            peer_state: CoordState::CheckWorker,
-            netbench_ctx,
-            event_recorder: EventRecorder::default(),
-        }
+        let name = format!("server-{}", id);
+        ProcessWorkerProtocol::from_context(name, "netbench-server", netbench_ctx)
     }
 }
 
-impl Protocol for WorkerProtocol {
-    type State = WorkerState;
-
-    fn name(&self) -> String {
-        format!("server-{}", self.id)
+impl ProcessContext for ServerContext {
+    type Run = NetbenchRun;
+
+    fn spec(&self, name: &str, run: Option<&NetbenchRun>) -> ProcessSpec {
+        let run = run.cloned().unwrap_or_default();
+        let driver = run.driver.unwrap_or_else(|| self.driver.clone());
+        let scenario = run.scenario.unwrap_or_else(|| self.scenario.clone());
+        let netbench_port = run.netbench_port.unwrap_or(self.netbench_port);
+
+        // the output is named after the driver since a Worker can run several
+        let output = format!("{}-{}", name, trim_driver_name(&driver));
+        // sudo SCENARIO=./target/netbench/connect.json ./target/release/netbench-collector
+        //   ./target/release/netbench-driver-s2n-quic-server
+        let netbench_path = self.netbench_path.to_str().unwrap();
+        let collector = format!("{}/s2n-netbench-collector", netbench_path);
+        // driver value ex.: netbench-driver-s2n-quic-server
+        let driver_path = format!("{}/{}", netbench_path, driver);
+        // the scenario can also be an absolute path to a file elsewhere
+        let scenario_path = self.netbench_path.join(&scenario).display().to_string();
+
+        let spec = ProcessSpec::new(collector)
+            .with_env("PORT", netbench_port.to_string())
+            // .with_args(["--disable-bpf"])
+            .with_args([driver_path, "--scenario".to_string(), scenario_path])
+            .with_stdout_path(output_path(&self.output_dir, format!("{}.json", output)))
+            .with_stderr_path(output_path(
+                &self.output_dir,
+                format!("{}.stderr.log", output),
+            ))
+            .with_port(netbench_port);
+
+        // the collector starts the driver as a child process, which must also be
+        // stopped to free the netbench port for the next run
+        spec.with_process_group()
+            .with_max_run_duration(self.max_run_duration)
+            .with_params(run.params)
+            .with_result_name(result_name(&scenario, &driver, &output))
     }
 
-    async fn connect(&self, addr: &SocketAddr) -> RussulaResult<TcpStream> {
-        let listener = TcpListener::bind(addr).await.unwrap();
-        info!("{} listening on: {}", self.name(), addr);
-
-        let (stream, _local_addr) = listener.accept().await.map_err(RussulaError::from)?;
-        info!("{} success connection: {addr}", self.name());
-
-        Ok(stream)
+    fn executor(&self) -> Arc<dyn Executor> {
+        executor(&self.executor, self.dry_run, self.simulate)
     }
+}
 
-    fn update_peer_state(&mut self, msg: Msg) -> RussulaResult<()> {
-        self.peer_state = CoordState::from_msg(msg)?;
-        debug!("{} ... peer_state {:?}", self.name(), self.peer_state);
-
-        Ok(())
+impl ProcessState for WorkerState {
+    fn init() -> Self {
+        WorkerState::WaitCoordInit
     }
 
-    fn state(&self) -> &Self::State {
-        &self.state
+    fn ready() -> Self {
+        WorkerState::Ready
     }
 
-    fn state_mut(&mut self) -> &mut Self::State {
-        &mut self.state
+    fn run() -> Self {
+        WorkerState::Run
     }
 
-    fn ready_state(&self) -> Self::State {
-        WorkerState::Ready
+    fn done() -> Self {
+        WorkerState::Done
     }
 
-    fn done_state(&self) -> Self::State {
-        WorkerState::Done
+    fn running(pid: u32) -> Self {
+        WorkerState::RunningAwaitKill(pid)
     }
 
-    fn worker_running_state(&self) -> Self::State {
-        unimplemented!()
+    fn stopped() -> Self {
+        WorkerState::Stopped
     }
 
-    async fn run(&mut self, stream: &TcpStream) -> RussulaResult<Option<Msg>> {
-        match self.state_mut() {
-            WorkerState::WaitCoordInit => {
-                // self.notify_peer(stream).await?;
-                self.await_next_msg(stream).await
-            }
-            WorkerState::Ready => {
-                notify_peer!(self, stream);
-                self.await_next_msg(stream).await
-            }
-            WorkerState::Run => {
-                let child = match &self.netbench_ctx.testing {
-                    false => {
-                        let output_log_file = format!("{}.json", self.name());
-                        let output_log_file =
-                            File::create(output_log_file).expect("failed to open log");
-
-                        // sudo SCENARIO=./target/netbench/connect.json ./target/release/netbench-collector
-                        //   ./target/release/netbench-driver-s2n-quic-server
-                        info!("{} run task netbench", self.name());
-                        println!("{} run task netbench", self.name());
-
-                        let netbench_path = self.netbench_ctx.netbench_path.to_str().unwrap();
-                        let collector = format!("{}/s2n-netbench-collector", netbench_path);
-                        // driver value ex.: netbench-driver-s2n-quic-server
-                        let driver = format!("{}/{}", netbench_path, self.netbench_ctx.driver);
-                        let scenario = format!("{}/{}", netbench_path, self.netbench_ctx.scenario);
-
-                        debug!("netbench_port: {}", self.netbench_ctx.netbench_port);
-
-                        let mut cmd = Command::new(collector);
-                        cmd.env("PORT", self.netbench_ctx.netbench_port.to_string());
-                        // cmd.arg("--disable-bpf");
-                        cmd.args([&driver, "--scenario", &scenario])
-                            .stdout(output_log_file);
-                        println!("{:?}", cmd);
-                        debug!("{:?}", cmd);
-                        cmd.spawn()
-                            .expect("Failed to start netbench server process")
-                    }
-                    true => {
-                        info!("{} run task sim_netbench_server", self.name());
-                        Command::new("sh")
-                            .args(["scripts/sim_netbench_server.sh", &self.name()])
-                            .spawn()
-                            .expect("Failed to start echo process")
-                    }
-                };
-
-                let pid = child.id();
-                debug!(
-                    "{}----------------------------child id {}",
-                    self.name(),
-                    pid
-                );
-
-                *self.state_mut() = WorkerState::RunningAwaitKill(pid);
-                Ok(None)
-            }
-            WorkerState::RunningAwaitKill(_pid) => {
-                notify_peer!(self, stream);
-                self.await_next_msg(stream).await
-            }
-            WorkerState::Killing(pid) => {
-                let pid = Pid::from_u32(*pid);
-                let mut system = sysinfo::System::new_all();
-                if system.refresh_process(pid) {
-                    let process = system.process(pid).unwrap();
-                    let kill = process.kill();
-                    debug!("did KILL pid: {} {}----------------------------", pid, kill);
-                }
-
-                self.transition_self_or_user_driven(stream).await?;
-                Ok(None)
-            }
-            WorkerState::Stopped => {
-                notify_peer!(self, stream);
-                self.await_next_msg(stream).await
-            }
-            WorkerState::Done => {
-                notify_peer!(self, stream);
-                Ok(None)
-            }
-        }
+    fn timed_out(pid: u32) -> Self {
+        WorkerState::TimedOut(pid)
     }
 
-    fn event_recorder(&mut self) -> &mut EventRecorder {
-        &mut self.event_recorder
+    fn action(&self) -> ProcessAction {
+        match self {
+            WorkerState::WaitCoordInit => ProcessAction::AwaitPeer,
+            WorkerState::Ready => ProcessAction::AwaitPeer,
+            WorkerState::Run => ProcessAction::Spawn,
+            WorkerState::RunningAwaitKill(_pid) => ProcessAction::AwaitPeer,
+            WorkerState::Killing(pid) => ProcessAction::Kill(*pid),
+            WorkerState::TimedOut(pid) => ProcessAction::Kill(*pid),
+            WorkerState::Stopped => ProcessAction::AwaitPeer,
+            WorkerState::Done => ProcessAction::Finished,
+        }
     }
 }
 
 impl StateApi for WorkerState {
-    fn transition_step(&self) -> TransitionStep {
+    type PeerState = CoordState;
+
+    fn transition_step(&self) -> TransitionStep<CoordState> {
         match self {
-            WorkerState::WaitCoordInit => {
-                TransitionStep::AwaitNext(CoordState::CheckWorker.as_bytes())
-            }
-            WorkerState::Ready => TransitionStep::AwaitNext(CoordState::RunWorker.as_bytes()),
+            WorkerState::WaitCoordInit => TransitionStep::AwaitNext(CoordState::CheckWorker),
+            WorkerState::Ready => TransitionStep::AwaitNext(CoordState::RunWorker),
             WorkerState::Run => TransitionStep::SelfDriven,
-            WorkerState::RunningAwaitKill(_) => {
-                TransitionStep::AwaitNext(CoordState::KillWorker.as_bytes())
-            }
+            WorkerState::RunningAwaitKill(_) => TransitionStep::AwaitNext(CoordState::KillWorker),
             WorkerState::Killing(_) => TransitionStep::SelfDriven,
-            WorkerState::Stopped => TransitionStep::AwaitNext(CoordState::Done.as_bytes()),
+            WorkerState::TimedOut(_) => TransitionStep::SelfDriven,
+            WorkerState::Stopped => TransitionStep::AwaitNext(CoordState::Done),
             WorkerState::Done => TransitionStep::Finished,
         }
     }
 
-    fn next_state(&self) -> Self {
-        match self {
+    fn next_state(&self) -> Option<Self> {
+        let next = match self {
             WorkerState::WaitCoordInit => WorkerState::Ready,
             WorkerState::Ready => WorkerState::Run,
-            // FIXME error prone
-            WorkerState::Run => WorkerState::RunningAwaitKill(PLACEHOLDER_PID),
+            // The pid is only known once the process is spawned so `ProcessWorkerProtocol`
+            // sets RunningAwaitKill(pid) itself
+            WorkerState::Run => return None,
             WorkerState::RunningAwaitKill(pid) => WorkerState::Killing(*pid),
             WorkerState::Killing(_) => WorkerState::Stopped,
+            WorkerState::TimedOut(_) => WorkerState::Stopped,
             WorkerState::Stopped => WorkerState::Done,
-            WorkerState::Done => WorkerState::Done,
-        }
+            WorkerState::Done => return None,
+        };
+        Some(next)
     }
 }
 
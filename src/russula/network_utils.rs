@@ -1,10 +1,15 @@
 // Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::russula::{RussulaError, RussulaResult};
-use bytes::Bytes;
-use tokio::net::TcpStream;
-use tracing::error;
+use crate::russula::{transport::Transport, RussulaError, RussulaResult};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use core::{future::poll_fn, task::Poll, time::Duration};
+use std::time::Instant;
+
+// Size of the length prefix for each frame
+pub(crate) const LEN_PREFIX: usize = core::mem::size_of::<u16>();
+// Default upper bound on the size of a single frame payload
+pub const MAX_FRAME_LEN: usize = u16::MAX as usize;
 
 macro_rules! to_russula_err {
     {$error:ident} => {{
@@ -17,55 +22,238 @@ macro_rules! to_russula_err {
     }}
 }
 
-pub async fn recv_msg(stream: &TcpStream) -> RussulaResult<Msg> {
-    stream
-        .readable()
-        .await
-        .map_err(|err| to_russula_err!(err))?;
-    read_msg(stream).await
+/// A [`Transport`] which reads and writes length-prefixed [`Msg`] frames.
+///
+/// Partially read frames are buffered across calls to [`recv_msg`] so that
+/// a frame arriving over multiple segments is not lost. Similarly, multiple
+/// frames arriving in a single segment are returned one at a time.
+#[derive(Debug)]
+pub struct FramedStream {
+    stream: Box<dyn Transport>,
+    codec: MsgCodec,
+    send_seq: u64,
+    last_recv: Instant,
+    last_send: Instant,
+    heartbeat_interval: Option<Duration>,
+}
+
+impl FramedStream {
+    pub fn new(stream: Box<dyn Transport>) -> Self {
+        Self::with_max_frame_len(stream, MAX_FRAME_LEN)
+    }
+
+    pub fn with_max_frame_len(stream: Box<dyn Transport>, max_frame_len: usize) -> Self {
+        FramedStream {
+            stream,
+            codec: MsgCodec::new(max_frame_len),
+            send_seq: 0,
+            last_recv: Instant::now(),
+            last_send: Instant::now(),
+            heartbeat_interval: None,
+        }
+    }
+
+    /// Re-send the current state to the peer if no msg has been sent within
+    /// `interval` while waiting for the next msg.
+    pub fn set_heartbeat_interval(&mut self, interval: Option<Duration>) {
+        self.heartbeat_interval = interval;
+    }
+
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        self.heartbeat_interval
+    }
+
+    /// The last time a msg was received from the peer
+    pub fn last_recv(&self) -> Instant {
+        self.last_recv
+    }
+
+    /// True if nothing has been sent to the peer within the heartbeat interval
+    pub fn should_heartbeat(&self) -> bool {
+        self.heartbeat_interval
+            .is_some_and(|interval| self.last_send.elapsed() >= interval)
+    }
+
+    /// Sequence number for the next msg sent on this stream
+    pub fn next_seq(&mut self) -> u64 {
+        let seq = self.send_seq;
+        self.send_seq += 1;
+        seq
+    }
+}
+
+pub async fn recv_msg(stream: &mut FramedStream) -> RussulaResult<Msg> {
+    // Return a previously buffered frame before reading from the socket
+    let msg = match stream.codec.decode()? {
+        Some(msg) => msg,
+        None => {
+            poll_fn(|cx| stream.stream.poll_read_ready(cx))
+                .await
+                .map_err(|err| to_russula_err!(err))?;
+            read_msg(stream)?
+        }
+    };
+
+    stream.last_recv = Instant::now();
+    Ok(msg)
+}
+
+/// Return the next msg without waiting for the socket to become readable.
+///
+/// Returns [`RussulaError::NetworkBlocked`] if a complete msg is not available.
+pub fn try_recv_msg(stream: &mut FramedStream) -> RussulaResult<Msg> {
+    let msg = match stream.codec.decode()? {
+        Some(msg) => msg,
+        None => read_msg(stream)?,
+    };
+
+    stream.last_recv = Instant::now();
+    Ok(msg)
+}
+
+/// Wait until a msg can be read from any of the `streams`.
+///
+/// Also completes if a stream has been closed or errored, so that the error
+/// is surfaced by the next read.
+pub async fn readable_any(streams: &[&FramedStream]) {
+    poll_fn(|cx| {
+        for stream in streams {
+            if stream.codec.has_frame() || stream.stream.poll_read_ready(cx).is_ready() {
+                return Poll::Ready(());
+            }
+        }
+        Poll::Pending
+    })
+    .await
 }
 
-pub async fn send_msg(stream: &TcpStream, msg: Msg) -> RussulaResult<usize> {
-    stream
-        .writable()
-        .await
-        .map_err(|err| to_russula_err!(err))?;
-    write_msg(stream, msg).await
+pub async fn send_msg(stream: &mut FramedStream, msg: Msg) -> RussulaResult<usize> {
+    let len = stream.codec.encode(msg)?;
+    write_msg(stream).await?;
+    stream.last_send = Instant::now();
+    Ok(len)
 }
 
-async fn write_msg(stream: &TcpStream, msg: Msg) -> RussulaResult<usize> {
-    let mut data: Vec<u8> = Vec::with_capacity((msg.len + 1).into());
-    data.extend(msg.len.to_be_bytes());
-    data.extend(msg.data);
+// Flush the pending write buffer, retrying on short writes until the
+// entire buffer has been written to the socket.
+async fn write_msg(stream: &mut FramedStream) -> RussulaResult<()> {
+    while stream.codec.has_pending_write() {
+        poll_fn(|cx| stream.stream.poll_write_ready(cx))
+            .await
+            .map_err(|err| to_russula_err!(err))?;
+
+        match stream.stream.try_write(stream.codec.pending_write()) {
+            Ok(written) => stream.codec.advance_write(written),
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(err) => return Err(to_russula_err!(err)),
+        }
+    }
 
-    stream.try_write(&data).map_err(|err| to_russula_err!(err))
+    Ok(())
 }
 
-async fn read_msg(stream: &TcpStream) -> RussulaResult<Msg> {
-    let mut len_buf = [0; 2];
-    let o = stream
-        .try_read(&mut len_buf)
-        .map_err(|err| to_russula_err!(err))?;
-    if o == 0 {
-        error!("read len 0");
-        return Err(RussulaError::NetworkBlocked {
-            dbg: "read 0 data.. read socket closed?".to_string(),
-        });
-    }
-    let len = u16::from_be_bytes(len_buf);
-
-    let mut data = Vec::with_capacity(len.into());
-    let read_bytes = stream
-        .try_read_buf(&mut data)
-        .map_err(|err| to_russula_err!(err))?;
-
-    if read_bytes == len as usize {
-        Ok(Msg::new(data.into()))
-    } else {
-        let data = std::str::from_utf8(&data).unwrap_or("Unable to parse bytes as str!!");
-        Err(RussulaError::BadMsg {
-            dbg: format!("received a malformed msg. len: {} data: {:?}", len, data),
-        })
+// Read from the transport until a complete frame is available or the transport
+// would block. Partial frames remain buffered for the next call.
+fn read_msg(stream: &mut FramedStream) -> RussulaResult<Msg> {
+    loop {
+        let read_bytes = stream
+            .stream
+            .try_read_buf(stream.codec.read_buf())
+            .map_err(|err| to_russula_err!(err))?;
+        if read_bytes == 0 {
+            return Err(RussulaError::NetworkFail {
+                dbg: "read 0 data.. peer closed the connection".to_string(),
+            });
+        }
+
+        if let Some(msg) = stream.codec.decode()? {
+            return Ok(msg);
+        }
+    }
+}
+
+/// Encodes and decodes length-prefixed [`Msg`] frames.
+#[derive(Debug)]
+struct MsgCodec {
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+    max_frame_len: usize,
+}
+
+impl MsgCodec {
+    fn new(max_frame_len: usize) -> Self {
+        MsgCodec {
+            read_buf: BytesMut::new(),
+            write_buf: BytesMut::new(),
+            max_frame_len: max_frame_len.min(MAX_FRAME_LEN),
+        }
+    }
+
+    fn encode(&mut self, msg: Msg) -> RussulaResult<usize> {
+        let len = msg.data.len();
+        if len > self.max_frame_len {
+            return Err(RussulaError::BadMsg {
+                dbg: format!(
+                    "msg len: {} exceeds max frame len: {}",
+                    len, self.max_frame_len
+                ),
+            });
+        }
+
+        self.write_buf.reserve(LEN_PREFIX + len);
+        self.write_buf.put_u16(len as u16);
+        self.write_buf.put(msg.data);
+        Ok(LEN_PREFIX + len)
+    }
+
+    fn decode(&mut self) -> RussulaResult<Option<Msg>> {
+        if self.read_buf.len() < LEN_PREFIX {
+            return Ok(None);
+        }
+
+        let len = u16::from_be_bytes([self.read_buf[0], self.read_buf[1]]) as usize;
+        if len > self.max_frame_len {
+            return Err(RussulaError::BadMsg {
+                dbg: format!(
+                    "received a malformed msg. len: {} exceeds max frame len: {}",
+                    len, self.max_frame_len
+                ),
+            });
+        }
+
+        if self.read_buf.len() < LEN_PREFIX + len {
+            // wait for the rest of the frame
+            self.read_buf
+                .reserve(LEN_PREFIX + len - self.read_buf.len());
+            return Ok(None);
+        }
+
+        self.read_buf.advance(LEN_PREFIX);
+        let data = self.read_buf.split_to(len).freeze();
+        Ok(Some(Msg::new(data)))
+    }
+
+    // A complete frame is buffered and can be decoded without reading from the socket
+    fn has_frame(&self) -> bool {
+        self.read_buf.len() >= LEN_PREFIX
+            && self.read_buf.len()
+                >= LEN_PREFIX + u16::from_be_bytes([self.read_buf[0], self.read_buf[1]]) as usize
+    }
+
+    fn read_buf(&mut self) -> &mut BytesMut {
+        &mut self.read_buf
+    }
+
+    fn has_pending_write(&self) -> bool {
+        !self.write_buf.is_empty()
+    }
+
+    fn pending_write(&self) -> &[u8] {
+        &self.write_buf
+    }
+
+    fn advance_write(&mut self, cnt: usize) {
+        self.write_buf.advance(cnt)
     }
 }
 
@@ -86,6 +274,11 @@ impl Msg {
     pub fn as_bytes(&self) -> &[u8] {
         &self.data
     }
+
+    /// Size of the msg on the wire, including the length prefix
+    pub fn frame_len(&self) -> usize {
+        LEN_PREFIX + self.data.len()
+    }
 }
 
 impl std::fmt::Display for Msg {
@@ -94,3 +287,162 @@ impl std::fmt::Display for Msg {
         write!(f, "Msg [ len: {} data: {} ]", self.len, data)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (client, server) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        (client.unwrap(), server.unwrap().0)
+    }
+
+    async fn write_raw(stream: &dyn Transport, data: &[u8]) {
+        let mut written = 0;
+        while written < data.len() {
+            poll_fn(|cx| stream.poll_write_ready(cx)).await.unwrap();
+            match stream.try_write(&data[written..]) {
+                Ok(n) => written += n,
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(err) => panic!("{}", err),
+            }
+        }
+    }
+
+    fn frame(data: &[u8]) -> Vec<u8> {
+        let mut frame = (data.len() as u16).to_be_bytes().to_vec();
+        frame.extend(data);
+        frame
+    }
+
+    #[tokio::test]
+    async fn fragmented_frame() {
+        let (tx, rx) = socket_pair().await;
+        let mut rx = FramedStream::new(Box::new(rx));
+        let frame = frame(b"\"Ready\"");
+
+        // send the length prefix and a partial body
+        write_raw(&tx, &frame[..1]).await;
+        write_raw(&tx, &frame[1..4]).await;
+        tokio::time::sleep(core::time::Duration::from_millis(10)).await;
+        assert!(matches!(
+            recv_msg(&mut rx).await,
+            Err(RussulaError::NetworkBlocked { .. })
+        ));
+
+        // the partial frame is retained and completed on the next poll
+        write_raw(&tx, &frame[4..]).await;
+        let msg = recv_msg(&mut rx).await.unwrap();
+        assert_eq!(msg.as_bytes(), b"\"Ready\"");
+    }
+
+    #[tokio::test]
+    async fn coalesced_frames() {
+        let (tx, rx) = socket_pair().await;
+        let mut rx = FramedStream::new(Box::new(rx));
+
+        let partial = frame(b"\"RunWorker\"");
+        let mut data = frame(b"\"CheckWorker\"");
+        data.extend(frame(b"\"Ready\""));
+        data.extend(&partial[..6]);
+        write_raw(&tx, &data).await;
+        tokio::time::sleep(core::time::Duration::from_millis(10)).await;
+
+        assert_eq!(
+            recv_msg(&mut rx).await.unwrap().as_bytes(),
+            b"\"CheckWorker\""
+        );
+        assert_eq!(recv_msg(&mut rx).await.unwrap().as_bytes(), b"\"Ready\"");
+        assert!(matches!(
+            recv_msg(&mut rx).await,
+            Err(RussulaError::NetworkBlocked { .. })
+        ));
+
+        write_raw(&tx, &partial[6..]).await;
+        assert_eq!(
+            recv_msg(&mut rx).await.unwrap().as_bytes(),
+            b"\"RunWorker\""
+        );
+    }
+
+    #[tokio::test]
+    async fn send_recv_large_frames() {
+        let (tx, rx) = socket_pair().await;
+        let mut tx = FramedStream::new(Box::new(tx));
+        let mut rx = FramedStream::new(Box::new(rx));
+
+        // large enough to span multiple segments and cause short writes
+        let payload = Bytes::from(vec![b'a'; MAX_FRAME_LEN]);
+        let send = async {
+            for _ in 0..8 {
+                send_msg(&mut tx, Msg::new(payload.clone())).await.unwrap();
+            }
+        };
+        let recv = async {
+            let mut cnt = 0;
+            while cnt < 8 {
+                match recv_msg(&mut rx).await {
+                    Ok(msg) => {
+                        assert_eq!(msg.data, payload);
+                        cnt += 1;
+                    }
+                    Err(RussulaError::NetworkBlocked { .. }) => tokio::task::yield_now().await,
+                    Err(err) => panic!("{}", err),
+                }
+            }
+        };
+        tokio::join!(send, recv);
+    }
+
+    #[tokio::test]
+    async fn readable_any_stream() {
+        let (tx1, rx1) = socket_pair().await;
+        let (_tx2, rx2) = socket_pair().await;
+        let mut rx1 = FramedStream::new(Box::new(rx1));
+        let rx2 = FramedStream::new(Box::new(rx2));
+
+        assert!(matches!(
+            try_recv_msg(&mut rx1),
+            Err(RussulaError::NetworkBlocked { .. })
+        ));
+
+        // wake as soon as any stream has a msg
+        write_raw(&tx1, &frame(b"\"Ready\"")).await;
+        tokio::time::timeout(Duration::from_secs(1), readable_any(&[&rx2, &rx1]))
+            .await
+            .unwrap();
+        assert_eq!(try_recv_msg(&mut rx1).unwrap().as_bytes(), b"\"Ready\"");
+        assert!(matches!(
+            try_recv_msg(&mut rx1),
+            Err(RussulaError::NetworkBlocked { .. })
+        ));
+
+        // drained all msgs
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), readable_any(&[&rx2, &rx1]))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn max_frame_len() {
+        let (tx, rx) = socket_pair().await;
+        let mut tx = FramedStream::with_max_frame_len(Box::new(tx), 4);
+        let mut rx = FramedStream::with_max_frame_len(Box::new(rx), 4);
+
+        assert!(matches!(
+            send_msg(&mut tx, Msg::new(Bytes::from_static(b"too long"))).await,
+            Err(RussulaError::BadMsg { .. })
+        ));
+
+        write_raw(&*tx.stream, &frame(b"too long")).await;
+        assert!(matches!(
+            recv_msg(&mut rx).await,
+            Err(RussulaError::BadMsg { .. })
+        ));
+    }
+}
@@ -0,0 +1,1247 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::russula::{
+    clock::{self, ClockOffset},
+    error::{RussulaError, RussulaResult},
+    event::{EventRecorder, EventType},
+    executor::{Executor, LocalProcess, ProcessHandle},
+    network_utils::{FramedStream, Msg},
+    protocol::{notify_peer, Protocol},
+    results::ResultChunk,
+    states::Envelope,
+    transport::{Listener, Network, Transport},
+    StateApi, TransitionStep,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    net::{Ipv4Addr, SocketAddr},
+    os::unix::process::{CommandExt, ExitStatusExt},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{debug, error, info, warn};
+
+// Number of bytes from the end of stderr reported when the process fails
+const STDERR_TAIL_LEN: u64 = 1024;
+
+// Time given to the process to exit after SIGTERM before it is sent SIGKILL
+const DEFAULT_KILL_GRACE: Duration = Duration::from_secs(5);
+
+// Bounds how far ahead the Coordinator can schedule the start of a run, since the
+// Worker doesn't read msgs from the Coordinator while waiting to start
+const MAX_START_DELAY: Duration = Duration::from_secs(60);
+
+/// Data sent by a Coordinator along with its state
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct CoordPayload {
+    /// Wall-clock time, on the Coordinator's clock, at which the Workers should start
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_at_us: Option<u64>,
+    /// Incremented each time the Coordinator runs the Workers again in the same session
+    #[serde(default)]
+    pub iteration: u32,
+    /// Parameters of the run, interpreted by the Worker's [`ProcessContext`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<serde_json::Value>,
+    /// Stream the result file to the Coordinator once the process stops
+    #[serde(default)]
+    pub collect_results: bool,
+}
+
+/// Data sent by a Worker along with its state
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct WorkerPayload {
+    /// Offset of the Coordinator's clock relative to the Worker's clock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_offset: Option<ClockOffset>,
+    /// Wall-clock time, on the Worker's clock, at which the process was started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at_us: Option<u64>,
+    /// How the process stopped. Set once the Worker reaches Stopped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit: Option<ProcessExit>,
+    /// The run parameters applied to the process. Set once the process is started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<RunParams>,
+    /// A chunk of the result file streamed once the process has stopped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<ResultChunk>,
+    /// The iteration of the process which the State refers to
+    #[serde(default)]
+    pub iteration: u32,
+}
+
+impl WorkerPayload {
+    /// Time between the scheduled `start_at_us`, on the Coordinator's clock, and when
+    /// the process was started
+    pub fn start_skew_us(&self, start_at_us: u64) -> Option<i64> {
+        let started_at_us = self.started_at_us? as i64 + self.clock_offset?.offset_us;
+        Some(started_at_us - start_at_us as i64)
+    }
+}
+
+/// Extra environment variables and arguments applied to the process of a run,
+/// such as `TRACE=stdio` or a congestion controller option.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunParams {
+    /// Set in the process environment, overriding any set by the Worker
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub envs: BTreeMap<String, String>,
+    /// Appended to the process arguments
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+}
+
+impl RunParams {
+    /// Parse a `KEY=VALUE` environment variable from the command line
+    pub fn parse_env(env: &str) -> Result<(String, String), String> {
+        match env.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+            _ => Err(format!("expected KEY=VALUE but got '{}'", env)),
+        }
+    }
+}
+
+/// How the process supervised by a Worker stopped
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessExit {
+    /// Exit code if the process exited on its own
+    pub code: Option<i32>,
+    /// Signal which terminated the process
+    pub signal: Option<i32>,
+    /// The Worker stopped the process as part of its completion policy
+    pub killed: bool,
+    /// The Worker killed the process since it ran longer than the max run duration
+    #[serde(default)]
+    pub timed_out: bool,
+    /// The end of the process stderr if it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr_tail: Option<String>,
+    /// Why the process couldn't be started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spawn_error: Option<String>,
+    /// Why the Worker couldn't wait on the process
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wait_error: Option<String>,
+}
+
+impl ProcessExit {
+    fn new(status: ExitStatus, killed: bool, timed_out: bool, stderr_path: Option<&Path>) -> Self {
+        let mut exit = ProcessExit {
+            code: status.code(),
+            signal: status.signal(),
+            killed,
+            timed_out,
+            stderr_tail: None,
+            spawn_error: None,
+            wait_error: None,
+        };
+        if !exit.success() {
+            exit.stderr_tail = stderr_path.and_then(read_tail);
+        }
+        exit
+    }
+
+    fn spawn_failed(err: &io::Error) -> Self {
+        ProcessExit {
+            code: None,
+            signal: None,
+            killed: false,
+            timed_out: false,
+            stderr_tail: None,
+            spawn_error: Some(err.to_string()),
+            wait_error: None,
+        }
+    }
+
+    fn wait_failed(err: &io::Error) -> Self {
+        ProcessExit {
+            code: None,
+            signal: None,
+            killed: false,
+            timed_out: false,
+            stderr_tail: None,
+            spawn_error: None,
+            wait_error: Some(err.to_string()),
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.spawn_error.is_none()
+            && self.wait_error.is_none()
+            && !self.timed_out
+            && (self.killed || self.code == Some(0))
+    }
+}
+
+impl std::fmt::Display for ProcessExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(spawn_error) = &self.spawn_error {
+            return write!(f, "failed to start: {}", spawn_error);
+        }
+        if let Some(wait_error) = &self.wait_error {
+            return write!(f, "failed to wait: {}", wait_error);
+        }
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "exit code: {}", code)?,
+            (None, Some(signal)) => write!(f, "signal: {}", signal)?,
+            (None, None) => write!(f, "unknown exit")?,
+        }
+        if self.timed_out {
+            write!(f, " (timed out)")?;
+        } else if self.killed {
+            write!(f, " (killed)")?;
+        }
+        if let Some(stderr_tail) = &self.stderr_tail {
+            write!(f, " stderr: {}", stderr_tail)?;
+        }
+        Ok(())
+    }
+}
+
+// Read up to STDERR_TAIL_LEN bytes from the end of the file
+fn read_tail(path: &Path) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let len = file.metadata().ok()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(STDERR_TAIL_LEN)))
+        .ok()?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).ok()?;
+    Some(String::from_utf8_lossy(&tail).trim().to_string())
+}
+
+// The output files of later iterations are prefixed with the iteration so
+// that the results of every iteration are kept
+fn iteration_path(path: &Path, iteration: u32) -> PathBuf {
+    match (iteration, path.file_name()) {
+        (0, _) | (_, None) => path.to_path_buf(),
+        (_, Some(file_name)) => {
+            path.with_file_name(format!("iter{}-{}", iteration, file_name.to_string_lossy()))
+        }
+    }
+}
+
+/// The process run by a [`ProcessWorkerProtocol`]
+#[derive(Clone, Debug)]
+pub struct ProcessSpec {
+    pub program: String,
+    pub args: Vec<String>,
+    pub envs: Vec<(String, String)>,
+    /// Write the stdout of the process to this file
+    pub stdout_path: Option<PathBuf>,
+    /// Write the stderr of the process to this file
+    pub stderr_path: Option<PathBuf>,
+    /// Start the process in its own process group so that any processes it
+    /// starts are stopped along with it
+    pub process_group: bool,
+    /// Time between SIGTERM and SIGKILL when stopping the process
+    pub kill_grace: Duration,
+    /// Port used by the process, which must be released before the process is
+    /// considered stopped
+    pub port: Option<u16>,
+    /// Kill the process if it runs for longer than this duration
+    pub max_run_duration: Option<Duration>,
+    /// Parameters of the run applied on top of `args` and `envs`
+    pub params: RunParams,
+    /// Path, relative to the Coordinator's results dir, which stdout is written
+    /// to when the Coordinator collects results
+    pub result_name: Option<PathBuf>,
+}
+
+impl ProcessSpec {
+    pub fn new(program: impl Into<String>) -> Self {
+        ProcessSpec {
+            program: program.into(),
+            args: Vec::new(),
+            envs: Vec::new(),
+            stdout_path: None,
+            stderr_path: None,
+            process_group: false,
+            kill_grace: DEFAULT_KILL_GRACE,
+            port: None,
+            max_run_duration: None,
+            params: RunParams::default(),
+            result_name: None,
+        }
+    }
+
+    pub fn with_args<I, A>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn with_env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn with_stdout_path(mut self, stdout_path: impl Into<PathBuf>) -> Self {
+        self.stdout_path = Some(stdout_path.into());
+        self
+    }
+
+    pub fn with_stderr_path(mut self, stderr_path: impl Into<PathBuf>) -> Self {
+        self.stderr_path = Some(stderr_path.into());
+        self
+    }
+
+    pub fn with_process_group(mut self) -> Self {
+        self.process_group = true;
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_kill_grace(mut self, kill_grace: Duration) -> Self {
+        self.kill_grace = kill_grace;
+        self
+    }
+
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    pub fn with_max_run_duration(mut self, max_run_duration: Option<Duration>) -> Self {
+        self.max_run_duration = max_run_duration;
+        self
+    }
+
+    pub fn with_params(mut self, params: RunParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    pub fn with_result_name(mut self, result_name: impl Into<PathBuf>) -> Self {
+        self.result_name = Some(result_name.into());
+        self
+    }
+
+    pub(crate) fn stdout_path(&self, iteration: u32) -> Option<PathBuf> {
+        self.stdout_path
+            .as_deref()
+            .map(|path| iteration_path(path, iteration))
+    }
+
+    pub(crate) fn stderr_path(&self, iteration: u32) -> Option<PathBuf> {
+        self.stderr_path
+            .as_deref()
+            .map(|path| iteration_path(path, iteration))
+    }
+
+    // The command without its output redirected, which is left to the Executor
+    pub(crate) fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(&self.args)
+            .args(&self.params.args)
+            .envs(self.envs.iter().cloned())
+            .envs(self.params.envs.iter());
+        if self.process_group {
+            // a new group with the pgid set to the pid of the process
+            cmd.process_group(0);
+        }
+        cmd
+    }
+}
+
+/// What a [`ProcessWorkerProtocol`] does in a State.
+///
+/// The completion policy of a Worker is expressed by its States: a Worker which
+/// runs the process until the Coordinator asks it to stop has a State which maps
+/// to `Kill`, while a Worker which runs the process until it exits has a State
+/// which maps to `AwaitExit`.
+#[derive(Debug)]
+pub enum ProcessAction {
+    /// Wait for the Coordinator to move to the next State
+    AwaitPeer,
+    /// Start the process and move to [`ProcessState::running`]
+    Spawn,
+    /// Stop the process and move to the next State
+    Kill(u32),
+    /// Move to the next State once the process exits
+    AwaitExit(u32),
+    /// Report the final State to the Coordinator
+    Finished,
+}
+
+/// The States of a Worker which supervises a process
+pub trait ProcessState: StateApi {
+    fn init() -> Self;
+    fn ready() -> Self;
+    /// The State which starts the process
+    fn run() -> Self;
+    fn done() -> Self;
+    /// The State entered once the process has been started
+    fn running(pid: u32) -> Self;
+    /// The State entered once the process has stopped. The process is run again
+    /// if the Coordinator starts another iteration while the Worker is in this State.
+    fn stopped() -> Self;
+    /// The State entered once the process runs longer than the max run duration.
+    /// Its action should stop the process.
+    fn timed_out(pid: u32) -> Self;
+    fn action(&self) -> ProcessAction;
+}
+
+/// Builds the process run by a [`ProcessWorkerProtocol`].
+///
+/// The spec is built again before each run, so a long-lived Worker can run a
+/// different process each iteration based on the parameters sent by the
+/// Coordinator.
+pub trait ProcessContext: Clone + Debug {
+    /// Parameters of a single run sent by the Coordinator
+    type Run: Serialize + DeserializeOwned + Clone + Debug;
+
+    /// The process to run, given the latest parameters sent by the Coordinator if any
+    fn spec(&self, name: &str, run: Option<&Self::Run>) -> ProcessSpec;
+
+    /// Runs the process. Defaults to running it on the local host.
+    fn executor(&self) -> Arc<dyn Executor> {
+        Arc::new(LocalProcess)
+    }
+}
+
+/// A Worker which starts a process when the Coordinator is ready and stops it
+/// according to the completion policy of its States.
+#[derive(Clone, Debug)]
+pub struct ProcessWorkerProtocol<S: ProcessState, C: ProcessContext> {
+    name: String,
+    protocol_name: &'static str,
+    state: S,
+    peer_state: Option<S::PeerState>,
+    ctx: C,
+    // The latest run parameters sent by the Coordinator
+    run: Option<C::Run>,
+    // The process of the current run
+    spec: ProcessSpec,
+    executor: Arc<dyn Executor>,
+    event_recorder: EventRecorder,
+    // Kept alive after the first accept so that the Coordinator can reconnect
+    listener: Option<Listener>,
+    // Offset of the Coordinator's clock relative to the Worker's clock
+    clock_offset: ClockOffset,
+    // Wall-clock time, on the Coordinator's clock, at which to start the process
+    start_at_us: Option<u64>,
+    started_at_us: Option<u64>,
+    // When the process was started on the monotonic clock, which the max run
+    // duration is measured against so that it's unaffected by clock adjustments
+    spawned_at: Option<Instant>,
+    // Shared so that the protocol can be cloned
+    child: Option<Arc<Mutex<Box<dyn ProcessHandle>>>>,
+    exit: Option<ProcessExit>,
+    // When the Worker started to stop the process
+    terminate_started: Option<Instant>,
+    timed_out: bool,
+    // The current iteration and the latest one requested by the Coordinator
+    iteration: u32,
+    peer_iteration: u32,
+    // The Coordinator asked for the result file
+    collect_results: bool,
+    // The chunk of the result file included in the next msg
+    result_chunk: Option<ResultChunk>,
+}
+
+impl<S: ProcessState, C: ProcessContext> ProcessWorkerProtocol<S, C> {
+    pub fn from_context(name: String, protocol_name: &'static str, ctx: C) -> Self {
+        let spec = ctx.spec(&name, None);
+        let executor = ctx.executor();
+        ProcessWorkerProtocol {
+            name,
+            protocol_name,
+            state: S::init(),
+            peer_state: None,
+            ctx,
+            run: None,
+            spec,
+            executor,
+            event_recorder: EventRecorder::default(),
+            listener: None,
+            clock_offset: ClockOffset::default(),
+            start_at_us: None,
+            started_at_us: None,
+            spawned_at: None,
+            child: None,
+            exit: None,
+            terminate_started: None,
+            timed_out: false,
+            iteration: 0,
+            peer_iteration: 0,
+            collect_results: false,
+            result_chunk: None,
+        }
+    }
+
+    // Time until the start scheduled by the Coordinator, on the Worker's clock
+    fn start_delay(&self) -> Duration {
+        let start_at_us = self.start_at_us.map_or(0, |start_at_us| {
+            self.clock_offset.peer_to_local(start_at_us)
+        });
+        Duration::from_micros(start_at_us.saturating_sub(clock::now_us()))
+    }
+
+    fn spawn(&mut self) -> io::Result<u32> {
+        self.spec = self.ctx.spec(&self.name, self.run.as_ref());
+        info!("{} run process {}", self.name, self.spec.program);
+
+        let child = self.executor.spawn(&self.spec, self.iteration)?;
+        let pid = child.id();
+        debug!("{}----------------------------child id {}", self.name, pid);
+        self.event_recorder
+            .record_run_params(self.spec.params.clone());
+        self.child = Some(Arc::new(Mutex::new(child)));
+        self.spawned_at = Some(Instant::now());
+        Ok(pid)
+    }
+
+    // Reap the process if it has exited
+    fn try_wait(&self) -> io::Result<Option<ExitStatus>> {
+        match &self.child {
+            Some(child) => child.lock().unwrap().try_wait(),
+            None => Ok(None),
+        }
+    }
+
+    fn record_exit(&mut self, status: ExitStatus, killed: bool) -> ProcessExit {
+        let stderr_path = self.spec.stderr_path(self.iteration);
+        let exit = ProcessExit::new(status, killed, self.timed_out, stderr_path.as_deref());
+        match exit.success() {
+            true => info!("{} process stopped. {}", self.name, exit),
+            false => error!("{} process failed. {}", self.name, exit),
+        }
+        self.event_recorder.record_process_exit(exit.clone());
+        self.exit = Some(exit.clone());
+        exit
+    }
+
+    // The process can't be reaped so its exit status is unknown
+    fn record_wait_error(&mut self, err: &io::Error) -> ProcessExit {
+        let exit = ProcessExit::wait_failed(err);
+        error!("{} process failed. {}", self.name, exit);
+        self.event_recorder.record_process_exit(exit.clone());
+        self.exit = Some(exit.clone());
+        exit
+    }
+
+    // The Coordinator started another iteration while the process is stopped
+    fn next_iteration_requested(&self) -> bool {
+        let start_state = match S::ready().transition_step() {
+            TransitionStep::AwaitNext(start_state) => start_state,
+            _ => return false,
+        };
+        let peer_starting = self
+            .peer_state
+            .as_ref()
+            .is_some_and(|peer_state| peer_state.eq(&start_state));
+
+        self.state.eq(&S::stopped()) && peer_starting && self.peer_iteration > self.iteration
+    }
+
+    // The Coordinator moved to Done before the process was started
+    fn run_cancelled(&self) -> bool {
+        let not_started = self.state.eq(&S::init()) || self.state.eq(&S::ready());
+        let peer_done = self.peer_state.as_ref().is_some_and(|peer_state| {
+            matches!(peer_state.transition_step(), TransitionStep::Finished)
+        });
+
+        not_started && peer_done
+    }
+
+    // Reset the process and move to the State which runs it
+    fn start_next_iteration(&mut self) {
+        info!("{} starting iteration {}", self.name, self.peer_iteration);
+        self.iteration = self.peer_iteration;
+        self.started_at_us = None;
+        self.spawned_at = None;
+        self.child = None;
+        self.exit = None;
+        self.terminate_started = None;
+        self.timed_out = false;
+        self.state = S::run();
+    }
+
+    // Stream the result file to the Coordinator, one chunk per msg.
+    //
+    // Called once the process has exited and before moving to Stopped, so the
+    // Coordinator receives the whole file before it sees the Worker stop.
+    async fn send_results(&mut self, stream: &mut FramedStream) -> RussulaResult<()> {
+        let result_name = self.spec.result_name.as_deref();
+        let (Some(stdout_path), Some(result_name)) =
+            (self.spec.stdout_path(self.iteration), result_name)
+        else {
+            return Ok(());
+        };
+        if !self.collect_results {
+            return Ok(());
+        }
+
+        let result = match std::fs::read(&stdout_path) {
+            Ok(result) => result,
+            Err(err) => {
+                let err = format!("failed to read result {}: {}", stdout_path.display(), err);
+                error!("{} {}", self.name, err);
+                self.event_recorder.process(EventType::Error(err));
+                return Ok(());
+            }
+        };
+
+        let result_name = iteration_path(result_name, self.iteration);
+        let chunks = ResultChunk::split(&result_name, &result);
+        info!(
+            "{} sending result {} in {} chunks",
+            self.name,
+            result_name.display(),
+            chunks.len()
+        );
+        for chunk in chunks {
+            self.result_chunk = Some(chunk);
+            notify_peer!(self, stream);
+        }
+        self.result_chunk = None;
+        Ok(())
+    }
+
+    // Returns the pid if the process is still running after the max run duration
+    fn run_deadline_passed(&self) -> Option<u32> {
+        let max_run_duration = self.spec.max_run_duration?;
+        let spawned_at = self.spawned_at?;
+        if self.exit.is_some() || self.timed_out {
+            return None;
+        }
+
+        if spawned_at.elapsed() <= max_run_duration {
+            return None;
+        }
+        self.child.as_ref().map(|child| child.lock().unwrap().id())
+    }
+
+    // Send `signal` to the process, or to its whole process group if it has one
+    fn signal(&self, signal: libc::c_int) {
+        let Some(child) = &self.child else {
+            return;
+        };
+        let mut child = child.lock().unwrap();
+        if let Err(err) = child.signal(signal) {
+            warn!("{} failed to signal {}: {}", self.name, child.id(), err);
+        }
+    }
+
+    // Stop the process, escalating from SIGTERM to SIGKILL once the grace period
+    // passes, and wait for its port to be released.
+    //
+    // Returns true once stopped. Called repeatedly so the Worker can heartbeat
+    // while the process shuts down.
+    fn terminate(&mut self) -> bool {
+        if self.child.is_none() {
+            // the process was never started by this Worker
+            return true;
+        }
+
+        if self.exit.is_none() {
+            match (self.try_wait(), self.terminate_started) {
+                (Ok(Some(status)), terminate_started) => {
+                    self.record_exit(status, terminate_started.is_some());
+                    if self.spec.process_group {
+                        // processes in the group can outlive the group leader
+                        self.signal(libc::SIGKILL);
+                    }
+                }
+                (Ok(None), None) => {
+                    debug!("{} sending SIGTERM", self.name);
+                    self.signal(libc::SIGTERM);
+                    self.terminate_started = Some(Instant::now());
+                    return false;
+                }
+                (Ok(None), Some(terminate_started)) => {
+                    if terminate_started.elapsed() > self.spec.kill_grace {
+                        warn!(
+                            "{} process didn't exit on SIGTERM. sending SIGKILL",
+                            self.name
+                        );
+                        self.signal(libc::SIGKILL);
+                    }
+                    return false;
+                }
+                (Err(err), _) => {
+                    // make sure the process which can't be reaped isn't left running
+                    self.signal(libc::SIGKILL);
+                    self.record_wait_error(&err);
+                }
+            }
+        }
+
+        self.port_released()
+    }
+
+    // Check that the port used by the process is free. Gives up after twice the
+    // kill grace period since a leftover process can't be stopped by the Worker.
+    fn port_released(&mut self) -> bool {
+        let Some(port) = self.spec.port else {
+            return true;
+        };
+        if port_free(port) {
+            return true;
+        }
+
+        let terminate_started = *self.terminate_started.get_or_insert_with(Instant::now);
+        if terminate_started.elapsed() > self.spec.kill_grace * 2 {
+            let err = format!(
+                "{} port {} still in use after stopping the process",
+                self.name, port
+            );
+            error!("{}", err);
+            self.event_recorder.process(EventType::Error(err));
+            return true;
+        }
+        debug!("{} waiting for port {} to be released", self.name, port);
+        false
+    }
+}
+
+// A Worker which fails or is abandoned before stopping its process shouldn't leave
+// the process running
+impl<S: ProcessState, C: ProcessContext> Drop for ProcessWorkerProtocol<S, C> {
+    fn drop(&mut self) {
+        let Some(child) = &self.child else {
+            return;
+        };
+        // a clone of the protocol still owns the process
+        if Arc::strong_count(child) > 1
+            || self.exit.is_some()
+            || matches!(self.try_wait(), Ok(Some(_)))
+        {
+            return;
+        }
+
+        warn!(
+            "{} dropped while the process is running. sending SIGKILL",
+            self.name
+        );
+        self.signal(libc::SIGKILL);
+        if let Err(err) = child.lock().unwrap().wait() {
+            warn!(
+                "{} failed to wait on the killed process: {}",
+                self.name, err
+            );
+        }
+    }
+}
+
+// Check if neither TCP nor UDP are bound to the port
+fn port_free(port: u16) -> bool {
+    let addr = (Ipv4Addr::UNSPECIFIED, port);
+    std::net::TcpListener::bind(addr).is_ok() && std::net::UdpSocket::bind(addr).is_ok()
+}
+
+impl<S: ProcessState, C: ProcessContext> Protocol for ProcessWorkerProtocol<S, C> {
+    type State = S;
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    async fn connect(
+        &mut self,
+        network: &Network,
+        addr: &SocketAddr,
+    ) -> RussulaResult<Box<dyn Transport>> {
+        let listener = match &self.listener {
+            Some(listener) => listener.clone(),
+            None => {
+                let listener = network.listen(addr).await?;
+                info!("{} listening on: {}", self.name(), addr);
+                self.listener.insert(listener).clone()
+            }
+        };
+
+        let stream = listener.accept().await?;
+        info!("{} success connection: {addr}", self.name());
+
+        Ok(stream)
+    }
+
+    fn protocol_name(&self) -> &'static str {
+        self.protocol_name
+    }
+
+    fn update_peer_state(&mut self, msg: Msg) -> RussulaResult<()> {
+        let envelope = Envelope::<S::PeerState>::from_msg(&msg)?;
+        if let Some(payload) = envelope.payload_as::<CoordPayload>()? {
+            if let Some(start_at_us) = payload.start_at_us {
+                self.start_at_us = Some(start_at_us);
+            }
+            self.peer_iteration = payload.iteration;
+            if let Some(run) = payload.run {
+                let run = serde_json::from_value(run).map_err(|err| RussulaError::BadMsg {
+                    dbg: format!("received malformed run parameters: {}", err),
+                })?;
+                self.run = Some(run);
+            }
+            self.collect_results = payload.collect_results;
+        }
+        debug!("{} ... peer_state {:?}", self.name(), envelope.state);
+        self.peer_state = Some(envelope.state);
+
+        Ok(())
+    }
+
+    fn set_clock_offset(&mut self, clock_offset: ClockOffset) {
+        self.clock_offset = clock_offset;
+        self.event_recorder.record_clock_offset(clock_offset);
+    }
+
+    fn payload(&self) -> Option<serde_json::Value> {
+        let payload = WorkerPayload {
+            clock_offset: Some(self.clock_offset),
+            started_at_us: self.started_at_us,
+            exit: self.exit.clone(),
+            params: self.child.as_ref().map(|_| self.spec.params.clone()),
+            result: self.result_chunk.clone(),
+            iteration: self.iteration,
+        };
+        Some(serde_json::to_value(payload).unwrap())
+    }
+
+    fn state(&self) -> &Self::State {
+        &self.state
+    }
+
+    fn state_mut(&mut self) -> &mut Self::State {
+        &mut self.state
+    }
+
+    fn ready_state(&self) -> Self::State {
+        S::ready()
+    }
+
+    fn done_state(&self) -> Self::State {
+        S::done()
+    }
+
+    // States are compared by variant so the pid doesn't matter
+    fn worker_running_state(&self) -> Self::State {
+        S::running(0)
+    }
+
+    fn worker_stopped_state(&self) -> Self::State {
+        S::stopped()
+    }
+
+    async fn run(&mut self, stream: &mut FramedStream) -> RussulaResult<Option<Msg>> {
+        if let Some(pid) = self.run_deadline_passed() {
+            let err = format!(
+                "{} process pid: {} exceeded the max run duration of {:?}",
+                self.name(),
+                pid,
+                self.spec.max_run_duration.unwrap_or_default()
+            );
+            error!("{}", err);
+            self.event_recorder.process(EventType::Error(err));
+            self.timed_out = true;
+            *self.state_mut() = S::timed_out(pid);
+            notify_peer!(self, stream);
+            return Ok(None);
+        }
+
+        if self.next_iteration_requested() {
+            self.start_next_iteration();
+            notify_peer!(self, stream);
+            return Ok(None);
+        }
+
+        if self.run_cancelled() {
+            info!("{} run cancelled by the Coordinator", self.name());
+            self.state = S::done();
+            notify_peer!(self, stream);
+            return Ok(None);
+        }
+
+        match self.state.action() {
+            ProcessAction::AwaitPeer => self.await_next_msg(stream).await,
+            ProcessAction::Spawn => {
+                let delay = self.start_delay();
+                let scheduled = if delay > MAX_START_DELAY {
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "start scheduled {:?} away exceeds the max start delay of {:?}",
+                            delay, MAX_START_DELAY
+                        ),
+                    ))
+                } else {
+                    // Wait in the poll loop, heartbeating, until the start is within a
+                    // heartbeat interval. The rest is slept so that the start isn't
+                    // delayed by the poll delay.
+                    let interval = stream.heartbeat_interval();
+                    if interval.is_some_and(|interval| delay > interval) {
+                        debug!("{} starting in {:?}", self.name(), delay);
+                        self.heartbeat(stream).await?;
+                        return Ok(None);
+                    }
+                    if !delay.is_zero() {
+                        info!("{} starting in {:?}", self.name(), delay);
+                        self.heartbeat(stream).await?;
+                        tokio::time::sleep(delay).await;
+                    }
+                    Ok(())
+                };
+
+                let started_at_us = clock::now_us();
+                match scheduled.and_then(|()| self.spawn()) {
+                    Ok(pid) => {
+                        self.started_at_us = Some(started_at_us);
+                        *self.state_mut() = S::running(pid);
+                    }
+                    Err(err) => {
+                        // report the failed run rather than take down the Worker
+                        error!(
+                            "{} failed to start {}: {}",
+                            self.name, self.spec.program, err
+                        );
+                        let exit = ProcessExit::spawn_failed(&err);
+                        self.event_recorder.record_process_exit(exit.clone());
+                        self.exit = Some(exit);
+                        *self.state_mut() = S::stopped();
+                    }
+                }
+                notify_peer!(self, stream);
+                Ok(None)
+            }
+            ProcessAction::Kill(pid) => {
+                self.heartbeat(stream).await?;
+
+                if self.terminate() {
+                    self.send_results(stream).await?;
+                    self.transition_self_or_user_driven(stream).await?;
+                } else {
+                    debug!("{} waiting for pid: {} to stop", self.name(), pid);
+                }
+                Ok(None)
+            }
+            ProcessAction::AwaitExit(pid) => {
+                self.heartbeat(stream).await?;
+
+                let exited = match self.try_wait() {
+                    Ok(Some(status)) => {
+                        self.record_exit(status, false);
+                        true
+                    }
+                    Ok(None) => false,
+                    Err(err) => {
+                        self.record_wait_error(&err);
+                        true
+                    }
+                };
+                if exited {
+                    self.send_results(stream).await?;
+                    self.transition_self_or_user_driven(stream).await?;
+                } else {
+                    debug!("{} process still running. pid: {}", self.name(), pid);
+                }
+                Ok(None)
+            }
+            ProcessAction::Finished => {
+                notify_peer!(self, stream);
+                Ok(None)
+            }
+        }
+    }
+
+    fn event_recorder(&mut self) -> &mut EventRecorder {
+        &mut self.event_recorder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::russula::{
+        netbench::client::{CoordState, WorkerState},
+        transport::memory::{Faults, MemoryTransport},
+    };
+
+    // Runs a fixed process
+    impl ProcessContext for ProcessSpec {
+        type Run = ();
+
+        fn spec(&self, _name: &str, _run: Option<&()>) -> ProcessSpec {
+            self.clone()
+        }
+    }
+
+    type TestWorker = ProcessWorkerProtocol<WorkerState, ProcessSpec>;
+
+    // Runs the shell command sent by the Coordinator
+    #[derive(Clone, Debug)]
+    struct ShellContext;
+
+    impl ProcessContext for ShellContext {
+        type Run = String;
+
+        fn spec(&self, _name: &str, run: Option<&String>) -> ProcessSpec {
+            let cmd = run.map_or("exit 0", String::as_str);
+            ProcessSpec::new("sh").with_args(["-c", cmd])
+        }
+    }
+
+    fn run_worker_msg(run: serde_json::Value) -> Msg {
+        let payload = CoordPayload {
+            run: Some(run),
+            ..Default::default()
+        };
+        Envelope {
+            seq: 0,
+            sender: "coord".to_string(),
+            state: CoordState::RunWorker,
+            payload: Some(serde_json::to_value(payload).unwrap()),
+        }
+        .as_msg()
+    }
+
+    #[test]
+    fn spec_command() {
+        let spec = ProcessSpec::new("iperf3")
+            .with_args(["-c", "127.0.0.1"])
+            .with_env("TRACE", "stdio");
+
+        let cmd = spec.command();
+        assert_eq!(cmd.get_program(), "iperf3");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), ["-c", "127.0.0.1"]);
+        assert_eq!(
+            cmd.get_envs().collect::<Vec<_>>(),
+            [("TRACE".as_ref(), Some("stdio".as_ref()))]
+        );
+    }
+
+    #[test]
+    fn run_params() {
+        let params = RunParams {
+            envs: BTreeMap::from([("TRACE".to_string(), "disabled".to_string())]),
+            args: vec!["--gso".to_string(), "false".to_string()],
+        };
+        let spec = ProcessSpec::new("iperf3")
+            .with_args(["-c", "127.0.0.1"])
+            .with_env("TRACE", "stdio")
+            .with_params(params);
+
+        let cmd = spec.command();
+        assert_eq!(
+            cmd.get_args().collect::<Vec<_>>(),
+            ["-c", "127.0.0.1", "--gso", "false"]
+        );
+        // the run parameters override the env set by the Worker
+        let trace = cmd.get_envs().find(|(key, _)| *key == "TRACE").unwrap();
+        assert_eq!(trace.1, Some("disabled".as_ref()));
+
+        assert_eq!(
+            RunParams::parse_env("CC=bbr=2"),
+            Ok(("CC".to_string(), "bbr=2".to_string()))
+        );
+        assert!(RunParams::parse_env("=bbr").is_err());
+        assert!(RunParams::parse_env("bbr").is_err());
+    }
+
+    #[test]
+    fn iteration_output() {
+        let path = Path::new("results/server-0-s2n-quic.json");
+        assert_eq!(iteration_path(path, 0), path);
+        assert_eq!(
+            iteration_path(path, 2),
+            Path::new("results/iter2-server-0-s2n-quic.json")
+        );
+    }
+
+    fn wait_until(mut done: impl FnMut() -> bool) {
+        for _ in 0..500 {
+            if done() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("timed out");
+    }
+
+    #[test]
+    fn process_exit() {
+        let dir = tempdir::TempDir::new("process_exit").unwrap();
+        let stderr_path = dir.path().join("worker.stderr.log");
+        let spec = ProcessSpec::new("sh")
+            .with_args(["-c", "echo oops >&2; exit 3"])
+            .with_stderr_path(&stderr_path);
+        let mut worker = TestWorker::from_context("w".to_string(), "test", spec);
+
+        worker.spawn().unwrap();
+        let mut status = None;
+        wait_until(|| {
+            status = worker.try_wait().unwrap();
+            status.is_some()
+        });
+        let exit = worker.record_exit(status.unwrap(), false);
+        assert_eq!(exit.code, Some(3));
+        assert!(!exit.killed);
+        assert!(!exit.success());
+        assert_eq!(exit.stderr_tail.as_deref(), Some("oops"));
+    }
+
+    #[test]
+    fn run_parameters() {
+        let mut worker = ProcessWorkerProtocol::<WorkerState, ShellContext>::from_context(
+            "w".to_string(),
+            "test",
+            ShellContext,
+        );
+        worker
+            .update_peer_state(run_worker_msg(serde_json::json!("exit 4")))
+            .unwrap();
+
+        worker.spawn().unwrap();
+        assert_eq!(worker.spec.args, ["-c", "exit 4"]);
+        let mut status = None;
+        wait_until(|| {
+            status = worker.try_wait().unwrap();
+            status.is_some()
+        });
+        assert_eq!(worker.record_exit(status.unwrap(), false).code, Some(4));
+
+        // run parameters which the Worker can't interpret are rejected
+        assert!(matches!(
+            worker.update_peer_state(run_worker_msg(serde_json::json!(4))),
+            Err(RussulaError::BadMsg { .. })
+        ));
+    }
+
+    #[test]
+    fn process_killed() {
+        let spec = ProcessSpec::new("sleep").with_args(["10"]);
+        let mut worker = TestWorker::from_context("w".to_string(), "test", spec);
+
+        worker.spawn().unwrap();
+        assert!(worker.try_wait().unwrap().is_none());
+        wait_until(|| worker.terminate());
+        let exit = worker.exit.clone().unwrap();
+        assert_eq!(exit.code, None);
+        assert_eq!(exit.signal, Some(libc::SIGTERM));
+        assert!(exit.killed);
+        assert!(exit.success());
+
+        // the exit status is sent to the Coordinator
+        let payload: WorkerPayload = serde_json::from_value(worker.payload().unwrap()).unwrap();
+        assert_eq!(payload.exit, Some(exit));
+    }
+
+    #[test]
+    fn max_run_duration() {
+        let spec = ProcessSpec::new("sleep")
+            .with_args(["10"])
+            .with_max_run_duration(Some(Duration::from_millis(50)));
+        let mut worker = TestWorker::from_context("w".to_string(), "test", spec);
+
+        let pid = worker.spawn().unwrap();
+        assert_eq!(worker.run_deadline_passed(), None);
+        wait_until(|| worker.run_deadline_passed() == Some(pid));
+
+        worker.timed_out = true;
+        wait_until(|| worker.terminate());
+        let exit = worker.exit.clone().unwrap();
+        assert!(exit.timed_out);
+        assert!(!exit.success());
+    }
+
+    #[test]
+    fn kill_process_group() {
+        let dir = tempdir::TempDir::new("kill_process_group").unwrap();
+        let stdout_path = dir.path().join("worker.log");
+        // ignore SIGTERM and start a child, like the collector starting a driver
+        let spec = ProcessSpec::new("sh")
+            .with_args(["-c", "trap '' TERM; sleep 30 & echo $!; wait"])
+            .with_stdout_path(&stdout_path)
+            .with_process_group()
+            .with_kill_grace(Duration::from_millis(100));
+        let mut worker = TestWorker::from_context("w".to_string(), "test", spec);
+
+        worker.spawn().unwrap();
+        let mut child_pid = String::new();
+        wait_until(|| {
+            child_pid = std::fs::read_to_string(&stdout_path).unwrap();
+            child_pid.ends_with('\n')
+        });
+
+        wait_until(|| worker.terminate());
+        let exit = worker.exit.clone().unwrap();
+        assert_eq!(exit.signal, Some(libc::SIGKILL));
+        assert!(exit.killed);
+
+        // the child was killed along with the group leader
+        let stat = format!("/proc/{}/stat", child_pid.trim());
+        wait_until(|| match std::fs::read_to_string(&stat) {
+            Ok(stat) => stat.contains(") Z "),
+            Err(_) => true,
+        });
+    }
+
+    fn scheduled_worker(start_in: Duration) -> (TestWorker, FramedStream, FramedStream) {
+        let (coord, worker) = MemoryTransport::pair(Faults::default(), Faults::default());
+        let mut stream = FramedStream::new(Box::new(worker));
+        stream.set_heartbeat_interval(Some(Duration::from_millis(100)));
+        let mut worker = TestWorker::from_context(
+            "w".to_string(),
+            "test",
+            ProcessSpec::new("sh").with_args(["-c", "exit 0"]),
+        );
+        worker.state = WorkerState::Run;
+        worker.start_at_us = Some(clock::now_us() + start_in.as_micros() as u64);
+        (worker, stream, FramedStream::new(Box::new(coord)))
+    }
+
+    #[tokio::test]
+    async fn scheduled_start() {
+        let (mut worker, mut stream, _coord) = scheduled_worker(Duration::from_millis(500));
+        let start_at_us = worker.start_at_us.unwrap();
+
+        // the Worker returns to the poll loop while the start is more than a
+        // heartbeat interval away
+        worker.run(&mut stream).await.unwrap();
+        assert!(matches!(worker.state, WorkerState::Run));
+
+        let mut polls = 1;
+        while matches!(worker.state, WorkerState::Run) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            worker.run(&mut stream).await.unwrap();
+            polls += 1;
+        }
+        assert!(polls > 2);
+        assert!(worker.is_worker_running_state());
+        assert!(worker.started_at_us.unwrap() >= start_at_us);
+        assert!(worker.exit.is_none());
+    }
+
+    #[tokio::test]
+    async fn scheduled_start_too_far() {
+        let (mut worker, mut stream, _coord) =
+            scheduled_worker(MAX_START_DELAY + Duration::from_secs(60));
+
+        // reported as a failed run rather than waiting
+        worker.run(&mut stream).await.unwrap();
+        assert!(worker.is_worker_stopped_state());
+        let exit = worker.exit.clone().unwrap();
+        assert!(exit.spawn_error.unwrap().contains("max start delay"));
+    }
+
+    #[test]
+    fn port_release() {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(!port_free(port));
+
+        drop(listener);
+        assert!(port_free(port));
+    }
+}
@@ -2,21 +2,132 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::{
+    auth::{AuthChallenge, AuthResponse, AuthToken},
+    clock::{self, ClockOffset},
     error::RussulaError,
     event::EventType,
     network_utils,
-    network_utils::Msg,
-    states::{StateApi, TransitionStep},
+    network_utils::{FramedStream, Msg},
+    states::{Envelope, StateApi, TransitionStep},
+    transport::{Network, Transport},
     RussulaResult,
 };
-use crate::russula::event::EventRecorder;
+use crate::russula::{event::EventRecorder, VERSION};
+use bytes::Bytes;
 use core::{task::Poll, time::Duration};
 use paste::paste;
-use std::net::SocketAddr;
-use tokio::net::TcpStream;
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, path::PathBuf, time::SystemTime};
 use tracing::{debug, info};
 
-const NOTIFY_DONE_TIMEOUT: Duration = Duration::from_secs(1);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Version of the Russula wire protocol.
+///
+/// Should be bumped when the framing or msg format changes.
+pub const PROTOCOL_VERSION: u16 = 4;
+
+/// The first msg exchanged by a Coordinator and Worker after connecting.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handshake {
+    protocol: String,
+    protocol_version: u16,
+    version: String,
+    /// The sender requires the peer to authenticate with an [`AuthToken`]
+    auth: bool,
+}
+
+impl Handshake {
+    fn new(protocol: &str, auth: bool) -> Self {
+        Handshake {
+            protocol: protocol.to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            version: VERSION.to_string(),
+            auth,
+        }
+    }
+
+    // The peers only disagree on whether to authenticate
+    fn auth_mismatch(&self, other: &Self) -> bool {
+        self.auth != other.auth
+            && self.protocol == other.protocol
+            && self.protocol_version == other.protocol_version
+            && self.version == other.version
+    }
+
+    fn as_msg(&self) -> Msg {
+        Msg::new(Bytes::from(serde_json::to_string(self).unwrap()))
+    }
+
+    fn from_msg(msg: &Msg) -> Option<Self> {
+        serde_json::from_slice(msg.as_bytes()).ok()
+    }
+}
+
+impl std::fmt::Display for Handshake {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{} {}",
+            self.protocol, self.protocol_version, self.version
+        )
+    }
+}
+
+/// Sent after the [`Handshake`] to estimate the clock offset between peers.
+#[derive(Debug, Serialize, Deserialize)]
+struct ClockSync {
+    /// Time the peer's Handshake was received
+    recv_at_us: u64,
+    /// Time this msg was sent
+    sent_at_us: u64,
+}
+
+impl ClockSync {
+    fn as_msg(&self) -> Msg {
+        Msg::new(Bytes::from(serde_json::to_string(self).unwrap()))
+    }
+
+    fn from_msg(msg: &Msg) -> RussulaResult<Self> {
+        serde_json::from_slice(msg.as_bytes()).map_err(|err| RussulaError::BadMsg {
+            dbg: format!("received a malformed ClockSync msg: {}", err),
+        })
+    }
+}
+
+// Wait for the next msg during the handshake
+async fn recv_handshake_msg(stream: &mut FramedStream, name: &str) -> RussulaResult<Msg> {
+    let recv = async {
+        loop {
+            match network_utils::recv_msg(stream).await {
+                Err(RussulaError::NetworkBlocked { dbg: _ }) => continue,
+                res => break res,
+            }
+        }
+    };
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, recv)
+        .await
+        .map_err(|_err| RussulaError::NetworkFail {
+            dbg: format!("{} timed out waiting for handshake", name),
+        })?
+}
+
+// Exchange challenges with the peer and check that it knows the `auth_token`
+async fn authenticate(
+    stream: &mut FramedStream,
+    auth_token: &AuthToken,
+    protocol: &str,
+    name: &str,
+) -> RussulaResult<()> {
+    let local = AuthChallenge::new();
+    network_utils::send_msg(stream, local.as_msg()).await?;
+    let peer = AuthChallenge::from_msg(&recv_handshake_msg(stream, name).await?)?;
+
+    let response = auth_token.respond(protocol, &peer, &local);
+    network_utils::send_msg(stream, response.as_msg()).await?;
+    let peer_response = AuthResponse::from_msg(&recv_handshake_msg(stream, name).await?)?;
+    auth_token.verify(protocol, &local, &peer, &peer_response)
+}
 
 macro_rules! state_api {
 {
@@ -38,15 +149,21 @@ macro_rules! state_api {
 
 macro_rules! notify_peer {
 {$protocol:ident, $stream:ident} => {
-    use crate::russula::network_utils;
-    let msg = Msg::new($protocol.state().as_bytes());
+    use crate::russula::{network_utils, states::Envelope};
+    let envelope = Envelope {
+        seq: $stream.next_seq(),
+        sender: $protocol.name(),
+        state: $protocol.state().clone(),
+        payload: $protocol.payload(),
+    };
+    let msg = envelope.as_msg();
     debug!(
         "{} ----> send msg {}",
         $protocol.name(),
         std::str::from_utf8(&msg.data).unwrap()
     );
-    network_utils::send_msg($stream, msg).await?;
-    $protocol.on_event(EventType::SendMsg);
+    let len = network_utils::send_msg($stream, msg).await?;
+    $protocol.on_event(EventType::SendMsg(len));
 }
 }
 pub(crate) use notify_peer;
@@ -54,28 +171,35 @@ pub(crate) use notify_peer;
 pub trait Protocol: Clone {
     type State: StateApi;
 
-    async fn connect(&self, addr: &SocketAddr) -> RussulaResult<TcpStream>;
-    async fn run(&mut self, stream: &TcpStream) -> RussulaResult<Option<Msg>>;
+    /// Establish a connection with the peer over `network`.
+    ///
+    /// Called again to re-establish the connection if it is dropped, so Workers
+    /// should keep listening on `addr` after the first accept.
+    async fn connect(
+        &mut self,
+        network: &Network,
+        addr: &SocketAddr,
+    ) -> RussulaResult<Box<dyn Transport>>;
+    /// Name shared by the Coordinator and Worker of a protocol pair.
+    fn protocol_name(&self) -> &'static str;
+    async fn run(&mut self, stream: &mut FramedStream) -> RussulaResult<Option<Msg>>;
     fn name(&self) -> String;
     fn update_peer_state(&mut self, msg: Msg) -> RussulaResult<()>;
     fn state(&self) -> &Self::State;
     fn state_mut(&mut self) -> &mut Self::State;
     fn event_recorder(&mut self) -> &mut EventRecorder;
 
+    /// Optional data sent to the peer along with the current state
+    fn payload(&self) -> Option<serde_json::Value> {
+        None
+    }
+
     // Ready ==============
     state_api!(ready);
-    async fn poll_ready(&mut self, stream: &TcpStream) -> RussulaResult<Poll<()>> {
-        let state = self.ready_state();
-        self.poll_state(stream, &state).await
-    }
 
     // Done ==============
     // state_api!(done);
     fn done_state(&self) -> Self::State;
-    async fn poll_done(&mut self, stream: &TcpStream) -> RussulaResult<Poll<()>> {
-        let state = self.done_state();
-        self.poll_state(stream, &state).await
-    }
 
     /// Done is the only State with TransitionStep::Finished
     fn is_done_state(&self) -> bool {
@@ -91,16 +215,168 @@ pub trait Protocol: Clone {
         /// Should only be called by Coordinators
         worker_running
     );
-    /// Check if worker the Instance is Running
-    async fn poll_worker_running(&mut self, stream: &TcpStream) -> RussulaResult<Poll<()>> {
-        let state = self.worker_running_state();
-        self.poll_state(stream, &state).await
+
+    // Stopped ==============
+    state_api!(
+        /// Should only be called by Coordinators
+        worker_stopped
+    );
+
+    /// Exchange a [`Handshake`] with the peer, which is expected to be the other half
+    /// of the protocol pair and built from the same version. If an `auth_token` is
+    /// provided the peer must also prove that it knows the token.
+    ///
+    /// Returns an estimate of the peer's clock offset, measured from the time each
+    /// peer received the other's handshake.
+    async fn handshake(
+        &self,
+        stream: &mut FramedStream,
+        auth_token: Option<&AuthToken>,
+    ) -> RussulaResult<ClockOffset> {
+        let local = Handshake::new(self.protocol_name(), auth_token.is_some());
+        let sent_at_us = clock::now_us();
+        network_utils::send_msg(stream, local.as_msg()).await?;
+
+        let msg = recv_handshake_msg(stream, &self.name()).await?;
+        let recv_at_us = clock::now_us();
+        match Handshake::from_msg(&msg) {
+            Some(peer) if peer == local => {
+                info!("{} handshake complete: {}", self.name(), local);
+            }
+            Some(peer) if peer.auth_mismatch(&local) => {
+                return Err(RussulaError::AuthFailed {
+                    dbg: format!(
+                        "auth token required by local: {} peer: {}",
+                        local.auth, peer.auth
+                    ),
+                })
+            }
+            // the wrong type of peer connected, such as a server Worker to a
+            // client Coordinator, which a newer build won't fix
+            Some(peer) if peer.protocol != local.protocol => {
+                return Err(RussulaError::ProtocolMismatch {
+                    dbg: format!("expected a {} peer but found {}", local.protocol, peer),
+                })
+            }
+            Some(peer) => {
+                return Err(RussulaError::VersionMismatch {
+                    local: local.to_string(),
+                    peer: peer.to_string(),
+                })
+            }
+            None => {
+                return Err(RussulaError::BadMsg {
+                    dbg: format!(
+                        "received a malformed Handshake msg: {}",
+                        String::from_utf8_lossy(&msg.data)
+                    ),
+                })
+            }
+        }
+
+        if let Some(auth_token) = auth_token {
+            authenticate(stream, auth_token, self.protocol_name(), &self.name()).await?;
+            info!("{} authenticated peer", self.name());
+        }
+
+        let sync = ClockSync {
+            recv_at_us,
+            sent_at_us: clock::now_us(),
+        };
+        network_utils::send_msg(stream, sync.as_msg()).await?;
+        let peer = ClockSync::from_msg(&recv_handshake_msg(stream, &self.name()).await?)?;
+        let clock_offset = ClockOffset::estimate(
+            sent_at_us,
+            peer.recv_at_us,
+            peer.sent_at_us,
+            clock::now_us(),
+        );
+        debug!("{} peer clock offset: {:?}", self.name(), clock_offset);
+        Ok(clock_offset)
+    }
+
+    /// Record the peer's clock offset measured during the handshake
+    fn set_clock_offset(&mut self, _clock_offset: ClockOffset) {}
+
+    /// Schedule Workers to start at the wall-clock time `start_at`.
+    ///
+    /// Should only be implemented by Coordinators.
+    fn set_start_at(&mut self, _start_at: SystemTime) {}
+
+    /// Parameters of the next run, sent to the Workers along with the State which
+    /// starts the run.
+    ///
+    /// Should only be implemented by Coordinators.
+    fn set_run(&mut self, _run: serde_json::Value) {}
+
+    /// Ask the Workers to stream their result files, which are written under `dir`.
+    ///
+    /// Should only be implemented by Coordinators.
+    fn set_results_dir(&mut self, _dir: PathBuf) {}
+
+    /// The number of result files written under the results dir.
+    ///
+    /// Should only be implemented by Coordinators.
+    fn results_written(&self) -> usize {
+        0
+    }
+
+    /// Run the Workers again, instead of moving to Done, once they have stopped.
+    ///
+    /// Should only be implemented by Coordinators.
+    fn next_iteration(&mut self) {}
+
+    /// A failure reported by the peer, such as the Worker process exiting with an
+    /// error. Checked once coordination is Done.
+    ///
+    /// Should only be implemented by Coordinators.
+    fn peer_failure(&self) -> Option<RussulaError> {
+        None
+    }
+
+    /// False until the Coordinator moves the Workers to run their process.
+    ///
+    /// Should only be implemented by Coordinators.
+    fn workers_started(&self) -> bool {
+        true
+    }
+
+    /// Move straight to Done if the Workers haven't been started, such as when
+    /// another peer failed, rather than running them only to stop them. Workers
+    /// which see the Coordinator Done before they start also move to Done.
+    async fn cancel(&mut self, stream: &mut FramedStream) -> RussulaResult<()> {
+        if self.workers_started() || self.is_done_state() {
+            return Ok(());
+        }
+
+        let prev = self.state().clone();
+        *self.state_mut() = self.done_state();
+        info!(
+            "{:?} CANCELLING RUN. {:?} ===> {:?}",
+            self.name(),
+            prev,
+            self.state()
+        );
+        self.on_event(EventType::Transition {
+            from: format!("{:?}", prev),
+            to: format!("{:?}", self.state()),
+        });
+        notify_peer!(self, stream);
+        Ok(())
+    }
+
+    /// Report the current state to the peer after (re)establishing a connection so
+    /// that the state machine can resume from where it left off.
+    async fn resume(&mut self, stream: &mut FramedStream) -> RussulaResult<()> {
+        info!("{} resuming at state: {:?}", self.name(), self.state());
+        notify_peer!(self, stream);
+        Ok(())
     }
 
     // If the peer is not at the desired state then attempt to make progress
     async fn poll_state(
         &mut self,
-        stream: &TcpStream,
+        stream: &mut FramedStream,
         state: &Self::State,
     ) -> RussulaResult<Poll<()>> {
         if !self.state().eq(state) {
@@ -112,27 +388,9 @@ pub trait Protocol: Clone {
                 prev,
                 self.state()
             );
-        }
 
-        // Notify the peer that we have reached a terminal state
-        if self.is_done_state() {
-            tracing::info!("{}", self.event_recorder());
-
-            // Notify 3 time in case of packet loss.. this is best effort
-            for _i in 0..3 {
-                match self.run_current(stream).await {
-                    Ok(_) => (),
-                    // We notify the peer of the Done state multiple times. Since the peer could
-                    // have killed the connection in the meantime, its better to ignore network
-                    // failures
-                    Err(RussulaError::NetworkConnectionRefused { dbg: _ })
-                    | Err(RussulaError::NetworkBlocked { dbg: _ })
-                    | Err(RussulaError::NetworkFail { dbg: _ }) => {
-                        debug!("Ignore network failure since coordination is Done.")
-                    }
-                    Err(err) => return Err(err),
-                }
-                tokio::time::sleep(NOTIFY_DONE_TIMEOUT).await;
+            if self.is_done_state() {
+                tracing::info!("{}", self.event_recorder());
             }
         }
 
@@ -145,14 +403,23 @@ pub trait Protocol: Clone {
     }
 
     // run action for the current state and update the peer state
-    async fn run_current(&mut self, stream: &TcpStream) -> RussulaResult<()> {
-        if let Some(msg) = self.run(stream).await? {
+    async fn run_current(&mut self, stream: &mut FramedStream) -> RussulaResult<()> {
+        let prev = self.state().clone();
+        let run = self.run(stream).await;
+        if !prev.eq(self.state()) {
+            self.on_event(EventType::Transition {
+                from: format!("{:?}", prev),
+                to: format!("{:?}", self.state()),
+            });
+        }
+
+        if let Some(msg) = run? {
             self.update_peer_state(msg)?;
         }
         Ok(())
     }
 
-    async fn await_next_msg(&mut self, stream: &TcpStream) -> RussulaResult<Option<Msg>> {
+    async fn await_next_msg(&mut self, stream: &mut FramedStream) -> RussulaResult<Option<Msg>> {
         if !matches!(self.state().transition_step(), TransitionStep::AwaitNext(_)) {
             panic!(
                 "expected AwaitNext but found: {:?}",
@@ -160,35 +427,45 @@ pub trait Protocol: Clone {
             );
         }
         // loop until we receive a transition msg from peer or drain all msg from queue.
-        // recv_msg aborts if the read queue is empty
+        // try_recv_msg returns NetworkBlocked once the read queue is empty
         let mut last_msg = None;
         // Continue to read from stream until:
         // - the msg results in a transition
         // - there is no more data available (drained all messages)
         // - there is a error while reading
         loop {
-            match network_utils::recv_msg(stream).await {
+            match network_utils::try_recv_msg(stream) {
                 Ok(msg) => {
-                    self.on_event(EventType::RecvMsg);
+                    self.on_event(EventType::RecvMsg(msg.frame_len()));
                     debug!(
                         "{} <---- recv msg {}",
                         self.name(),
                         std::str::from_utf8(&msg.data).unwrap()
                     );
 
-                    let should_transition = self.matches_transition_msg(&msg)?;
-                    last_msg = Some(msg);
+                    let peer = Envelope::from_msg(&msg)?;
+                    let should_transition = self.matches_transition_msg(&peer);
+                    let peer_finished =
+                        matches!(peer.state.transition_step(), TransitionStep::Finished);
+                    // only the last msg is returned so process the earlier ones now,
+                    // since they can carry data such as result chunks
+                    if let Some(prev_msg) = last_msg.replace(msg) {
+                        self.update_peer_state(prev_msg)?;
+                    }
                     if should_transition {
                         self.transition_next(stream).await?;
                         break;
                     }
+                    // the peer is free to close the connection once it's Finished, so
+                    // act on its State rather than fail reading past it
+                    if peer_finished {
+                        break;
+                    }
                 }
                 Err(RussulaError::NetworkBlocked { dbg: _ }) => {
-                    // This might not be extra since a protocol needs to be polled
-                    // to make progress
-                    //
-                    // notify the peer and make progress
-                    notify_peer!(self, stream);
+                    // Drained all msgs. Let the peer know we are still alive while
+                    // waiting for the next msg
+                    self.heartbeat(stream).await?;
                     break;
                 }
                 Err(err) => return Err(err),
@@ -198,24 +475,41 @@ pub trait Protocol: Clone {
         Ok(last_msg)
     }
 
-    fn matches_transition_msg(&self, recv_msg: &Msg) -> RussulaResult<bool> {
-        let state = self.state();
-        if let TransitionStep::AwaitNext(expected_msg) = state.transition_step() {
-            let should_transition_to_next = expected_msg == recv_msg.as_bytes();
+    /// Re-send the current state if nothing has been sent to the peer within the
+    /// heartbeat interval
+    async fn heartbeat(&mut self, stream: &mut FramedStream) -> RussulaResult<()> {
+        if stream.should_heartbeat() {
+            debug!("{} ----> heartbeat", self.name());
+            notify_peer!(self, stream);
+        }
+        Ok(())
+    }
+
+    fn matches_transition_msg(
+        &mut self,
+        peer: &Envelope<<Self::State as StateApi>::PeerState>,
+    ) -> bool {
+        if let TransitionStep::AwaitNext(expected_state) = self.state().transition_step() {
+            self.event_recorder()
+                .record_peer_state(format!("{:?}", peer.state));
+            let should_transition_to_next = expected_state.eq(&peer.state);
             debug!(
-                "{} expect: {} actual: {}",
+                "{} expect: {:?} actual: {:?}",
                 self.name(),
-                std::str::from_utf8(&expected_msg).unwrap(),
-                std::str::from_utf8(&recv_msg.data).unwrap()
+                expected_state,
+                peer.state
             );
-            Ok(should_transition_to_next)
+            should_transition_to_next
         } else {
-            Ok(false)
+            false
         }
     }
 
-    async fn transition_next(&mut self, stream: &TcpStream) -> RussulaResult<()> {
-        let nxt = self.state().next_state();
+    async fn transition_next(&mut self, stream: &mut FramedStream) -> RussulaResult<()> {
+        let nxt = self
+            .state()
+            .next_state()
+            .unwrap_or_else(|| panic!("{} {:?} has no next state", self.name(), self.state()));
         info!(
             "{:?} MOVING TO NEXT STATE. {:?} ===> {:?}",
             self.name(),
@@ -228,7 +522,10 @@ pub trait Protocol: Clone {
         Ok(())
     }
 
-    async fn transition_self_or_user_driven(&mut self, stream: &TcpStream) -> RussulaResult<()> {
+    async fn transition_self_or_user_driven(
+        &mut self,
+        stream: &mut FramedStream,
+    ) -> RussulaResult<()> {
         let state = self.state();
         assert!(
             matches!(state.transition_step(), TransitionStep::SelfDriven)
@@ -242,3 +539,119 @@ pub trait Protocol: Clone {
         self.event_recorder().process(event);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::russula::{
+        netbench::{self, client, server},
+        transport::memory::{Faults, MemoryTransport},
+    };
+
+    async fn stream_pair() -> (FramedStream, FramedStream) {
+        let (coord, worker) = MemoryTransport::pair(Faults::default(), Faults::default());
+        (
+            FramedStream::new(Box::new(coord)),
+            FramedStream::new(Box::new(worker)),
+        )
+    }
+
+    #[tokio::test]
+    async fn handshake() {
+        let (mut coord_stream, mut worker_stream) = stream_pair().await;
+        let coord = server::CoordProtocol::new();
+        let worker =
+            server::WorkerProtocol::new("0".to_string(), netbench::ServerContext::testing());
+
+        let (coord, worker) = tokio::join!(
+            coord.handshake(&mut coord_stream, None),
+            worker.handshake(&mut worker_stream, None)
+        );
+        // both peers share a clock
+        for clock_offset in [coord.unwrap(), worker.unwrap()] {
+            assert!(clock_offset.offset_us.unsigned_abs() <= clock_offset.rtt_us);
+        }
+    }
+
+    #[tokio::test]
+    async fn handshake_protocol_mismatch() {
+        let (mut coord_stream, mut worker_stream) = stream_pair().await;
+        let coord = client::CoordProtocol::new();
+        let worker =
+            server::WorkerProtocol::new("0".to_string(), netbench::ServerContext::testing());
+
+        let (coord, worker) = tokio::join!(
+            coord.handshake(&mut coord_stream, None),
+            worker.handshake(&mut worker_stream, None)
+        );
+        assert!(matches!(coord, Err(RussulaError::ProtocolMismatch { .. })));
+        assert!(matches!(worker, Err(RussulaError::ProtocolMismatch { .. })));
+    }
+
+    #[tokio::test]
+    async fn handshake_version_mismatch() {
+        let (mut coord_stream, mut worker_stream) = stream_pair().await;
+        let coord = server::CoordProtocol::new();
+
+        // a worker built from a different version
+        let worker = Handshake {
+            version: "v0.0.0".to_string(),
+            ..Handshake::new(coord.protocol_name(), false)
+        };
+        network_utils::send_msg(&mut worker_stream, worker.as_msg())
+            .await
+            .unwrap();
+
+        match coord.handshake(&mut coord_stream, None).await {
+            Err(RussulaError::VersionMismatch { local, peer }) => {
+                assert!(local.ends_with(VERSION));
+                assert!(peer.ends_with("v0.0.0"));
+            }
+            res => panic!("expected VersionMismatch but found: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn handshake_malformed() {
+        let (mut coord_stream, mut worker_stream) = stream_pair().await;
+        let coord = server::CoordProtocol::new();
+
+        // a peer which doesn't speak Russula
+        let msg = Msg::new(Bytes::from_static(b"GET / HTTP/1.1"));
+        network_utils::send_msg(&mut worker_stream, msg)
+            .await
+            .unwrap();
+
+        let res = coord.handshake(&mut coord_stream, None).await;
+        assert!(matches!(res, Err(RussulaError::BadMsg { .. })), "{:?}", res);
+    }
+
+    #[tokio::test]
+    async fn handshake_auth() {
+        let coord = server::CoordProtocol::new();
+        let worker =
+            server::WorkerProtocol::new("0".to_string(), netbench::ServerContext::testing());
+        let token = AuthToken::new("secret");
+        let other_token = AuthToken::new("guess");
+
+        let (mut coord_stream, mut worker_stream) = stream_pair().await;
+        let (coord_res, worker_res) = tokio::join!(
+            coord.handshake(&mut coord_stream, Some(&token)),
+            worker.handshake(&mut worker_stream, Some(&token))
+        );
+        assert!(coord_res.is_ok());
+        assert!(worker_res.is_ok());
+
+        for (coord_token, worker_token) in
+            [(Some(&token), Some(&other_token)), (Some(&token), None)]
+        {
+            let (mut coord_stream, mut worker_stream) = stream_pair().await;
+            let (coord_res, worker_res) = tokio::join!(
+                coord.handshake(&mut coord_stream, coord_token),
+                worker.handshake(&mut worker_stream, worker_token)
+            );
+            assert!(matches!(coord_res, Err(RussulaError::AuthFailed { .. })));
+            assert!(matches!(worker_res, Err(RussulaError::AuthFailed { .. })));
+        }
+    }
+}
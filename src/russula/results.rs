@@ -0,0 +1,225 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::russula::{
+    error::{RussulaError, RussulaResult},
+    event::{EventRecorder, EventType},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+};
+use tracing::{error, info};
+
+// Number of bytes of a result file sent in a single msg. Base64 encoding grows
+// the chunk by a third, which leaves room for the rest of the msg within the
+// max frame length.
+const RESULT_CHUNK_LEN: usize = 32 * 1024;
+
+/// A piece of a result file streamed by a Worker to the Coordinator once its
+/// process has stopped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResultChunk {
+    /// Path of the file relative to the Coordinator's results dir
+    pub path: PathBuf,
+    /// Offset of the chunk within the file
+    pub offset: u64,
+    /// Base64 encoded chunk of the file
+    pub data: String,
+    /// Base64 encoded SHA-256 of the chunk
+    pub checksum: String,
+    /// Base64 encoded SHA-256 of the whole file. Set on the last chunk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_checksum: Option<String>,
+}
+
+impl ResultChunk {
+    /// Split `file` into the chunks sent to the Coordinator. An empty file is sent
+    /// as a single empty chunk.
+    pub fn split(path: &Path, file: &[u8]) -> Vec<ResultChunk> {
+        let mut chunks: Vec<&[u8]> = file.chunks(RESULT_CHUNK_LEN).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+
+        let last = chunks.len() - 1;
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(idx, chunk)| ResultChunk {
+                path: path.to_path_buf(),
+                offset: (idx * RESULT_CHUNK_LEN) as u64,
+                data: STANDARD.encode(chunk),
+                checksum: checksum(chunk),
+                file_checksum: (idx == last).then(|| checksum(file)),
+            })
+            .collect()
+    }
+
+    // The chunk data after checking it against the checksum
+    fn decode(&self) -> RussulaResult<Vec<u8>> {
+        let data = STANDARD
+            .decode(&self.data)
+            .map_err(|err| bad_chunk(&self.path, err))?;
+        if checksum(&data) != self.checksum {
+            return Err(bad_chunk(&self.path, "chunk checksum mismatch"));
+        }
+        Ok(data)
+    }
+}
+
+/// Reassembles the result files streamed by a Worker and writes them under the
+/// results dir.
+#[derive(Debug, Clone)]
+pub struct ResultCollector {
+    dir: PathBuf,
+    // Files which are still being received
+    partial: BTreeMap<PathBuf, Vec<u8>>,
+    // Number of files written under the results dir
+    written: usize,
+}
+
+impl ResultCollector {
+    pub fn new(dir: PathBuf) -> Self {
+        ResultCollector {
+            dir,
+            partial: BTreeMap::new(),
+            written: 0,
+        }
+    }
+
+    /// The number of result files written under the results dir
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Add a chunk and write the file once its last chunk is received.
+    ///
+    /// A corrupt or out of order chunk is an error since the file can't be
+    /// reassembled. Failing to write the file is only recorded since the
+    /// coordination can still complete.
+    pub fn receive(
+        &mut self,
+        chunk: ResultChunk,
+        event_recorder: &mut EventRecorder,
+    ) -> RussulaResult<()> {
+        // the path is provided by the peer so it must stay within the results dir
+        let is_relative = chunk
+            .path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if chunk.path.as_os_str().is_empty() || !is_relative {
+            return Err(bad_chunk(&chunk.path, "path outside the results dir"));
+        }
+
+        let data = chunk.decode()?;
+        let file = self.partial.entry(chunk.path.clone()).or_default();
+        if chunk.offset != file.len() as u64 {
+            self.partial.remove(&chunk.path);
+            return Err(bad_chunk(&chunk.path, "chunk out of order"));
+        }
+        file.extend_from_slice(&data);
+
+        let Some(file_checksum) = &chunk.file_checksum else {
+            return Ok(());
+        };
+        let file = self.partial.remove(&chunk.path).unwrap_or_default();
+        if checksum(&file) != *file_checksum {
+            return Err(bad_chunk(&chunk.path, "file checksum mismatch"));
+        }
+
+        let path = self.dir.join(&chunk.path);
+        let write = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&path, &file));
+        match write {
+            Ok(()) => {
+                info!(
+                    "received result file {} ({} bytes)",
+                    path.display(),
+                    file.len()
+                );
+                self.written += 1;
+            }
+            Err(err) => {
+                let err = format!("failed to write result file {}: {}", path.display(), err);
+                error!("{}", err);
+                event_recorder.process(EventType::Error(err));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn checksum(data: &[u8]) -> String {
+    STANDARD.encode(Sha256::digest(data))
+}
+
+fn bad_chunk(path: &Path, err: impl std::fmt::Display) -> RussulaError {
+    RussulaError::BadMsg {
+        dbg: format!("bad result chunk for {}: {}", path.display(), err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reassemble() {
+        let dir = tempdir::TempDir::new("results").unwrap();
+        let mut collector = ResultCollector::new(dir.path().to_path_buf());
+        let mut events = EventRecorder::default();
+
+        let file: Vec<u8> = (0..RESULT_CHUNK_LEN * 2 + 10).map(|i| i as u8).collect();
+        let path = Path::new("request_response/s2n-quic/server-0-s2n-quic.json");
+        let chunks = ResultChunk::split(path, &file);
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks[..2]
+            .iter()
+            .all(|chunk| chunk.file_checksum.is_none()));
+
+        for chunk in chunks {
+            collector.receive(chunk, &mut events).unwrap();
+        }
+        assert_eq!(std::fs::read(dir.path().join(path)).unwrap(), file);
+
+        // an empty file is still written
+        let path = Path::new("empty.json");
+        let chunks = ResultChunk::split(path, &[]);
+        assert_eq!(chunks.len(), 1);
+        collector.receive(chunks[0].clone(), &mut events).unwrap();
+        assert!(std::fs::read(dir.path().join(path)).unwrap().is_empty());
+        assert_eq!(collector.written(), 2);
+        assert!(events.errors().is_empty());
+    }
+
+    #[test]
+    fn bad_chunks() {
+        let dir = tempdir::TempDir::new("results").unwrap();
+        let mut collector = ResultCollector::new(dir.path().to_path_buf());
+        let mut events = EventRecorder::default();
+        let file = vec![7; RESULT_CHUNK_LEN + 1];
+        let chunks = ResultChunk::split(Path::new("server.json"), &file);
+
+        let mut corrupt = chunks[0].clone();
+        corrupt.data = STANDARD.encode(vec![8; RESULT_CHUNK_LEN]);
+        assert!(matches!(
+            collector.receive(corrupt, &mut events),
+            Err(RussulaError::BadMsg { .. })
+        ));
+
+        // the last chunk arrives without the first
+        assert!(collector.receive(chunks[1].clone(), &mut events).is_err());
+
+        for path in ["../server.json", "/tmp/server.json", ""] {
+            let chunk = ResultChunk::split(Path::new(path), &file).remove(0);
+            assert!(collector.receive(chunk, &mut events).is_err());
+        }
+        assert!(!dir.path().join("server.json").exists());
+    }
+}
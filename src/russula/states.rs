@@ -5,45 +5,116 @@ use super::{error::RussulaError, network_utils::Msg};
 use crate::russula::RussulaResult;
 use bytes::Bytes;
 use core::fmt::Debug;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 #[derive(Debug)]
-pub enum TransitionStep {
+pub enum TransitionStep<P> {
     // State machine is responsible for moving to the next state
     SelfDriven,
     // Wait for user input before moving to the next state
     UserDriven,
     // Wait for a peer msg before moving to the next state
-    AwaitNext(Bytes),
+    //
+    // Only the variant of the peer state is compared so any data carried
+    // by the expected state is ignored.
+    AwaitNext(P),
     // Final step in the state machine with no next transitions
     Finished,
 }
 
-pub trait StateApi: Clone + Debug + Serialize + for<'a> Deserialize<'a> {
-    fn transition_step(&self) -> TransitionStep;
-    fn next_state(&self) -> Self;
+pub trait StateApi: Clone + Debug + Serialize + DeserializeOwned {
+    /// The State of the other half of the protocol pair
+    type PeerState: StateApi;
 
+    fn transition_step(&self) -> TransitionStep<Self::PeerState>;
+
+    /// The State moved to once the transition step completes.
+    ///
+    /// None for Finished States and for States whose next State carries data only
+    /// known at runtime, such as a pid, which the protocol sets itself.
+    fn next_state(&self) -> Option<Self>;
+
+    /// Compare the state variants, ignoring any data carried by the state
     fn eq(&self, other: &Self) -> bool {
-        self.as_bytes() == other.as_bytes()
+        core::mem::discriminant(self) == core::mem::discriminant(other)
     }
+}
 
-    fn as_bytes(&self) -> Bytes {
-        serde_json::to_string(self).unwrap().into()
-    }
+/// The msg exchanged between a Coordinator and Worker.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Envelope<S> {
+    /// Incremented for each msg sent on a connection
+    pub seq: u64,
+    /// The name of the protocol instance which sent the msg
+    pub sender: String,
+    pub state: S,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload: Option<serde_json::Value>,
+}
 
-    fn from_msg(msg: Msg) -> RussulaResult<Self> {
-        let msg_str = std::str::from_utf8(&msg.data).map_err(|_err| RussulaError::BadMsg {
-            dbg: format!(
-                "received a malformed msg. len: {} data: {:?}",
-                msg.len, msg.data
-            ),
-        })?;
+impl<S: StateApi> Envelope<S> {
+    pub fn as_msg(&self) -> Msg {
+        Msg::new(Bytes::from(serde_json::to_string(self).unwrap()))
+    }
 
-        serde_json::from_str(msg_str).map_err(|_err| RussulaError::BadMsg {
+    pub fn from_msg(msg: &Msg) -> RussulaResult<Self> {
+        serde_json::from_slice(&msg.data).map_err(|err| RussulaError::BadMsg {
             dbg: format!(
-                "received a malformed msg. len: {} data: {:?}",
-                msg.len, msg.data
+                "received a malformed msg. len: {} data: {:?} err: {}",
+                msg.len, msg.data, err
             ),
         })
     }
+
+    /// Decode the payload as `T`
+    pub fn payload_as<T: DeserializeOwned>(&self) -> RussulaResult<Option<T>> {
+        self.payload
+            .clone()
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(|err| RussulaError::BadMsg {
+                dbg: format!("received a malformed payload from {}: {}", self.sender, err),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::russula::netbench::server::{CoordState, WorkerState};
+
+    #[test]
+    fn envelope_round_trip() {
+        let envelope = Envelope {
+            seq: 7,
+            sender: "server-0".to_string(),
+            state: WorkerState::RunningAwaitKill(1234),
+            payload: Some(serde_json::json!({ "port": 4433 })),
+        };
+
+        let recv = Envelope::<WorkerState>::from_msg(&envelope.as_msg()).unwrap();
+        assert_eq!(recv.seq, 7);
+        assert_eq!(recv.sender, "server-0");
+        assert_eq!(recv.payload, envelope.payload);
+        // data carried by the state is preserved
+        assert!(matches!(recv.state, WorkerState::RunningAwaitKill(1234)));
+    }
+
+    #[test]
+    fn transition_matches_variant() {
+        let TransitionStep::AwaitNext(expected) = CoordState::RunWorker.transition_step() else {
+            panic!("expected AwaitNext");
+        };
+        assert!(expected.eq(&WorkerState::RunningAwaitKill(1234)));
+        assert!(!expected.eq(&WorkerState::Killing(1234)));
+    }
+
+    #[test]
+    fn malformed_envelope() {
+        let msg = Msg::new(Bytes::from_static(b"\"Ready\""));
+        assert!(matches!(
+            Envelope::<WorkerState>::from_msg(&msg),
+            Err(RussulaError::BadMsg { .. })
+        ));
+    }
 }
@@ -0,0 +1,665 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::states::{StateApi, TransitionStep};
+use std::collections::{BTreeSet, VecDeque};
+
+const ARROW_RIGHT: &str = "--------->";
+const ARROW_LEFT: &str = "<---------";
+
+/// A Coordinator/Worker protocol pair declared as a transition table.
+///
+/// Each side lists its transitions as edges, starting with a transition out of
+/// the initial State. The step which triggers an edge is read from the
+/// [`StateApi`] impl of the State it leaves, unless the edge is an alternative
+/// to that step such as a timeout. The State it moves to is declared rather
+/// than read from [`StateApi::next_state`], since some States carry data, such
+/// as a pid, which is only known at runtime.
+///
+/// A side waiting on a peer State can only move while the peer is in that
+/// State, since msgs received in other States are dropped and heartbeats only
+/// carry the current State.
+pub struct TransitionTable<C: StateApi, W: StateApi> {
+    coord: Vec<Edge<C>>,
+    worker: Vec<Edge<W>>,
+}
+
+/// A transition of one side of a [`TransitionTable`]
+pub struct Edge<S: StateApi> {
+    from: S,
+    to: S,
+    // The step which triggers an alternative transition. None if the transition
+    // is taken once the step of `from` completes.
+    step: Option<TransitionStep<S::PeerState>>,
+}
+
+impl<S: StateApi> Edge<S> {
+    /// The transition taken once the step of `from` completes
+    pub fn next(from: S, to: S) -> Self {
+        Edge {
+            from,
+            to,
+            step: None,
+        }
+    }
+
+    /// A transition which the state machine takes on its own instead of the step
+    /// of `from`, such as a timeout
+    pub fn self_driven(from: S, to: S) -> Self {
+        Edge {
+            from,
+            to,
+            step: Some(TransitionStep::SelfDriven),
+        }
+    }
+
+    /// A transition which the user picks instead of the step of `from`, such as
+    /// starting another iteration
+    pub fn user_driven(from: S, to: S) -> Self {
+        Edge {
+            from,
+            to,
+            step: Some(TransitionStep::UserDriven),
+        }
+    }
+
+    /// A transition taken instead of the step of `from` once the peer is in `peer`
+    pub fn await_next(from: S, peer: S::PeerState, to: S) -> Self {
+        Edge {
+            from,
+            to,
+            step: Some(TransitionStep::AwaitNext(peer)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Step {
+    SelfDriven,
+    UserDriven,
+    // Index of the expected peer State
+    AwaitNext(usize),
+    Finished,
+}
+
+#[derive(Clone, Copy)]
+struct Transition {
+    step: Step,
+    to: usize,
+    // Taken once the step of the State completes rather than an alternative
+    primary: bool,
+}
+
+// One side of the table with States referred to by their index
+struct Side {
+    label: &'static str,
+    names: Vec<String>,
+    // The step of each State
+    steps: Vec<Step>,
+    // The transitions out of each State. The first is taken by its step, if the
+    // State has one.
+    next: Vec<Vec<Transition>>,
+    // Index of each edge as (State, transition) in the order they're declared
+    edges: Vec<(usize, usize)>,
+}
+
+impl<C, W> TransitionTable<C, W>
+where
+    C: StateApi<PeerState = W>,
+    W: StateApi<PeerState = C>,
+{
+    pub fn new(coord: Vec<Edge<C>>, worker: Vec<Edge<W>>) -> Self {
+        TransitionTable { coord, worker }
+    }
+
+    /// Check that every State awaited by one side is reachable by the other,
+    /// that both sides can reach a Finished State and that no interleaving
+    /// of the two sides deadlocks.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        let (coord, worker) = self.sides(&mut errors);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        for (side, peer) in [(&coord, &worker), (&worker, &coord)] {
+            let reachable = side.reachable();
+            let peer_reachable = peer.reachable();
+
+            let finishes = side
+                .steps
+                .iter()
+                .zip(reachable.iter())
+                .any(|(step, reachable)| *reachable && *step == Step::Finished);
+            if !finishes {
+                errors.push(format!("{} never reaches a Finished state", side.label));
+            }
+
+            for (idx, next) in side.next.iter().enumerate() {
+                for transition in next {
+                    if let Step::AwaitNext(expected) = transition.step {
+                        if !peer_reachable[expected] {
+                            errors.push(format!(
+                                "{} {} awaits {} {} which is unreachable",
+                                side.label, side.names[idx], peer.label, peer.names[expected]
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        // Explore every interleaving of the two sides
+        let mut seen = BTreeSet::from([(0, 0)]);
+        let mut queue = VecDeque::from([(0, 0)]);
+        while let Some((c, w)) = queue.pop_front() {
+            let coord_moves = coord.enabled(c, w).map(|next| (next, w));
+            let worker_moves = worker.enabled(w, c).map(|next| (c, next));
+            let moves: Vec<_> = coord_moves.chain(worker_moves).collect();
+
+            let finished = coord.steps[c] == Step::Finished && worker.steps[w] == Step::Finished;
+            if moves.is_empty() && !finished {
+                errors.push(format!(
+                    "deadlock with {} in {} and {} in {}",
+                    coord.label, coord.names[c], worker.label, worker.names[w]
+                ));
+            }
+
+            for next in moves {
+                if seen.insert(next) {
+                    queue.push_back(next);
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Render the msgs and transitions of the protocol pair as an ASCII diagram,
+    /// with the Coordinator on the left and Worker on the right.
+    ///
+    /// The diagram starts with a run which takes the first transition of each
+    /// State. Transitions triggered by a peer msg are taken first, followed by
+    /// self and then user driven transitions. The Worker moves first on a tie.
+    /// Every edge which the run doesn't take follows, in the order declared.
+    pub fn render(&self) -> String {
+        let mut errors = Vec::new();
+        let (coord, worker) = self.sides(&mut errors);
+
+        let coord_width = coord.names.iter().map(String::len).max().unwrap_or(0) + 2;
+        let worker_col = coord_width + ARROW_RIGHT.len() + 2;
+        let coord_line = |c: usize| coord.names[c].clone();
+        let worker_line = |w: usize| format!("{:worker_col$}{}", "", worker.names[w]);
+        let side_line = |coord_moves: bool, state: usize| match coord_moves {
+            true => coord_line(state),
+            false => worker_line(state),
+        };
+        let msg_line = |c: usize, arrow: &str, w: usize| {
+            format!(
+                "{:coord_width$}{}  {}",
+                coord.names[c], arrow, worker.names[w]
+            )
+        };
+        let step_lines = |indent: usize, step: Step| {
+            let label = match step {
+                Step::SelfDriven => " (self)",
+                Step::UserDriven => " (user)",
+                _ => "",
+            };
+            [
+                format!("{:indent$}|{}", "", label, indent = indent + 3),
+                format!("{:indent$}v", "", indent = indent + 3),
+            ]
+        };
+        // The lines of a transition, given the State of each side
+        let transition_lines = |coord_moves: bool, step: Step, c: usize, w: usize| {
+            let mut lines = Vec::new();
+            match (coord_moves, step) {
+                (true, Step::AwaitNext(_)) => lines.push(msg_line(c, ARROW_LEFT, w)),
+                (true, _) => lines.push(coord_line(c)),
+                (false, Step::AwaitNext(_)) => lines.push(msg_line(c, ARROW_RIGHT, w)),
+                (false, _) => lines.push(worker_line(w)),
+            }
+            let indent = if coord_moves { 0 } else { worker_col };
+            lines.extend(step_lines(indent, step));
+            lines
+        };
+
+        let mut lines = Vec::new();
+        // The edges taken by the run as (Coordinator, State, transition)
+        let mut taken = BTreeSet::new();
+        let (mut c, mut w) = (0, 0);
+        let mut seen = BTreeSet::from([(c, w)]);
+        let mut last_moved = None;
+        loop {
+            let coord_rank = coord.rank(c, w);
+            let worker_rank = worker.rank(w, c);
+            let coord_moves = match (coord_rank, worker_rank) {
+                (Some(coord_rank), Some(worker_rank)) => coord_rank < worker_rank,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+
+            if coord_moves {
+                let transition = coord.next[c][0];
+                lines.extend(transition_lines(true, transition.step, c, w));
+                taken.insert((true, c, 0));
+                c = transition.to;
+            } else {
+                let transition = worker.next[w][0];
+                lines.extend(transition_lines(false, transition.step, c, w));
+                taken.insert((false, w, 0));
+                w = transition.to;
+            }
+            last_moved = Some(coord_moves);
+
+            // stop if the run loops back on itself
+            if !seen.insert((c, w)) {
+                break;
+            }
+        }
+
+        match last_moved {
+            Some(true) => lines.push(coord_line(c)),
+            Some(false) => lines.push(worker_line(w)),
+            None => lines.push(msg_line(c, "          ", w).trim_end().to_string()),
+        }
+
+        // Each remaining edge starts a new block, unless it's the transition taken
+        // by the State which the previous edge moved to
+        for (coord_moves, side) in [(true, &coord), (false, &worker)] {
+            let mut block_end = None;
+            for (state, idx) in side.edges.iter().copied() {
+                if taken.contains(&(coord_moves, state, idx)) {
+                    continue;
+                }
+                let transition = side.next[state][idx];
+                if block_end != Some(state) || !transition.primary {
+                    if let Some(block_end) = block_end {
+                        lines.push(side_line(coord_moves, block_end));
+                    }
+                    lines.push(String::new());
+                }
+
+                // a msg is drawn from the awaited peer State
+                let (c, w) = match (coord_moves, transition.step) {
+                    (true, Step::AwaitNext(peer)) => (state, peer),
+                    (false, Step::AwaitNext(peer)) => (peer, state),
+                    (true, _) => (state, 0),
+                    (false, _) => (0, state),
+                };
+                lines.extend(transition_lines(coord_moves, transition.step, c, w));
+                block_end = Some(transition.to);
+            }
+            if let Some(block_end) = block_end {
+                lines.push(side_line(coord_moves, block_end));
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    fn sides(&self, errors: &mut Vec<String>) -> (Side, Side) {
+        let coord_states = states(&self.coord);
+        let worker_states = states(&self.worker);
+        (
+            Side::new(
+                "Coordinator",
+                &coord_states,
+                &self.coord,
+                &worker_states,
+                errors,
+            ),
+            Side::new(
+                "Worker",
+                &worker_states,
+                &self.worker,
+                &coord_states,
+                errors,
+            ),
+        )
+    }
+}
+
+// The States of one side in the order they first appear in its edges, which
+// starts with the initial State
+fn states<S: StateApi>(edges: &[Edge<S>]) -> Vec<S> {
+    let mut states: Vec<S> = Vec::new();
+    for state in edges.iter().flat_map(|edge| [&edge.from, &edge.to]) {
+        if !states.iter().any(|known| known.eq(state)) {
+            states.push(state.clone());
+        }
+    }
+    states
+}
+
+impl Side {
+    fn new<S: StateApi>(
+        label: &'static str,
+        states: &[S],
+        edges: &[Edge<S>],
+        peer: &[S::PeerState],
+        errors: &mut Vec<String>,
+    ) -> Self {
+        let index = |state: &S| states.iter().position(|known| known.eq(state)).unwrap();
+        let names: Vec<String> = states.iter().map(state_name).collect();
+        let step = |name: &str, step: &TransitionStep<S::PeerState>, errors: &mut Vec<String>| {
+            match step {
+                TransitionStep::SelfDriven => Step::SelfDriven,
+                TransitionStep::UserDriven => Step::UserDriven,
+                TransitionStep::Finished => Step::Finished,
+                TransitionStep::AwaitNext(expected) => {
+                    match peer.iter().position(|peer| peer.eq(expected)) {
+                        Some(expected) => Step::AwaitNext(expected),
+                        None => {
+                            errors.push(format!(
+                                "{} {} awaits {:?} which is not in the table",
+                                label, name, expected
+                            ));
+                            Step::Finished
+                        }
+                    }
+                }
+            }
+        };
+
+        let steps: Vec<Step> = states
+            .iter()
+            .zip(&names)
+            .map(|(state, name)| step(name, &state.transition_step(), errors))
+            .collect();
+        let mut side = Side {
+            label,
+            names,
+            steps,
+            next: vec![Vec::new(); states.len()],
+            edges: Vec::new(),
+        };
+
+        for edge in edges {
+            let (from, to) = (index(&edge.from), index(&edge.to));
+            if matches!(edge.from.transition_step(), TransitionStep::Finished) {
+                errors.push(format!(
+                    "{} {} is Finished but moves to {}",
+                    label, side.names[from], side.names[to]
+                ));
+            }
+
+            let transition_step = match &edge.step {
+                Some(alternative) => step(&side.names[from], alternative, errors),
+                None => {
+                    // States which carry runtime data don't declare their next State
+                    if let Some(next_state) = edge.from.next_state() {
+                        if !next_state.eq(&edge.to) {
+                            errors.push(format!(
+                                "{} {} moves to {} but the table has {}",
+                                label,
+                                side.names[from],
+                                state_name(&next_state),
+                                side.names[to]
+                            ));
+                        }
+                    }
+                    side.steps[from]
+                }
+            };
+            let transition = Transition {
+                step: transition_step,
+                to,
+                primary: edge.step.is_none(),
+            };
+
+            // the transition taken by the step of the State comes first
+            let next = &mut side.next[from];
+            match edge.step {
+                None => {
+                    next.insert(0, transition);
+                    for (state, idx) in side.edges.iter_mut() {
+                        if *state == from {
+                            *idx += 1;
+                        }
+                    }
+                    side.edges.push((from, 0));
+                }
+                Some(_) => {
+                    next.push(transition);
+                    side.edges.push((from, next.len() - 1));
+                }
+            }
+        }
+
+        for (idx, next) in side.next.iter_mut().enumerate() {
+            if next.is_empty() {
+                if side.steps[idx] != Step::Finished {
+                    errors.push(format!("{} {} has no transitions", label, side.names[idx]));
+                }
+                // Finished States stay in place
+                next.push(Transition {
+                    step: Step::Finished,
+                    to: idx,
+                    primary: true,
+                });
+            }
+        }
+
+        side
+    }
+
+    // States reachable from the initial State
+    fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.names.len()];
+        let mut queue = VecDeque::from([0]);
+        while let Some(state) = queue.pop_front() {
+            if !reachable[state] {
+                reachable[state] = true;
+                queue.extend(self.next[state].iter().map(|transition| transition.to));
+            }
+        }
+        reachable
+    }
+
+    // The States which can be moved to while the peer is in `peer_state`
+    fn enabled(&self, state: usize, peer_state: usize) -> impl Iterator<Item = usize> + '_ {
+        self.next[state]
+            .iter()
+            .filter(move |transition| rank(transition.step, peer_state).is_some())
+            .map(|transition| transition.to)
+    }
+
+    // Order in which the first transition is taken when rendering a run
+    fn rank(&self, state: usize, peer_state: usize) -> Option<u8> {
+        rank(self.next[state][0].step, peer_state)
+    }
+}
+
+fn rank(step: Step, peer_state: usize) -> Option<u8> {
+    match step {
+        Step::AwaitNext(expected) if expected == peer_state => Some(0),
+        Step::SelfDriven => Some(1),
+        Step::UserDriven => Some(2),
+        Step::AwaitNext(_) | Step::Finished => None,
+    }
+}
+
+// The name of the State variant without any data it carries
+fn state_name<S: StateApi>(state: &S) -> String {
+    let name = format!("{:?}", state);
+    match name.split_once('(') {
+        Some((variant, _)) => variant.to_string(),
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    enum Coord {
+        Start,
+        Wait,
+        Stuck,
+        Done,
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    enum Worker {
+        Start,
+        Wait(u32),
+        Done,
+    }
+
+    impl StateApi for Coord {
+        type PeerState = Worker;
+
+        fn transition_step(&self) -> TransitionStep<Worker> {
+            match self {
+                Coord::Start => TransitionStep::UserDriven,
+                Coord::Wait => TransitionStep::AwaitNext(Worker::Wait(0)),
+                Coord::Stuck => TransitionStep::AwaitNext(Worker::Start),
+                Coord::Done => TransitionStep::Finished,
+            }
+        }
+
+        fn next_state(&self) -> Option<Self> {
+            match self {
+                Coord::Start => Some(Coord::Wait),
+                Coord::Wait | Coord::Stuck => Some(Coord::Done),
+                Coord::Done => None,
+            }
+        }
+    }
+
+    impl StateApi for Worker {
+        type PeerState = Coord;
+
+        fn transition_step(&self) -> TransitionStep<Coord> {
+            match self {
+                Worker::Start => TransitionStep::AwaitNext(Coord::Wait),
+                Worker::Wait(_) => TransitionStep::AwaitNext(Coord::Done),
+                Worker::Done => TransitionStep::Finished,
+            }
+        }
+
+        fn next_state(&self) -> Option<Self> {
+            match self {
+                // the Worker sets Wait along with its data
+                Worker::Start => None,
+                Worker::Wait(_) => Some(Worker::Done),
+                Worker::Done => None,
+            }
+        }
+    }
+
+    fn worker_edges() -> Vec<Edge<Worker>> {
+        vec![
+            Edge::next(Worker::Start, Worker::Wait(0)),
+            Edge::next(Worker::Wait(0), Worker::Done),
+        ]
+    }
+
+    #[test]
+    fn valid_table() {
+        let table = TransitionTable::new(
+            vec![
+                Edge::next(Coord::Start, Coord::Wait),
+                Edge::next(Coord::Wait, Coord::Done),
+            ],
+            worker_edges(),
+        );
+        assert_eq!(table.validate(), Ok(()));
+
+        let expected = [
+            "Start",
+            "   | (user)",
+            "   v",
+            "Wait   --------->  Start",
+            "                      |",
+            "                      v",
+            "Wait   <---------  Wait",
+            "   |",
+            "   v",
+            "Done   --------->  Wait",
+            "                      |",
+            "                      v",
+            "                   Done",
+        ];
+        assert_eq!(table.render(), expected.join("\n"));
+    }
+
+    #[test]
+    fn alternative_edges() {
+        // the Worker can give up waiting before the Coordinator sees it in Wait
+        let mut worker = worker_edges();
+        worker.push(Edge::self_driven(Worker::Wait(0), Worker::Done));
+        let table = TransitionTable::new(
+            vec![
+                Edge::next(Coord::Start, Coord::Wait),
+                Edge::next(Coord::Wait, Coord::Done),
+            ],
+            worker,
+        );
+        assert_eq!(
+            table.validate(),
+            Err(vec![
+                "deadlock with Coordinator in Wait and Worker in Done".to_string()
+            ])
+        );
+
+        // edges which the run doesn't take follow it
+        let expected = [
+            "                   Done",
+            "",
+            "                   Wait",
+            "                      | (self)",
+            "                      v",
+            "                   Done",
+        ];
+        assert!(table.render().ends_with(&expected.join("\n")));
+    }
+
+    #[test]
+    fn deadlock() {
+        // the Worker waits for Wait which the Coordinator never reaches
+        let table = TransitionTable::new(
+            vec![
+                Edge::next(Coord::Stuck, Coord::Done),
+                Edge::next(Coord::Wait, Coord::Done),
+            ],
+            worker_edges(),
+        );
+        let errors = table.validate().unwrap_err();
+        assert!(errors
+            .iter()
+            .any(|err| err == "Worker Start awaits Coordinator Wait which is unreachable"));
+        assert!(errors
+            .iter()
+            .any(|err| err == "deadlock with Coordinator in Done and Worker in Start"));
+    }
+
+    #[test]
+    fn missing_state() {
+        let table = TransitionTable::new(
+            vec![
+                Edge::next(Coord::Start, Coord::Done),
+                Edge::next(Coord::Stuck, Coord::Done),
+            ],
+            vec![Edge::next(Worker::Start, Worker::Wait(0))],
+        );
+        let errors = table.validate().unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                "Coordinator Start moves to Wait but the table has Done",
+                "Worker Start awaits Wait which is not in the table",
+                "Worker Wait has no transitions",
+            ]
+        );
+    }
+}
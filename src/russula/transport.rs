@@ -0,0 +1,125 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::russula::{RussulaError, RussulaResult};
+use bytes::BytesMut;
+use core::task::{Context, Poll};
+use std::{io, net::SocketAddr, sync::Arc};
+use tokio::net::{TcpListener, TcpStream};
+
+#[cfg(test)]
+pub mod memory;
+pub mod tls;
+
+use tls::TlsConfig;
+
+/// A byte stream connecting a Coordinator and Worker.
+///
+/// Mirrors the readiness based API of [`TcpStream`] so that [`FramedStream`] can
+/// read and write frames without knowing how the bytes are carried.
+///
+/// [`FramedStream`]: crate::russula::network_utils::FramedStream
+pub trait Transport: std::fmt::Debug + Send + Sync {
+    /// Ready once data can be read or the peer has closed the stream
+    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+
+    /// Read the available data into `buf`. Returns 0 once the peer has closed the
+    /// stream and [`io::ErrorKind::WouldBlock`] if no data is available.
+    fn try_read_buf(&self, buf: &mut BytesMut) -> io::Result<usize>;
+
+    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+
+    /// Write as much of `buf` as possible, returning the number of bytes written
+    fn try_write(&self, buf: &[u8]) -> io::Result<usize>;
+}
+
+impl Transport for TcpStream {
+    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        TcpStream::poll_read_ready(self, cx)
+    }
+
+    fn try_read_buf(&self, buf: &mut BytesMut) -> io::Result<usize> {
+        TcpStream::try_read_buf(self, buf)
+    }
+
+    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        TcpStream::poll_write_ready(self, cx)
+    }
+
+    fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        TcpStream::try_write(self, buf)
+    }
+}
+
+/// How a Coordinator reaches its Workers
+#[derive(Debug, Clone, Default)]
+pub enum Network {
+    #[default]
+    Tcp,
+    /// TCP connections which the peers authenticate and encrypt with mutual TLS
+    Tls(TlsConfig),
+    /// Peers in the same process connected by in-memory streams
+    #[cfg(test)]
+    Memory(memory::MemoryNetwork),
+}
+
+impl Network {
+    /// Connect to the Worker listening on `addr`
+    pub async fn connect(&self, addr: &SocketAddr) -> RussulaResult<Box<dyn Transport>> {
+        match self {
+            Network::Tcp => {
+                let stream = TcpStream::connect(addr).await.map_err(RussulaError::from)?;
+                Ok(Box::new(stream))
+            }
+            Network::Tls(tls) => {
+                let stream = TcpStream::connect(addr).await.map_err(RussulaError::from)?;
+                Ok(Box::new(tls.connect(stream).await?))
+            }
+            #[cfg(test)]
+            Network::Memory(network) => Ok(Box::new(network.connect(addr)?)),
+        }
+    }
+
+    /// Listen for connections from the Coordinator on `addr`
+    pub async fn listen(&self, addr: &SocketAddr) -> RussulaResult<Listener> {
+        match self {
+            Network::Tcp => {
+                let listener = TcpListener::bind(addr).await.map_err(RussulaError::from)?;
+                Ok(Listener::Tcp(Arc::new(listener)))
+            }
+            Network::Tls(tls) => {
+                let listener = TcpListener::bind(addr).await.map_err(RussulaError::from)?;
+                Ok(Listener::Tls(Arc::new(listener), tls.clone()))
+            }
+            #[cfg(test)]
+            Network::Memory(network) => Ok(Listener::Memory(network.listen(addr)?)),
+        }
+    }
+}
+
+/// Accepts connections from the Coordinator. Kept by Workers so that a dropped
+/// connection can be re-established on the same addr.
+#[derive(Debug, Clone)]
+pub enum Listener {
+    Tcp(Arc<TcpListener>),
+    Tls(Arc<TcpListener>, TlsConfig),
+    #[cfg(test)]
+    Memory(memory::MemoryListener),
+}
+
+impl Listener {
+    pub async fn accept(&self) -> RussulaResult<Box<dyn Transport>> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _peer_addr) = listener.accept().await.map_err(RussulaError::from)?;
+                Ok(Box::new(stream))
+            }
+            Listener::Tls(listener, tls) => {
+                let (stream, _peer_addr) = listener.accept().await.map_err(RussulaError::from)?;
+                Ok(Box::new(tls.accept(stream).await?))
+            }
+            #[cfg(test)]
+            Listener::Memory(listener) => Ok(Box::new(listener.accept().await)),
+        }
+    }
+}
@@ -0,0 +1,602 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::Transport;
+use crate::russula::{network_utils::LEN_PREFIX, RussulaError, RussulaResult};
+use bytes::{Bytes, BytesMut};
+use core::{
+    future::{poll_fn, Future},
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+use tokio::time::{Instant, Sleep};
+
+// Sent in place of a frame by Fault::Corrupt
+const CORRUPT_FRAME: &[u8] = b"corrupt";
+
+/// A fault applied to a frame sent over a [`MemoryTransport`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Drop,
+    /// Hold the frame, and any frame sent after it, for the duration
+    Delay(Duration),
+    Duplicate,
+    /// Deliver the frame after the next frame
+    Reorder,
+    /// Replace the frame with one which isn't a valid msg
+    Corrupt,
+    /// Drop the frame and close the connection in both directions
+    Disconnect,
+}
+
+/// The faults applied to the frames sent in one direction of a connection.
+///
+/// Scripted faults are shared by every connection made with the same `Faults`, so
+/// a fault isn't applied again once a peer reconnects. Random faults restart from
+/// the seed on each connection.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    rules: Vec<FaultRule>,
+    random: Option<RandomFaults>,
+}
+
+#[derive(Debug, Clone)]
+struct FaultRule {
+    pattern: Bytes,
+    fault: Fault,
+    remaining: Arc<AtomicUsize>,
+}
+
+#[derive(Debug, Clone)]
+struct RandomFaults {
+    rng: u64,
+    rate: f64,
+    // frames sent unchanged before any fault is injected
+    skip: usize,
+}
+
+impl Faults {
+    /// Apply `fault` to the next `count` frames which contain `pattern`, such as
+    /// `"state":"Done"`
+    pub fn on_frame(mut self, pattern: &str, fault: Fault, count: usize) -> Self {
+        self.rules.push(FaultRule {
+            pattern: Bytes::copy_from_slice(pattern.as_bytes()),
+            fault,
+            remaining: Arc::new(AtomicUsize::new(count)),
+        });
+        self
+    }
+
+    /// Apply a random fault to each frame with probability `rate`.
+    ///
+    /// The faults are picked by a rng seeded with `seed` so that a failure can be
+    /// reproduced. The first `skip` frames, such as the handshake, are sent
+    /// unchanged.
+    pub fn random(mut self, seed: u64, rate: f64, skip: usize) -> Self {
+        self.random = Some(RandomFaults {
+            rng: seed,
+            rate,
+            skip,
+        });
+        self
+    }
+
+    // The fault to apply to the next frame, if any
+    fn next(&mut self, frame: &[u8]) -> Option<Fault> {
+        let rule = self.rules.iter().find(|rule| {
+            frame
+                .windows(rule.pattern.len())
+                .any(|window| window == rule.pattern)
+                && rule
+                    .remaining
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                        remaining.checked_sub(1)
+                    })
+                    .is_ok()
+        });
+        if let Some(rule) = rule {
+            return Some(rule.fault);
+        }
+
+        let random = self.random.as_mut()?;
+        if random.skip > 0 {
+            random.skip -= 1;
+            return None;
+        }
+        if (splitmix64(&mut random.rng) as f64 / u64::MAX as f64) >= random.rate {
+            return None;
+        }
+        let fault = match splitmix64(&mut random.rng) % 4 {
+            0 => Fault::Drop,
+            1 => Fault::Delay(Duration::from_millis(splitmix64(&mut random.rng) % 100)),
+            2 => Fault::Duplicate,
+            _ => Fault::Reorder,
+        };
+        Some(fault)
+    }
+}
+
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[derive(Debug)]
+struct Frame {
+    data: Bytes,
+    deliver_at: Instant,
+}
+
+// One direction of a MemoryTransport
+#[derive(Debug, Default)]
+struct Pipe {
+    // Bytes written which don't yet form a complete frame
+    partial: BytesMut,
+    frames: VecDeque<Frame>,
+    // A frame held back by Fault::Reorder
+    held: Option<Bytes>,
+    faults: Faults,
+    // Fault::Disconnect was applied so the connection should be closed
+    disconnect: bool,
+    writer_closed: bool,
+    reader_closed: bool,
+    read_waker: Option<Waker>,
+    // Wakes the reader once a delayed frame can be delivered
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl Pipe {
+    fn new(faults: Faults) -> Self {
+        Pipe {
+            faults,
+            ..Default::default()
+        }
+    }
+
+    // Split the written bytes into frames and apply the faults to each
+    fn write(&mut self, buf: &[u8]) {
+        self.partial.extend_from_slice(buf);
+        while !self.disconnect && self.partial.len() >= LEN_PREFIX {
+            let len = u16::from_be_bytes([self.partial[0], self.partial[1]]) as usize;
+            if self.partial.len() < LEN_PREFIX + len {
+                break;
+            }
+            let frame = self.partial.split_to(LEN_PREFIX + len).freeze();
+            self.send(frame);
+        }
+
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn send(&mut self, frame: Bytes) {
+        let now = Instant::now();
+        match self.faults.next(&frame) {
+            Some(Fault::Drop) => {}
+            Some(Fault::Delay(delay)) => self.deliver(frame, now + delay),
+            Some(Fault::Duplicate) => {
+                self.deliver(frame.clone(), now);
+                self.deliver(frame, now);
+            }
+            Some(Fault::Reorder) => {
+                if let Some(held) = self.held.replace(frame) {
+                    self.deliver(held, now);
+                }
+                return;
+            }
+            Some(Fault::Corrupt) => {
+                let mut corrupt = BytesMut::new();
+                corrupt.extend_from_slice(&(CORRUPT_FRAME.len() as u16).to_be_bytes());
+                corrupt.extend_from_slice(CORRUPT_FRAME);
+                self.deliver(corrupt.freeze(), now);
+            }
+            Some(Fault::Disconnect) => {
+                self.disconnect = true;
+                return;
+            }
+            None => self.deliver(frame, now),
+        }
+
+        if let Some(held) = self.held.take() {
+            self.deliver(held, now);
+        }
+    }
+
+    // Frames are delivered in order so a delayed frame also holds the frames
+    // sent after it
+    fn deliver(&mut self, data: Bytes, deliver_at: Instant) {
+        let deliver_at = self
+            .frames
+            .back()
+            .map_or(deliver_at, |frame| frame.deliver_at.max(deliver_at));
+        self.frames.push_back(Frame { data, deliver_at });
+    }
+
+    fn close_writer(&mut self) {
+        if let Some(held) = self.held.take() {
+            self.deliver(held, Instant::now());
+        }
+        self.writer_closed = true;
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// One end of an in-memory stream which injects [`Faults`] into the frames sent
+/// to the other end.
+///
+/// Dropping either end closes the stream, as if the peer had closed its socket.
+#[derive(Debug)]
+pub struct MemoryTransport {
+    tx: Arc<Mutex<Pipe>>,
+    rx: Arc<Mutex<Pipe>>,
+}
+
+impl MemoryTransport {
+    /// A connected pair of streams which apply `a_to_b` to the frames sent by the
+    /// first stream and `b_to_a` to the frames sent by the second
+    pub fn pair(a_to_b: Faults, b_to_a: Faults) -> (MemoryTransport, MemoryTransport) {
+        let a_to_b = Arc::new(Mutex::new(Pipe::new(a_to_b)));
+        let b_to_a = Arc::new(Mutex::new(Pipe::new(b_to_a)));
+        let a = MemoryTransport {
+            tx: a_to_b.clone(),
+            rx: b_to_a.clone(),
+        };
+        let b = MemoryTransport {
+            tx: b_to_a,
+            rx: a_to_b,
+        };
+        (a, b)
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut rx = self.rx.lock().unwrap();
+        match rx.frames.front().map(|frame| frame.deliver_at) {
+            Some(deliver_at) if deliver_at <= Instant::now() => return Poll::Ready(Ok(())),
+            Some(deliver_at) => {
+                let delay = rx
+                    .delay
+                    .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deliver_at)));
+                delay.as_mut().reset(deliver_at);
+                if delay.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Ok(()));
+                }
+            }
+            None if rx.writer_closed => return Poll::Ready(Ok(())),
+            None => {}
+        }
+
+        rx.read_waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn try_read_buf(&self, buf: &mut BytesMut) -> io::Result<usize> {
+        let mut rx = self.rx.lock().unwrap();
+        match rx.frames.front() {
+            Some(frame) if frame.deliver_at <= Instant::now() => {
+                let frame = rx.frames.pop_front().expect("front frame exists");
+                buf.extend_from_slice(&frame.data);
+                Ok(frame.data.len())
+            }
+            None if rx.writer_closed => Ok(0),
+            _ => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn poll_write_ready(&self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut tx = self.tx.lock().unwrap();
+        if tx.reader_closed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "peer closed the connection",
+            ));
+        }
+        tx.write(buf);
+
+        if tx.disconnect {
+            tx.close_writer();
+            tx.reader_closed = true;
+            drop(tx);
+            let mut rx = self.rx.lock().unwrap();
+            rx.close_writer();
+            rx.reader_closed = true;
+        }
+        Ok(buf.len())
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.tx.lock().unwrap().close_writer();
+        self.rx.lock().unwrap().reader_closed = true;
+    }
+}
+
+/// Connects Coordinators and Workers in the same process over
+/// [`MemoryTransport`]s, keyed by the addr the Worker listens on.
+///
+/// Lets the state machines be tested without binding ports.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryNetwork {
+    listeners: Arc<Mutex<BTreeMap<SocketAddr, Weak<Mutex<Backlog>>>>>,
+    coord_faults: Faults,
+    worker_faults: Faults,
+    // Faults for the connection with a specific Worker
+    peer_faults: BTreeMap<SocketAddr, (Faults, Faults)>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        MemoryNetwork::default()
+    }
+
+    /// Apply `faults` to the frames sent by Coordinators
+    pub fn with_coord_faults(mut self, faults: Faults) -> Self {
+        self.coord_faults = faults;
+        self
+    }
+
+    /// Apply `faults` to the frames sent by Workers
+    pub fn with_worker_faults(mut self, faults: Faults) -> Self {
+        self.worker_faults = faults;
+        self
+    }
+
+    /// Apply `coord_faults` and `worker_faults` to the connection with the Worker
+    /// at `addr`, in place of the faults applied to every connection
+    pub fn with_peer_faults(
+        mut self,
+        addr: SocketAddr,
+        coord_faults: Faults,
+        worker_faults: Faults,
+    ) -> Self {
+        self.peer_faults.insert(addr, (coord_faults, worker_faults));
+        self
+    }
+
+    pub(super) fn connect(&self, addr: &SocketAddr) -> RussulaResult<MemoryTransport> {
+        let backlog = self
+            .listeners
+            .lock()
+            .unwrap()
+            .get(addr)
+            .and_then(Weak::upgrade)
+            .ok_or_else(|| RussulaError::NetworkConnectionRefused {
+                dbg: format!("nothing listening on {}", addr),
+            })?;
+
+        let (coord_faults, worker_faults) = self
+            .peer_faults
+            .get(addr)
+            .cloned()
+            .unwrap_or_else(|| (self.coord_faults.clone(), self.worker_faults.clone()));
+        let (coord, worker) = MemoryTransport::pair(coord_faults, worker_faults);
+        let mut backlog = backlog.lock().unwrap();
+        backlog.pending.push_back(worker);
+        if let Some(waker) = backlog.waker.take() {
+            waker.wake();
+        }
+        Ok(coord)
+    }
+
+    pub(super) fn listen(&self, addr: &SocketAddr) -> RussulaResult<MemoryListener> {
+        let mut listeners = self.listeners.lock().unwrap();
+        if listeners
+            .get(addr)
+            .is_some_and(|backlog| backlog.strong_count() > 0)
+        {
+            return Err(RussulaError::NetworkFail {
+                dbg: format!("{} is already in use", addr),
+            });
+        }
+
+        let listener = MemoryListener::default();
+        listeners.insert(*addr, Arc::downgrade(&listener.0));
+        Ok(listener)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Backlog {
+    pending: VecDeque<MemoryTransport>,
+    waker: Option<Waker>,
+}
+
+/// Accepts connections made to an addr on a [`MemoryNetwork`]. Connections are
+/// refused once every clone of the listener is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryListener(Arc<Mutex<Backlog>>);
+
+impl MemoryListener {
+    pub async fn accept(&self) -> MemoryTransport {
+        poll_fn(|cx| {
+            let mut backlog = self.0.lock().unwrap();
+            match backlog.pending.pop_front() {
+                Some(stream) => Poll::Ready(stream),
+                None => {
+                    backlog.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::russula::network_utils::{self, FramedStream, Msg};
+    use std::str::FromStr;
+
+    fn framed_pair(a_to_b: Faults, b_to_a: Faults) -> (FramedStream, FramedStream) {
+        let (a, b) = MemoryTransport::pair(a_to_b, b_to_a);
+        (
+            FramedStream::new(Box::new(a)),
+            FramedStream::new(Box::new(b)),
+        )
+    }
+
+    async fn send(stream: &mut FramedStream, msgs: &[&'static str]) {
+        for msg in msgs {
+            let msg = Msg::new(Bytes::from_static(msg.as_bytes()));
+            network_utils::send_msg(stream, msg).await.unwrap();
+        }
+    }
+
+    // Receive msgs until none arrive within `wait` or the stream is closed
+    async fn recv_all(stream: &mut FramedStream, wait: Duration) -> Vec<String> {
+        let mut msgs = Vec::new();
+        loop {
+            match tokio::time::timeout(wait, network_utils::recv_msg(stream)).await {
+                Ok(Ok(msg)) => msgs.push(String::from_utf8(msg.data.to_vec()).unwrap()),
+                Ok(Err(RussulaError::NetworkBlocked { .. })) => continue,
+                Ok(Err(err)) if err.is_disconnect() => return msgs,
+                Ok(Err(err)) => panic!("{}", err),
+                Err(_elapsed) => return msgs,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn send_recv_close() {
+        let (mut a, mut b) = framed_pair(Faults::default(), Faults::default());
+        send(&mut a, &["Ready", "RunWorker"]).await;
+        send(&mut b, &["Done"]).await;
+        assert_eq!(
+            recv_all(&mut b, Duration::from_millis(10)).await,
+            ["Ready", "RunWorker"]
+        );
+        assert_eq!(recv_all(&mut a, Duration::from_millis(10)).await, ["Done"]);
+
+        // closing one end fails both the reads and writes of the other
+        drop(a);
+        assert!(matches!(
+            network_utils::recv_msg(&mut b).await,
+            Err(RussulaError::NetworkFail { .. })
+        ));
+        let msg = Msg::new(Bytes::from_static(b"Done"));
+        assert!(matches!(
+            network_utils::send_msg(&mut b, msg).await,
+            Err(RussulaError::NetworkFail { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn scripted_faults() {
+        let wait = Duration::from_millis(10);
+        let faults = Faults::default()
+            .on_frame("drop", Fault::Drop, 1)
+            .on_frame("dup", Fault::Duplicate, 1)
+            .on_frame("reorder", Fault::Reorder, 1);
+        let (mut a, mut b) = framed_pair(faults, Faults::default());
+        send(&mut a, &["drop", "drop", "dup", "reorder", "next"]).await;
+        assert_eq!(
+            recv_all(&mut b, wait).await,
+            ["drop", "dup", "dup", "next", "reorder"]
+        );
+
+        let faults = Faults::default()
+            .on_frame("bad", Fault::Corrupt, 1)
+            .on_frame("bye", Fault::Disconnect, 1);
+        let (mut a, mut b) = framed_pair(faults, Faults::default());
+        send(&mut a, &["bad", "bye"]).await;
+        assert_eq!(recv_all(&mut b, wait).await, ["corrupt"]);
+        // both directions are closed once the connection is dropped
+        assert!(matches!(
+            network_utils::recv_msg(&mut b).await,
+            Err(RussulaError::NetworkFail { .. })
+        ));
+        assert!(matches!(
+            network_utils::recv_msg(&mut a).await,
+            Err(RussulaError::NetworkFail { .. })
+        ));
+
+        // a delayed frame holds back the frames sent after it
+        let delay = Duration::from_millis(100);
+        let faults = Faults::default().on_frame("slow", Fault::Delay(delay), 1);
+        let (mut a, mut b) = framed_pair(faults, Faults::default());
+        let start = Instant::now();
+        send(&mut a, &["slow", "fast"]).await;
+        assert!(recv_all(&mut b, wait).await.is_empty());
+        assert_eq!(recv_all(&mut b, delay).await, ["slow", "fast"]);
+        assert!(start.elapsed() >= delay);
+    }
+
+    #[tokio::test]
+    async fn random_faults_are_seeded() {
+        let msgs: Vec<&'static str> = vec![
+            "0", "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13", "14", "15",
+        ];
+        let mut runs = Vec::new();
+        for seed in [7, 7, 8] {
+            let (mut a, mut b) =
+                framed_pair(Faults::default().random(seed, 0.5, 2), Faults::default());
+            send(&mut a, &msgs).await;
+            runs.push(recv_all(&mut b, Duration::from_millis(150)).await);
+        }
+
+        assert_eq!(runs[0], runs[1]);
+        assert_ne!(runs[0], runs[2]);
+        assert_ne!(runs[0], msgs);
+        // the skipped frames are always delivered
+        assert_eq!(runs[0][..2], ["0", "1"]);
+    }
+
+    #[tokio::test]
+    async fn connect_listen() {
+        let faults = Faults::default().on_frame("Ready", Fault::Duplicate, 1);
+        let network = MemoryNetwork::new().with_worker_faults(faults);
+        let addr = SocketAddr::from_str("127.0.0.1:4433").unwrap();
+        assert!(matches!(
+            network.connect(&addr),
+            Err(RussulaError::NetworkConnectionRefused { .. })
+        ));
+
+        let listener = network.listen(&addr).unwrap();
+        assert!(network.listen(&addr).is_err());
+        let coord = network.connect(&addr).unwrap();
+        let worker = listener.accept().await;
+        let (mut coord, mut worker) = (
+            FramedStream::new(Box::new(coord)),
+            FramedStream::new(Box::new(worker)),
+        );
+        send(&mut coord, &["CheckWorker"]).await;
+        send(&mut worker, &["Ready"]).await;
+        assert_eq!(
+            recv_all(&mut worker, Duration::from_millis(10)).await,
+            ["CheckWorker"]
+        );
+        // the faults only apply to the msgs sent by the worker
+        assert_eq!(
+            recv_all(&mut coord, Duration::from_millis(10)).await,
+            ["Ready", "Ready"]
+        );
+
+        // the addr is free again once the listener is dropped
+        drop(listener);
+        assert!(network.connect(&addr).is_err());
+        assert!(network.listen(&addr).is_ok());
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::Transport;
+use crate::russula::{RussulaError, RussulaResult};
+use bytes::BytesMut;
+use core::{
+    future::Future,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, Issuer, KeyPair,
+};
+use rustls::{
+    crypto::ring, server::WebPkiClientVerifier, ClientConfig, Connection, RootCertStore,
+    ServerConfig,
+};
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName};
+use std::{
+    io::{self, Read, Write},
+    sync::{Arc, Mutex},
+};
+use tokio::net::TcpStream;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+// Name in the certificate shared by the peers of a run. Peers are authenticated
+// by the run's CA rather than their addr, which isn't known when the
+// certificates are generated.
+const PEER_NAME: &str = "russula";
+// Bounds the handshake so that a silent connection doesn't stop a Worker from
+// accepting the Coordinator
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Plaintext read from the connection per call
+const READ_LEN: usize = 4096;
+
+/// Certificates which authenticate the Coordinator and Workers of a run to each
+/// other and encrypt the connections between them.
+///
+/// A run's certificates are a PEM bundle holding the CA certificate, followed by
+/// the peer certificate and its key. The CA key is discarded once the peer
+/// certificate is signed, so only peers given the bundle can join the run.
+#[derive(Clone)]
+pub struct TlsConfig {
+    client: Arc<ClientConfig>,
+    server: Arc<ServerConfig>,
+}
+
+impl TlsConfig {
+    /// Generate the PEM bundle for a new run
+    pub fn generate_pem() -> RussulaResult<String> {
+        let mut ca = CertificateParams::new(Vec::new()).map_err(bad_config)?;
+        ca.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca.distinguished_name
+            .push(DnType::CommonName, "russula run CA");
+        let ca_key = KeyPair::generate().map_err(bad_config)?;
+        let ca_cert = ca.self_signed(&ca_key).map_err(bad_config)?;
+
+        let mut peer = CertificateParams::new(vec![PEER_NAME.to_string()]).map_err(bad_config)?;
+        peer.extended_key_usages = vec![
+            ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsagePurpose::ClientAuth,
+        ];
+        let peer_key = KeyPair::generate().map_err(bad_config)?;
+        let peer_cert = peer
+            .signed_by(&peer_key, &Issuer::new(ca, ca_key))
+            .map_err(bad_config)?;
+
+        Ok(format!(
+            "{}{}{}",
+            ca_cert.pem(),
+            peer_cert.pem(),
+            peer_key.serialize_pem()
+        ))
+    }
+
+    /// Load the run's certificates from a bundle created by [`generate_pem`].
+    ///
+    /// [`generate_pem`]: TlsConfig::generate_pem
+    pub fn from_pem(pem: &str) -> RussulaResult<Self> {
+        let certs = CertificateDer::pem_slice_iter(pem.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(bad_config)?;
+        let key = PrivateKeyDer::from_pem_slice(pem.as_bytes()).map_err(bad_config)?;
+        let (ca, peer) = match <[_; 2]>::try_from(certs) {
+            Ok([ca, peer]) => (ca, vec![peer]),
+            Err(_certs) => {
+                return Err(bad_config(
+                    "expected a CA certificate, a peer certificate and its key",
+                ))
+            }
+        };
+
+        let provider = Arc::new(ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        roots.add(ca).map_err(bad_config)?;
+        let roots = Arc::new(roots);
+        let client = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(bad_config)?
+            .with_root_certificates(roots.clone())
+            .with_client_auth_cert(peer.clone(), key.clone_key())
+            .map_err(bad_config)?;
+        let verifier = WebPkiClientVerifier::builder_with_provider(roots, provider.clone())
+            .build()
+            .map_err(bad_config)?;
+        let server = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(bad_config)?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(peer, key)
+            .map_err(bad_config)?;
+
+        Ok(TlsConfig {
+            client: Arc::new(client),
+            server: Arc::new(server),
+        })
+    }
+
+    /// Authenticate the Worker connected to by a Coordinator
+    pub(super) async fn connect(&self, stream: TcpStream) -> RussulaResult<TlsTransport> {
+        let name = ServerName::try_from(PEER_NAME).expect("PEER_NAME is a valid DNS name");
+        let connect = TlsConnector::from(self.client.clone()).connect(name, stream);
+        let (stream, conn) = handshake(connect).await?.into_inner();
+        Ok(TlsTransport::new(stream, conn.into()))
+    }
+
+    /// Authenticate the Coordinator accepted by a Worker
+    pub(super) async fn accept(&self, stream: TcpStream) -> RussulaResult<TlsTransport> {
+        let accept = TlsAcceptor::from(self.server.clone()).accept(stream);
+        let (stream, conn) = handshake(accept).await?.into_inner();
+        Ok(TlsTransport::new(stream, conn.into()))
+    }
+}
+
+impl std::fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TlsConfig(..)")
+    }
+}
+
+// A peer which fails the handshake is treated like one with the wrong auth token
+async fn handshake<T>(handshake: impl Future<Output = io::Result<T>>) -> RussulaResult<T> {
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+        .await
+        .map_err(|_elapsed| RussulaError::AuthFailed {
+            dbg: format!(
+                "TLS handshake didn't complete within {:?}",
+                HANDSHAKE_TIMEOUT
+            ),
+        })?
+        .map_err(|err| RussulaError::AuthFailed {
+            dbg: format!("TLS handshake failed: {}", err),
+        })
+}
+
+fn bad_config(err: impl std::fmt::Display) -> RussulaError {
+    RussulaError::AuthFailed {
+        dbg: format!("invalid TLS certificates: {}", err),
+    }
+}
+
+/// A TLS connection over a [`TcpStream`].
+///
+/// Records are read and written with the non-blocking calls of the [`TcpStream`],
+/// so the connection keeps the readiness based API which [`Transport`] expects.
+#[derive(Debug)]
+pub struct TlsTransport {
+    stream: TcpStream,
+    conn: Mutex<Connection>,
+}
+
+// Reads and writes records without waiting on the socket
+struct NonBlocking<'a>(&'a TcpStream);
+
+impl Read for NonBlocking<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.try_read(buf)
+    }
+}
+
+impl Write for NonBlocking<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.try_write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl TlsTransport {
+    fn new(stream: TcpStream, conn: Connection) -> Self {
+        TlsTransport {
+            stream,
+            conn: Mutex::new(conn),
+        }
+    }
+
+    // Write the buffered records. Returns WouldBlock if the socket is full.
+    fn flush(&self, conn: &mut Connection) -> io::Result<()> {
+        while conn.wants_write() {
+            conn.write_tls(&mut NonBlocking(&self.stream))?;
+        }
+        Ok(())
+    }
+
+    // Read records until there is plaintext or the peer has closed the stream.
+    // Returns WouldBlock if neither is available yet.
+    fn fill(&self, conn: &mut Connection) -> io::Result<()> {
+        loop {
+            let state = conn
+                .process_new_packets()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            if state.plaintext_bytes_to_read() > 0 || state.peer_has_closed() {
+                return Ok(());
+            }
+            if conn.read_tls(&mut NonBlocking(&self.stream))? == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl Transport for TlsTransport {
+    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut conn = self.conn.lock().unwrap();
+        // records left over from a write, such as session tickets, are flushed
+        // while waiting to read
+        if let Err(err) = self.flush(&mut conn) {
+            if err.kind() == io::ErrorKind::WouldBlock {
+                let _ = self.stream.poll_write_ready(cx);
+            }
+        }
+        loop {
+            match self.fill(&mut conn) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    ready!(self.stream.poll_read_ready(cx))?
+                }
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    fn try_read_buf(&self, buf: &mut BytesMut) -> io::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        self.fill(&mut conn)?;
+        let mut data = [0; READ_LEN];
+        match conn.reader().read(&mut data) {
+            Ok(len) => {
+                buf.extend_from_slice(&data[..len]);
+                Ok(len)
+            }
+            // the peer closed the stream without closing the TLS session
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut conn = self.conn.lock().unwrap();
+        loop {
+            match self.flush(&mut conn) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    ready!(self.stream.poll_write_ready(cx))?
+                }
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        // the previous records must be written first
+        self.flush(&mut conn)?;
+        let written = conn.writer().write(buf)?;
+        match self.flush(&mut conn) {
+            // the rest is written by the next write or while waiting to read
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(written),
+            Err(err) => Err(err),
+            Ok(()) => Ok(written),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::russula::network_utils::{self, FramedStream, Msg};
+    use tokio::net::TcpListener;
+
+    // Connect a pair of peers configured with `coord` and `worker`
+    async fn connect(
+        coord: &TlsConfig,
+        worker: &TlsConfig,
+    ) -> (RussulaResult<TlsTransport>, RussulaResult<TlsTransport>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (accepted, _addr) = listener.accept().await.unwrap();
+        tokio::join!(coord.connect(stream), worker.accept(accepted))
+    }
+
+    #[tokio::test]
+    async fn mutual_auth() {
+        let run = TlsConfig::from_pem(&TlsConfig::generate_pem().unwrap()).unwrap();
+        let (coord, worker) = connect(&run, &run).await;
+        let mut coord = FramedStream::new(Box::new(coord.unwrap()));
+        let mut worker = FramedStream::new(Box::new(worker.unwrap()));
+
+        // a msg larger than a single read is reassembled
+        let data = "x".repeat(READ_LEN * 3);
+        network_utils::send_msg(&mut coord, Msg::new(data.clone().into()))
+            .await
+            .unwrap();
+        let msg = network_utils::recv_msg(&mut worker).await.unwrap();
+        assert_eq!(msg.as_bytes(), data.as_bytes());
+        network_utils::send_msg(&mut worker, Msg::new("ready".into()))
+            .await
+            .unwrap();
+        let msg = network_utils::recv_msg(&mut coord).await.unwrap();
+        assert_eq!(msg.as_bytes(), b"ready");
+
+        // closing the connection is seen by the peer
+        drop(worker);
+        assert!(matches!(
+            network_utils::recv_msg(&mut coord).await,
+            Err(RussulaError::NetworkFail { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn reject_other_run() {
+        let run = TlsConfig::from_pem(&TlsConfig::generate_pem().unwrap()).unwrap();
+        let other = TlsConfig::from_pem(&TlsConfig::generate_pem().unwrap()).unwrap();
+
+        // the Worker rejects a Coordinator of another run
+        let (_coord, worker) = connect(&other, &run).await;
+        assert!(matches!(worker, Err(RussulaError::AuthFailed { .. })));
+
+        // and a Coordinator rejects a Worker of another run
+        let (coord, _worker) = connect(&run, &other).await;
+        assert!(matches!(coord, Err(RussulaError::AuthFailed { .. })));
+
+        assert!(TlsConfig::from_pem("not a bundle").is_err());
+    }
+}
@@ -1,16 +1,25 @@
 use crate::russula::netbench;
 use core::time::Duration;
 use russula::{
-    netbench::{client, server},
-    RussulaBuilder,
+    netbench::{client, server, NetbenchRun, RunManifest, RunParams},
+    AuthToken, FailurePolicy, PeerEvents, Protocol, Russula, RussulaBuilder, RussulaResult,
+    TlsConfig,
+};
+use std::{
+    collections::BTreeSet,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::SystemTime,
 };
-use std::{collections::BTreeSet, net::SocketAddr};
 use structopt::StructOpt;
 use tracing::debug;
 use tracing_subscriber::EnvFilter;
 
 mod russula;
 
+// How long a simulated client runs when `local-run --simulate` has no duration
+const SIMULATED_CLIENT_DURATION: Duration = Duration::from_secs(1);
+
 /// This utility is a convenient CLI wrapper around Russula and can be used to launch
 /// different protocols.
 ///
@@ -18,13 +27,109 @@ mod russula;
 
 #[derive(StructOpt, Debug)]
 struct Opt {
+    /// Upper bound on how long to wait for a msg before polling the peers again.
     #[structopt(long, parse(try_from_str=parse_duration), default_value = "5s")]
     poll_delay: Duration,
 
+    /// Re-send the current state if the peer has been silent for this duration.
+    #[structopt(long, parse(try_from_str=parse_duration))]
+    heartbeat_interval: Option<Duration>,
+
+    /// Fail if the peer doesn't respond within this duration.
+    #[structopt(long, parse(try_from_str=parse_duration))]
+    liveness_timeout: Option<Duration>,
+
+    /// Once a peer has failed, fail the remaining peers which don't reach Done
+    /// within this duration.
+    #[structopt(long, parse(try_from_str=parse_duration), default_value = "60s")]
+    drive_to_done_timeout: Duration,
+
+    /// Delay from when the Coordinator schedules the Workers to start and when they
+    /// start their process. All Workers start at the same wall-clock time.
+    #[structopt(long, parse(try_from_str=parse_duration))]
+    start_delay: Option<Duration>,
+
+    /// Write the events recorded for each peer to this file as JSON.
+    #[structopt(long)]
+    events_path: Option<PathBuf>,
+
+    /// Coordinators collect the result files of the Workers into this dir, laid
+    /// out as <scenario>/<driver>/<result>.json.
+    #[structopt(long)]
+    results_dir: Option<PathBuf>,
+
+    /// Attempts made to re-establish a dropped connection to a peer.
+    #[structopt(long, default_value = "10")]
+    reconnect_attempts: usize,
+
+    /// Require peers to authenticate with the pre-shared token read from this file.
+    ///
+    /// The Coordinator and Workers must be configured with the same token.
+    #[structopt(long)]
+    auth_token_path: Option<PathBuf>,
+
+    /// Authenticate peers and encrypt their connections with mutual TLS, using the
+    /// certificates of the run read from this PEM bundle.
+    ///
+    /// The Coordinator and Workers must be configured with the same bundle.
+    #[structopt(long)]
+    tls_path: Option<PathBuf>,
+
     #[structopt(subcommand)]
     protocol: RussulaProtocol,
 }
 
+/// Parameters sent to the Workers with each run. Parameters which aren't set
+/// default to the values the Workers were launched with.
+#[derive(StructOpt, Debug, Clone)]
+struct RunOpt {
+    /// Drivers which the Workers run in sequence, each for `iterations` runs.
+    #[structopt(long)]
+    drivers: Vec<String>,
+
+    /// The name of the scenario file.
+    #[structopt(long)]
+    scenario: Option<String>,
+
+    /// The port which the Netbench Server process should accept connections.
+    #[structopt(long)]
+    netbench_port: Option<u16>,
+
+    /// Environment variables, as KEY=VALUE, set for the Netbench driver.
+    #[structopt(long = "env", parse(try_from_str = RunParams::parse_env))]
+    envs: Vec<(String, String)>,
+
+    /// Extra arguments passed to the Netbench driver.
+    #[structopt(long = "driver-arg", number_of_values = 1, allow_hyphen_values = true)]
+    driver_args: Vec<String>,
+}
+
+impl RunOpt {
+    // The runs of the session in order
+    fn runs(&self, iterations: u32) -> Vec<NetbenchRun> {
+        let drivers = match self.drivers.is_empty() {
+            true => vec![None],
+            false => self.drivers.iter().cloned().map(Some).collect(),
+        };
+        let params = RunParams {
+            envs: self.envs.iter().cloned().collect(),
+            args: self.driver_args.clone(),
+        };
+        drivers
+            .into_iter()
+            .flat_map(|driver| {
+                let run = NetbenchRun {
+                    driver,
+                    scenario: self.scenario.clone(),
+                    netbench_port: self.netbench_port,
+                    params: params.clone(),
+                };
+                std::iter::repeat(run).take(iterations as usize)
+            })
+            .collect()
+    }
+}
+
 #[allow(clippy::enum_variant_names)]
 #[derive(StructOpt, Debug)]
 enum RussulaProtocol {
@@ -49,15 +154,98 @@ enum RussulaProtocol {
         /// attempt to connect
         #[structopt(long, required = true)]
         russula_worker_addrs: Vec<SocketAddr>,
+
+        /// Number of times the Workers run Netbench in the same session.
+        #[structopt(long, default_value = "1")]
+        iterations: u32,
+
+        #[structopt(flatten)]
+        run: RunOpt,
     },
     NetbenchClientCoordinator {
         /// The list of worker addresses which the Coordinator should
         /// attempt to connect
         #[structopt(long)]
         russula_worker_addrs: Vec<SocketAddr>,
+
+        /// Number of times the Workers run Netbench in the same session.
+        #[structopt(long, default_value = "1")]
+        iterations: u32,
+
+        #[structopt(flatten)]
+        run: RunOpt,
+    },
+    /// Run a whole Netbench session on localhost: start the server and client
+    /// Workers and drive both Coordinators the way the orchestrator does.
+    LocalRun(LocalRunOpt),
+    /// Run the driver pairs listed in a manifest against server and client Workers
+    /// which are already running, such as on bare metal hosts. The session stops
+    /// at the first run which fails.
+    RemoteRun {
+        /// JSON file listing the Worker addresses, the scenario and the driver pairs.
+        #[structopt(long)]
+        manifest: PathBuf,
     },
 }
 
+#[derive(StructOpt, Debug)]
+struct LocalRunOpt {
+    /// The scenario file run by the drivers.
+    #[structopt(long)]
+    scenario: PathBuf,
+
+    /// The Netbench server driver, ex.: s2n-netbench-driver-server-s2n-quic
+    #[structopt(long)]
+    server_driver: String,
+
+    /// The Netbench client driver, ex.: s2n-netbench-driver-client-s2n-quic
+    #[structopt(long)]
+    client_driver: String,
+
+    /// The path to the netbench collector and drivers.
+    #[structopt(long)]
+    netbench_path: PathBuf,
+
+    /// Number of server Workers.
+    #[structopt(long, default_value = "1")]
+    servers: u16,
+
+    /// Number of client Workers.
+    #[structopt(long, default_value = "1")]
+    clients: u16,
+
+    /// Number of times the Workers run Netbench in the same session.
+    #[structopt(long, default_value = "1")]
+    iterations: u32,
+
+    /// The Workers 'listen' on consecutive ports starting at this port, servers first.
+    #[structopt(long, default_value = "7000")]
+    russula_port: u16,
+
+    /// The Netbench servers accept connections on consecutive ports starting at this port.
+    #[structopt(long, default_value = "4433")]
+    netbench_port: u16,
+
+    /// Directory which the Workers write the Netbench output and logs to. The
+    /// results are collected into --results-dir, or into this dir if not set.
+    #[structopt(long, default_value = "target/local-run")]
+    output_dir: PathBuf,
+
+    /// Print the Netbench commands which would be run instead of running them.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Simulate the Netbench processes instead of running them. The simulated
+    /// servers run until they're killed and the clients exit after the duration.
+    #[structopt(long)]
+    simulate: Option<Option<humantime::Duration>>,
+
+    /// Authenticate the peers and encrypt their connections with mutual TLS, using
+    /// certificates generated for the session.
+    #[structopt(long)]
+    tls: bool,
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> () {
     let opt = Opt::from_args();
@@ -84,84 +272,342 @@ async fn main() -> () {
         }
         RussulaProtocol::NetbenchServerCoordinator {
             russula_worker_addrs,
+            iterations,
+            run,
         } => {
             let w = russula_worker_addrs.clone();
-            run_local_server_coordinator(opt, w).await
+            let runs = run.runs(*iterations);
+            run_local_server_coordinator(opt, w, runs).await
         }
         RussulaProtocol::NetbenchClientCoordinator {
             russula_worker_addrs,
+            iterations,
+            run,
         } => {
             let w = russula_worker_addrs.clone();
-            run_local_client_coordinator(opt, w).await
+            let runs = run.runs(*iterations);
+            run_local_client_coordinator(opt, w, runs).await
         }
+        RussulaProtocol::LocalRun(local) => run_local(&opt, local).await,
+        RussulaProtocol::RemoteRun { manifest } => run_remote(&opt, manifest).await,
     };
 
     println!("cli done");
 }
 
-async fn run_server_worker(opt: Opt, netbench_ctx: netbench::ServerContext, russula_port: u16) {
-    let uuid = uuid::Uuid::new_v4().to_string();
-    let id = format!("{}-{}", uuid, netbench_ctx.trim_driver_name());
-    let protocol = server::WorkerProtocol::new(id, netbench_ctx);
-    let worker = RussulaBuilder::new(
-        BTreeSet::from_iter([local_listen_addr(russula_port)]),
+fn worker_builder<P: Protocol + Send>(
+    opt: &Opt,
+    addr: SocketAddr,
+    protocol: P,
+) -> RussulaBuilder<P> {
+    RussulaBuilder::new(BTreeSet::from_iter([addr]), protocol, opt.poll_delay)
+        .with_heartbeat_interval(opt.heartbeat_interval)
+        .with_liveness_timeout(opt.liveness_timeout)
+        .with_reconnect_attempts(opt.reconnect_attempts)
+        .with_auth_token(auth_token(opt))
+        .with_tls(tls(opt))
+}
+
+fn coord_builder<P: Protocol + Send>(
+    opt: &Opt,
+    russula_worker_addrs: Vec<SocketAddr>,
+    protocol: P,
+) -> RussulaBuilder<P> {
+    RussulaBuilder::new(
+        BTreeSet::from_iter(russula_worker_addrs),
         protocol,
         opt.poll_delay,
-    );
+    )
+    .with_heartbeat_interval(opt.heartbeat_interval)
+    .with_liveness_timeout(opt.liveness_timeout)
+    .with_reconnect_attempts(opt.reconnect_attempts)
+    .with_auth_token(auth_token(opt))
+    .with_tls(tls(opt))
+    .with_failure_policy(FailurePolicy::DriveToDone)
+    .with_drive_to_done_timeout(opt.drive_to_done_timeout)
+}
+
+async fn run_server_worker(opt: Opt, netbench_ctx: netbench::ServerContext, russula_port: u16) {
+    let id = uuid::Uuid::new_v4().to_string();
+    let protocol = server::WorkerProtocol::new(id, netbench_ctx);
+    let worker = worker_builder(&opt, local_listen_addr(russula_port), protocol);
     let mut worker = worker.build().await.unwrap();
     worker.run_till_ready().await.unwrap();
 
     worker.run_till_done().await.unwrap();
+    write_events(&opt, worker.peer_events());
 }
 
 async fn run_client_worker(opt: Opt, netbench_ctx: netbench::ClientContext, russula_port: u16) {
-    let uuid = uuid::Uuid::new_v4().to_string();
-    let id = format!("{}-{}", uuid, netbench_ctx.trim_driver_name());
+    let id = uuid::Uuid::new_v4().to_string();
     let protocol = client::WorkerProtocol::new(id, netbench_ctx);
-    let worker = RussulaBuilder::new(
-        BTreeSet::from_iter([local_listen_addr(russula_port)]),
-        protocol,
-        opt.poll_delay,
-    );
+    let worker = worker_builder(&opt, local_listen_addr(russula_port), protocol);
     let mut worker = worker.build().await.unwrap();
     worker.run_till_ready().await.unwrap();
 
     worker.run_till_done().await.unwrap();
+    write_events(&opt, worker.peer_events());
 }
 
-async fn run_local_server_coordinator(opt: Opt, russula_worker_addrs: Vec<SocketAddr>) {
-    let protocol = server::CoordProtocol::new();
-    let coord = RussulaBuilder::new(
-        // TODO for local testing.. we only connect to 1 local worker
-        BTreeSet::from_iter(russula_worker_addrs),
-        protocol,
-        opt.poll_delay,
-    );
+async fn run_local_server_coordinator(
+    opt: Opt,
+    russula_worker_addrs: Vec<SocketAddr>,
+    runs: Vec<NetbenchRun>,
+) {
+    let coord = coord_builder(&opt, russula_worker_addrs, server::CoordProtocol::new());
     let mut coord = coord.build().await.unwrap();
+    if let Some(results_dir) = &opt.results_dir {
+        coord.collect_results(results_dir);
+    }
 
-    coord.run_till_worker_running().await.unwrap();
+    for (iteration, run) in runs.iter().enumerate() {
+        if iteration > 0 {
+            coord.next_iteration();
+        }
+        coord.set_run(run);
+        if let Some(start_delay) = opt.start_delay {
+            coord.start_at(SystemTime::now() + start_delay);
+        }
+        coord.run_till_worker_running().await.unwrap();
 
-    println!("Waiting for user input to continue ... WorkersRunning");
-    let mut s = String::new();
-    let _ = std::io::stdin().read_line(&mut s);
-    println!("Stopping workers ...");
+        let status = coord.status();
+        println!("{}", serde_json::to_string_pretty(&status).unwrap());
+        println!("Waiting for user input to continue ... WorkersRunning");
+        let mut s = String::new();
+        let _ = std::io::stdin().read_line(&mut s);
+        println!("Stopping workers ...");
+
+        coord.run_till_worker_stopped().await.unwrap();
+    }
 
     coord.run_till_done().await.unwrap();
+    write_events(&opt, coord.peer_events());
 }
 
-async fn run_local_client_coordinator(opt: Opt, russula_worker_addrs: Vec<SocketAddr>) {
-    let protocol = client::CoordProtocol::new();
-    let coord = RussulaBuilder::new(
-        // TODO for local testing.. we only connect to 1 local worker
-        BTreeSet::from_iter(russula_worker_addrs),
-        protocol,
-        opt.poll_delay,
-    );
+async fn run_local_client_coordinator(
+    opt: Opt,
+    russula_worker_addrs: Vec<SocketAddr>,
+    runs: Vec<NetbenchRun>,
+) {
+    let coord = coord_builder(&opt, russula_worker_addrs, client::CoordProtocol::new());
     let mut coord = coord.build().await.unwrap();
+    if let Some(results_dir) = &opt.results_dir {
+        coord.collect_results(results_dir);
+    }
 
-    coord.run_till_worker_running().await.unwrap();
+    for (iteration, run) in runs.iter().enumerate() {
+        if iteration > 0 {
+            coord.next_iteration();
+        }
+        coord.set_run(run);
+        if let Some(start_delay) = opt.start_delay {
+            coord.start_at(SystemTime::now() + start_delay);
+        }
+        coord.run_till_worker_running().await.unwrap();
+        coord.run_till_worker_stopped().await.unwrap();
+    }
 
     coord.run_till_done().await.unwrap();
+    write_events(&opt, coord.peer_events());
+}
+
+// Drive the server and client Coordinators through each pair of runs the same way
+// as the orchestrator: the servers are started, the clients run to completion and
+// then the servers are stopped.
+async fn run_session(
+    opt: &Opt,
+    server_coord: &mut Russula<server::CoordProtocol>,
+    client_coord: &mut Russula<client::CoordProtocol>,
+    runs: &[(NetbenchRun, NetbenchRun)],
+) -> RussulaResult<()> {
+    let mut run = Ok(());
+    for (iteration, (server_run, client_run)) in runs.iter().enumerate() {
+        if iteration > 0 {
+            server_coord.next_iteration();
+            client_coord.next_iteration();
+        }
+        if let (Some(server), Some(client)) = (&server_run.driver, &client_run.driver) {
+            println!(
+                "Running Netbench with server: {} and client: {}",
+                server, client
+            );
+        }
+        server_coord.set_run(server_run);
+        client_coord.set_run(client_run);
+        if let Some(start_delay) = opt.start_delay {
+            server_coord.start_at(SystemTime::now() + start_delay);
+        }
+        run = async {
+            server_coord.run_till_worker_running().await?;
+            let client_stopped = async {
+                client_coord.run_till_worker_running().await?;
+                client_coord.run_till_worker_stopped().await
+            }
+            .await;
+            // stop the servers even if the clients failed
+            let server_stopped = server_coord.run_till_worker_stopped().await;
+            client_stopped.and(server_stopped)
+        }
+        .await;
+        if run.is_err() {
+            break;
+        }
+    }
+    let client_done = client_coord.run_till_done().await;
+    let server_done = server_coord.run_till_done().await;
+    run.and(client_done).and(server_done)
+}
+
+// Start the Workers on loopback ports and run a session against them
+async fn run_local(opt: &Opt, local: &LocalRunOpt) {
+    let scenario = std::fs::canonicalize(&local.scenario)
+        .unwrap_or_else(|err| panic!("scenario {}: {}", local.scenario.display(), err));
+    let scenario = scenario.display().to_string();
+    let results_dir = opt.results_dir.as_ref().unwrap_or(&local.output_dir);
+    let workers_dir = local.output_dir.join("workers");
+    let tls = local.tls.then(|| {
+        let pem = TlsConfig::generate_pem().expect("failed to generate TLS certificates");
+        TlsConfig::from_pem(&pem).unwrap()
+    });
+    std::fs::create_dir_all(&workers_dir).expect("failed to create the output dir");
+
+    let loopback = |port: u16| SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let server_addrs: Vec<SocketAddr> = (0..local.servers)
+        .map(|i| loopback(local.russula_port + i))
+        .collect();
+    let client_addrs: Vec<SocketAddr> = (0..local.clients)
+        .map(|i| loopback(local.russula_port + local.servers + i))
+        .collect();
+    let netbench_servers: Vec<SocketAddr> = (0..local.servers)
+        .map(|i| loopback(local.netbench_port + i))
+        .collect();
+
+    let mut workers = Vec::new();
+    for (i, (addr, netbench_server)) in server_addrs.iter().zip(&netbench_servers).enumerate() {
+        let ctx = netbench::ServerContext::new(
+            local.netbench_path.clone(),
+            local.server_driver.clone(),
+            scenario.clone(),
+            netbench_server.port(),
+        )
+        .with_output_dir(&workers_dir)
+        .with_dry_run(local.dry_run);
+        let ctx = match local.simulate {
+            Some(_) => ctx.with_simulate(None),
+            None => ctx,
+        };
+        let protocol = server::WorkerProtocol::new(i.to_string(), ctx);
+        let worker = worker_builder(opt, *addr, protocol).with_tls(tls.clone());
+        workers.push(tokio::spawn(async move {
+            worker.build().await?.run_till_done().await
+        }));
+    }
+    for (i, addr) in client_addrs.iter().enumerate() {
+        let ctx = netbench::ClientContext::new(
+            local.netbench_path.clone(),
+            local.client_driver.clone(),
+            scenario.clone(),
+            netbench_servers.clone(),
+        )
+        .with_output_dir(&workers_dir)
+        .with_dry_run(local.dry_run);
+        let ctx = match local.simulate {
+            Some(duration) => {
+                ctx.with_simulate(Some(duration.map_or(SIMULATED_CLIENT_DURATION, Into::into)))
+            }
+            None => ctx,
+        };
+        let protocol = client::WorkerProtocol::new(i.to_string(), ctx);
+        let worker = worker_builder(opt, *addr, protocol).with_tls(tls.clone());
+        workers.push(tokio::spawn(async move {
+            worker.build().await?.run_till_done().await
+        }));
+    }
+
+    let server_coord =
+        coord_builder(opt, server_addrs, server::CoordProtocol::new()).with_tls(tls.clone());
+    let mut server_coord = server_coord.build().await.unwrap();
+    server_coord.collect_results(results_dir);
+    let client_coord = coord_builder(opt, client_addrs, client::CoordProtocol::new()).with_tls(tls);
+    let mut client_coord = client_coord.build().await.unwrap();
+    client_coord.collect_results(results_dir);
+
+    // the Workers run the drivers and scenario they were launched with
+    let runs = vec![Default::default(); local.iterations as usize];
+    let run = run_session(opt, &mut server_coord, &mut client_coord, &runs).await;
+
+    let mut events = server_coord.peer_events();
+    events.extend(client_coord.peer_events());
+    write_events(opt, events);
+    for worker in workers {
+        if let Err(err) = worker.await.unwrap() {
+            eprintln!("worker failed: {}", err);
+        }
+    }
+    run.unwrap();
+    let collected = server_coord.results_collected() + client_coord.results_collected();
+    report_results(collected, results_dir);
+}
+
+// Run the session described by the manifest against Workers which are already running
+async fn run_remote(opt: &Opt, manifest_path: &Path) {
+    let manifest = RunManifest::from_file(manifest_path)
+        .unwrap_or_else(|err| panic!("manifest {}: {}", manifest_path.display(), err));
+
+    let server_coord = coord_builder(opt, manifest.servers.clone(), server::CoordProtocol::new());
+    let mut server_coord = server_coord.build().await.unwrap();
+    let client_coord = coord_builder(opt, manifest.clients.clone(), client::CoordProtocol::new());
+    let mut client_coord = client_coord.build().await.unwrap();
+    if let Some(results_dir) = &opt.results_dir {
+        server_coord.collect_results(results_dir);
+        client_coord.collect_results(results_dir);
+    }
+
+    let run = run_session(opt, &mut server_coord, &mut client_coord, &manifest.runs()).await;
+
+    // write the events even if the run failed
+    let mut events = server_coord.peer_events();
+    events.extend(client_coord.peer_events());
+    write_events(opt, events);
+    run.unwrap();
+    if let Some(results_dir) = &opt.results_dir {
+        let collected = server_coord.results_collected() + client_coord.results_collected();
+        report_results(collected, results_dir);
+    }
+}
+
+fn report_results(collected: usize, results_dir: &Path) {
+    if collected > 0 {
+        println!(
+            "{} result files collected in {}",
+            collected,
+            results_dir.display()
+        );
+    } else {
+        println!("no result files were collected");
+    }
+}
+
+fn write_events(opt: &Opt, events: Vec<PeerEvents>) {
+    if let Some(events_path) = &opt.events_path {
+        let file = std::fs::File::create(events_path).expect("failed to create events file");
+        serde_json::to_writer_pretty(file, &events).expect("failed to write events");
+    }
+}
+
+fn auth_token(opt: &Opt) -> Option<AuthToken> {
+    opt.auth_token_path.as_ref().map(|auth_token_path| {
+        let secret =
+            std::fs::read_to_string(auth_token_path).expect("failed to read auth token file");
+        AuthToken::new(secret.trim().to_string())
+    })
+}
+
+fn tls(opt: &Opt) -> Option<TlsConfig> {
+    opt.tls_path.as_ref().map(|tls_path| {
+        let pem = std::fs::read_to_string(tls_path).expect("failed to read TLS bundle");
+        TlsConfig::from_pem(&pem).expect("invalid TLS bundle")
+    })
 }
 
 fn local_listen_addr(russula_port: u16) -> SocketAddr {
@@ -54,8 +54,8 @@ pub async fn run_russula_worker(
         .unwrap();
 
     let netbench_cmd =
-        format!("env RUST_LOG=debug ./target/debug/russula_cli netbench-client-worker --russula-port {} --driver {} --scenario {} --netbench-servers {netbench_server_addr}",
-            STATE.russula_port, driver.driver_name(), config.netbench_scenario_filename);
+        format!("env RUST_LOG=debug ./target/debug/russula_cli --auth-token-path {} --tls-path {} --heartbeat-interval {} netbench-client-worker --russula-port {} --driver {} --scenario {} --netbench-servers {netbench_server_addr}{}",
+            STATE.host_auth_token_path(), STATE.host_tls_path(), humantime::format_duration(STATE.heartbeat_interval_russula), STATE.russula_port, driver.driver_name(), config.netbench_scenario_filename, config.max_run_duration_arg());
     debug!("{}", netbench_cmd);
 
     send_command(
@@ -208,6 +208,20 @@ async fn upload_netbench_scenario_file(
                 STATE.host_bin_path(),
                 scenario.netbench_scenario_filename
             ),
+            // copy the Russula auth token to host
+            format!(
+                "aws s3 cp {}/{} {}",
+                STATE.s3_private_path(unique_id, config),
+                STATE.russula_auth_token_file,
+                STATE.host_auth_token_path()
+            ),
+            // copy the Russula TLS certificates to host
+            format!(
+                "aws s3 cp {}/{} {}",
+                STATE.s3_private_path(unique_id, config),
+                STATE.russula_tls_file,
+                STATE.host_tls_path()
+            ),
         ]
         .into_iter()
         .map(String::from)
@@ -3,20 +3,25 @@
 
 use crate::{
     ec2_utils::InfraDetail,
-    orchestrator::OrchestratorConfig,
+    orchestrator::{OrchResult, OrchestratorConfig, StatusBoard},
     poll_ssm_results,
     russula::{
         self,
-        netbench::{client, server},
-        RussulaBuilder,
+        netbench::{client, server, NetbenchRun},
+        AuthToken, FailurePolicy, PeerEvents, RussulaBuilder, TlsConfig,
     },
-    ssm_utils, NetbenchDriverType, PubIp, STATE,
+    ssm_utils, upload_object, NetbenchDriverType, PubIp, STATE,
 };
+use aws_sdk_s3::primitives::ByteStream;
 use aws_sdk_ssm::operation::send_command::SendCommandOutput;
 use core::time::Duration;
 use indicatif::{ProgressBar, ProgressStyle};
-use std::{collections::BTreeSet, net::SocketAddr};
-use tracing::{debug, info};
+use std::{
+    collections::BTreeSet,
+    net::SocketAddr,
+    time::{Instant, SystemTime},
+};
+use tracing::{debug, error, info, warn};
 
 fn get_progress_bar(msg: String) -> ProgressBar {
     // TODO use multi-progress bar https://github.com/console-rs/indicatif/blob/main/examples/multi.rs
@@ -30,10 +35,29 @@ fn get_progress_bar(msg: String) -> ProgressBar {
     bar
 }
 
+/// Per-run credentials which the Coordinators use to authenticate to the Workers
+#[derive(Debug, Clone)]
+pub struct RussulaAuth {
+    pub token: AuthToken,
+    pub tls: TlsConfig,
+}
+
+// Sent to the Workers with each run so that they don't depend on the driver
+// they were launched with
+fn netbench_run(config: &OrchestratorConfig, driver: &NetbenchDriverType) -> NetbenchRun {
+    NetbenchRun {
+        driver: Some(driver.driver_name().clone()),
+        scenario: Some(config.netbench_scenario_filename.clone()),
+        netbench_port: Some(STATE.netbench_port),
+        params: config.run_params.clone(),
+    }
+}
+
 pub struct ServerNetbenchRussula {
     worker: SendCommandOutput,
     coord: russula::Russula<server::CoordProtocol>,
     driver_name: String,
+    status_board: StatusBoard,
 }
 
 impl ServerNetbenchRussula {
@@ -43,7 +67,9 @@ impl ServerNetbenchRussula {
         instance_ids: Vec<String>,
         scenario: &OrchestratorConfig,
         driver: &NetbenchDriverType,
-    ) -> Self {
+        auth: &RussulaAuth,
+        status_board: &StatusBoard,
+    ) -> OrchResult<Self> {
         // server run commands
         debug!("starting server worker");
 
@@ -55,17 +81,32 @@ impl ServerNetbenchRussula {
 
         // server coord
         debug!("starting server coordinator");
-        let coord = server_coord(infra.public_server_ips()).await;
-        ServerNetbenchRussula {
+        let mut coord = server_coord(infra.public_server_ips(), auth).await?;
+        coord.set_run(&netbench_run(scenario, driver));
+        Ok(ServerNetbenchRussula {
             worker,
             coord,
             driver_name: driver.trim_driver_name(),
-        }
+            status_board: status_board.clone(),
+        })
     }
 
-    pub async fn wait_workers_running(&mut self, ssm_client: &aws_sdk_ssm::Client) {
+    // Publish the status of the server coordination to the status endpoint. Called
+    // after each poll, before returning any error, so that failures are visible.
+    fn publish_status(&mut self) {
+        let status = self.coord.status();
+        self.status_board
+            .update("server", &self.driver_name, status);
+    }
+
+    pub async fn wait_workers_running(
+        &mut self,
+        ssm_client: &aws_sdk_ssm::Client,
+    ) -> OrchResult<()> {
         let msg = format!("{}: Waiting for server state Running.", self.driver_name);
         let bar = get_progress_bar(msg);
+        self.coord
+            .start_at(SystemTime::now() + STATE.start_delay_russula);
         loop {
             let poll_worker = poll_ssm_results(
                 "server",
@@ -75,7 +116,9 @@ impl ServerNetbenchRussula {
             .await
             .unwrap();
 
-            let poll_coord_worker_running = self.coord.poll_worker_running().await.unwrap();
+            let poll_coord_worker_running = self.coord.poll_worker_running().await;
+            self.publish_status();
+            let poll_coord_worker_running = poll_coord_worker_running?;
 
             debug!(
                 "Server Russula!: poll worker_running. Coordinator: {:?} Worker {:?}",
@@ -88,11 +131,54 @@ impl ServerNetbenchRussula {
             tokio::time::sleep(Duration::from_secs(5)).await;
         }
         bar.finish();
+
+        Ok(())
+    }
+
+    /// Run the servers again once they stop, rather than moving to Done
+    pub fn next_iteration(&mut self) {
+        self.coord.next_iteration();
+    }
+
+    pub async fn wait_workers_stopped(
+        &mut self,
+        ssm_client: &aws_sdk_ssm::Client,
+    ) -> OrchResult<()> {
+        let msg = format!("{}: Waiting for server state Stopped.", self.driver_name);
+        let bar = get_progress_bar(msg);
+        loop {
+            let poll_worker = poll_ssm_results(
+                "server",
+                ssm_client,
+                self.worker.command().unwrap().command_id().unwrap(),
+            )
+            .await
+            .unwrap();
+
+            let poll_coord_worker_stopped = self.coord.poll_worker_stopped().await;
+            self.publish_status();
+            let poll_coord_worker_stopped = poll_coord_worker_stopped?;
+
+            debug!(
+                "Server Russula!: poll worker_stopped. Coordinator: {:?} Worker {:?}",
+                poll_coord_worker_stopped, poll_worker
+            );
+
+            if poll_coord_worker_stopped.is_ready() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+        bar.finish();
+
+        Ok(())
     }
 
-    pub async fn wait_done(&mut self, ssm_client: &aws_sdk_ssm::Client) {
+    pub async fn wait_done(&mut self, ssm_client: &aws_sdk_ssm::Client) -> OrchResult<()> {
         let msg = format!("{}: Waiting for server state Done.", self.driver_name);
         let bar = get_progress_bar(msg);
+        // Set once the Coordinator is Done, bounding the wait for the worker command
+        let mut worker_deadline = None;
         // poll server russula workers/coord
         loop {
             let poll_worker = poll_ssm_results(
@@ -103,33 +189,57 @@ impl ServerNetbenchRussula {
             .await
             .unwrap();
 
-            let poll_coord_done = self.coord.poll_done().await.unwrap();
+            let poll_coord_done = self.coord.poll_done().await;
+            self.publish_status();
+            let poll_coord_done = poll_coord_done?;
 
             debug!(
                 "Server Russula!: Coordinator: {:?} Worker {:?}",
                 poll_coord_done, poll_worker
             );
 
-            // FIXME the worker doesnt complete but its not necessary to wait so continue.
-            //
-            // maybe try sudo
-            //
-            // The collector launches the driver process, which doesnt get killed when the
-            // collector is killed. However its not necessary to wait for its completing
-            // for the purpose of a single run.
-            // ```
-            //  55320  ./target/debug/russula_cli
-            //  55646  /home/ec2-user/bin/netbench-collector
-            //  55647  /home/ec2-user/bin/netbench-driver-s2n-quic-server
-            // ```
+            // The worker stops the collector's whole process group, including the
+            // driver, so the worker command completes once the results are uploaded.
             if poll_coord_done.is_ready() {
-                break;
+                if poll_worker.is_ready() {
+                    break;
+                }
+                let deadline = *worker_deadline
+                    .get_or_insert_with(|| Instant::now() + STATE.liveness_timeout_russula);
+                if Instant::now() >= deadline {
+                    warn!(
+                        "Server Russula!: worker command didn't complete within {:?} of Done. \
+                        Results may be missing.",
+                        STATE.liveness_timeout_russula
+                    );
+                    break;
+                }
             }
             tokio::time::sleep(Duration::from_secs(5)).await;
         }
         bar.finish();
 
         info!("Server Russula!: Successful");
+        Ok(())
+    }
+
+    /// Upload the coordination events recorded for each server
+    pub async fn upload_events(
+        &mut self,
+        s3_client: &aws_sdk_s3::Client,
+        unique_id: &str,
+        config: &OrchestratorConfig,
+    ) {
+        let events = self.coord.peer_events();
+        upload_events(
+            s3_client,
+            unique_id,
+            config,
+            "server",
+            &self.driver_name,
+            events,
+        )
+        .await
     }
 }
 
@@ -137,6 +247,7 @@ pub struct ClientNetbenchRussula {
     worker: SendCommandOutput,
     coord: russula::Russula<client::CoordProtocol>,
     driver_name: String,
+    status_board: StatusBoard,
 }
 
 impl ClientNetbenchRussula {
@@ -146,7 +257,9 @@ impl ClientNetbenchRussula {
         instance_ids: Vec<String>,
         scenario: &OrchestratorConfig,
         driver: &NetbenchDriverType,
-    ) -> Self {
+        auth: &RussulaAuth,
+        status_board: &StatusBoard,
+    ) -> OrchResult<Self> {
         // client run commands
         debug!("starting client worker");
         let worker = ssm_utils::client::run_russula_worker(
@@ -163,15 +276,66 @@ impl ClientNetbenchRussula {
 
         // client coord
         debug!("starting client coordinator");
-        let coord = client_coord(infra.public_client_ips()).await;
-        ClientNetbenchRussula {
+        let mut coord = client_coord(infra.public_client_ips(), auth).await?;
+        coord.set_run(&netbench_run(scenario, driver));
+        Ok(ClientNetbenchRussula {
             worker,
             coord,
             driver_name: driver.trim_driver_name(),
+            status_board: status_board.clone(),
+        })
+    }
+
+    // Publish the status of the client coordination to the status endpoint. Called
+    // after each poll, before returning any error, so that failures are visible.
+    fn publish_status(&mut self) {
+        let status = self.coord.status();
+        self.status_board
+            .update("client", &self.driver_name, status);
+    }
+
+    /// Run the clients again once they stop, rather than moving to Done
+    pub fn next_iteration(&mut self) {
+        self.coord.next_iteration();
+    }
+
+    pub async fn wait_workers_stopped(
+        &mut self,
+        ssm_client: &aws_sdk_ssm::Client,
+    ) -> OrchResult<()> {
+        let msg = format!("{}: Waiting for client state Stopped.", self.driver_name);
+        let bar = get_progress_bar(msg);
+        self.coord
+            .start_at(SystemTime::now() + STATE.start_delay_russula);
+        loop {
+            let poll_worker = poll_ssm_results(
+                "client",
+                ssm_client,
+                self.worker.command().unwrap().command_id().unwrap(),
+            )
+            .await
+            .unwrap();
+
+            let poll_coord_worker_stopped = self.coord.poll_worker_stopped().await;
+            self.publish_status();
+            let poll_coord_worker_stopped = poll_coord_worker_stopped?;
+
+            debug!(
+                "Client Russula!: poll worker_stopped. Coordinator: {:?} Worker {:?}",
+                poll_coord_worker_stopped, poll_worker
+            );
+
+            if poll_coord_worker_stopped.is_ready() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_secs(5)).await;
         }
+        bar.finish();
+
+        Ok(())
     }
 
-    pub async fn wait_done(&mut self, ssm_client: &aws_sdk_ssm::Client) {
+    pub async fn wait_done(&mut self, ssm_client: &aws_sdk_ssm::Client) -> OrchResult<()> {
         let msg = format!("{}: Waiting for client state Done.", self.driver_name);
         let bar = get_progress_bar(msg);
         // poll client russula workers/coord
@@ -184,7 +348,9 @@ impl ClientNetbenchRussula {
             .await
             .unwrap();
 
-            let poll_coord_done = self.coord.poll_done().await.unwrap();
+            let poll_coord_done = self.coord.poll_done().await;
+            self.publish_status();
+            let poll_coord_done = poll_coord_done?;
 
             debug!(
                 "Client Russula!: Coordinator: {:?} Worker {:?}",
@@ -200,10 +366,61 @@ impl ClientNetbenchRussula {
         bar.finish();
 
         info!("Client Russula!: Successful");
+        Ok(())
+    }
+
+    /// Upload the coordination events recorded for each client
+    pub async fn upload_events(
+        &mut self,
+        s3_client: &aws_sdk_s3::Client,
+        unique_id: &str,
+        config: &OrchestratorConfig,
+    ) {
+        let events = self.coord.peer_events();
+        upload_events(
+            s3_client,
+            unique_id,
+            config,
+            "client",
+            &self.driver_name,
+            events,
+        )
+        .await
     }
 }
 
-async fn server_coord(server_ips: Vec<&PubIp>) -> russula::Russula<server::CoordProtocol> {
+// Upload the events next to the netbench results so that the coordination overhead
+// can be compared with the duration of the run
+async fn upload_events(
+    s3_client: &aws_sdk_s3::Client,
+    unique_id: &str,
+    config: &OrchestratorConfig,
+    host: &str,
+    driver_name: &str,
+    events: Vec<PeerEvents>,
+) {
+    let key = format!(
+        "{unique_id}/russula/{}/{driver_name}/{host}_events.json",
+        config.netbench_scenario_file_stem()
+    );
+    let body = ByteStream::from(serde_json::to_vec_pretty(&events).unwrap());
+    match upload_object(
+        s3_client,
+        config.cdk_config.netbench_runner_public_s3_bucket(),
+        body,
+        &key,
+    )
+    .await
+    {
+        Ok(_) => info!("Uploaded Russula events: {}", key),
+        Err(err) => error!("Failed to upload Russula events: {} {}", key, err),
+    }
+}
+
+async fn server_coord(
+    server_ips: Vec<&PubIp>,
+    auth: &RussulaAuth,
+) -> OrchResult<russula::Russula<server::CoordProtocol>> {
     let protocol = server::CoordProtocol::new();
     let server_addr: Vec<SocketAddr> = server_ips
         .iter()
@@ -213,14 +430,22 @@ async fn server_coord(server_ips: Vec<&PubIp>) -> russula::Russula<server::Coord
         BTreeSet::from_iter(server_addr),
         protocol,
         STATE.poll_delay_russula,
-    );
-    let mut server_coord = server_coord.build().await.unwrap();
-    server_coord.run_till_ready().await.unwrap();
+    )
+    .with_heartbeat_interval(Some(STATE.heartbeat_interval_russula))
+    .with_liveness_timeout(Some(STATE.liveness_timeout_russula))
+    .with_auth_token(Some(auth.token.clone()))
+    .with_tls(Some(auth.tls.clone()))
+    .with_failure_policy(FailurePolicy::DriveToDone);
+    let mut server_coord = server_coord.build().await?;
+    server_coord.run_till_ready().await?;
     info!("server coord Ready");
-    server_coord
+    Ok(server_coord)
 }
 
-async fn client_coord(client_ips: Vec<&PubIp>) -> russula::Russula<client::CoordProtocol> {
+async fn client_coord(
+    client_ips: Vec<&PubIp>,
+    auth: &RussulaAuth,
+) -> OrchResult<russula::Russula<client::CoordProtocol>> {
     let protocol = client::CoordProtocol::new();
     let client_addr: Vec<SocketAddr> = client_ips
         .iter()
@@ -230,9 +455,14 @@ async fn client_coord(client_ips: Vec<&PubIp>) -> russula::Russula<client::Coord
         BTreeSet::from_iter(client_addr),
         protocol,
         STATE.poll_delay_russula,
-    );
-    let mut client_coord = client_coord.build().await.unwrap();
-    client_coord.run_till_ready().await.unwrap();
+    )
+    .with_heartbeat_interval(Some(STATE.heartbeat_interval_russula))
+    .with_liveness_timeout(Some(STATE.liveness_timeout_russula))
+    .with_auth_token(Some(auth.token.clone()))
+    .with_tls(Some(auth.tls.clone()))
+    .with_failure_policy(FailurePolicy::DriveToDone);
+    let mut client_coord = client_coord.build().await?;
+    client_coord.run_till_ready().await?;
     info!("client coord Ready");
-    client_coord
+    Ok(client_coord)
 }
@@ -45,8 +45,8 @@ pub async fn run_russula_worker(
     config: &OrchestratorConfig,
 ) -> SendCommandOutput {
     let netbench_cmd =
-        format!("env RUST_LOG=debug ./target/debug/russula_cli netbench-server-worker --russula-port {} --driver {} --scenario {} --netbench-port {}",
-            STATE.russula_port, driver.driver_name(), config.netbench_scenario_filename, STATE.netbench_port);
+        format!("env RUST_LOG=debug ./target/debug/russula_cli --auth-token-path {} --tls-path {} --heartbeat-interval {} netbench-server-worker --russula-port {} --driver {} --scenario {} --netbench-port {}{}",
+            STATE.host_auth_token_path(), STATE.host_tls_path(), humantime::format_duration(STATE.heartbeat_interval_russula), STATE.russula_port, driver.driver_name(), config.netbench_scenario_filename, STATE.netbench_port, config.max_run_duration_arg());
     debug!("{}", netbench_cmd);
 
     send_command(